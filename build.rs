@@ -4,6 +4,53 @@ use std::path::PathBuf;
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
 
+    // apex
+    //
+    // tree-sitter-sfapex has grammars for SOQL, SOSL, and Salesforce logs
+    // too, but we only vendor Apex. Like pascal below, it's patched from
+    // ABI 14 to 13.
+    let apex_dir: PathBuf = ["vendor", "tree-sitter-sfapex", "apex", "src"]
+        .iter()
+        .collect();
+
+    println!("cargo:rerun-if-changed=vendor/tree-sitter-sfapex/apex/src/parser.c");
+    cc::Build::new()
+        .include(&apex_dir)
+        .warnings(false)
+        .file(apex_dir.join("parser.c"))
+        .compile("tree-sitter-apex");
+
+    // bicep
+    //
+    // like pascal below, this is patched from ABI 14 to 13.
+    let bicep_dir: PathBuf = ["vendor", "tree-sitter-bicep", "src"].iter().collect();
+
+    println!("cargo:rerun-if-changed=vendor/tree-sitter-bicep/src/parser.c");
+    cc::Build::new()
+        .include(&bicep_dir)
+        .warnings(false)
+        .file(bicep_dir.join("parser.c"))
+        .compile("tree-sitter-bicep");
+
+    println!("cargo:rerun-if-changed=vendor/tree-sitter-bicep/src/scanner.c");
+    cc::Build::new()
+        .include(&bicep_dir)
+        .warnings(false)
+        .file(bicep_dir.join("scanner.c"))
+        .compile("tree_sitter_bicep_scanner");
+
+    // capnp
+    //
+    // like pascal below, this is patched from ABI 14 to 13.
+    let capnp_dir: PathBuf = ["vendor", "tree-sitter-capnp", "src"].iter().collect();
+
+    println!("cargo:rerun-if-changed=vendor/tree-sitter-capnp/src/parser.c");
+    cc::Build::new()
+        .include(&capnp_dir)
+        .warnings(false)
+        .file(capnp_dir.join("parser.c"))
+        .compile("tree-sitter-capnp");
+
     // cpp
     let cpp_dir: PathBuf = ["vendor", "tree-sitter-cpp", "src"].iter().collect();
 
@@ -58,6 +105,18 @@ fn main() {
         .file(elm_dir.join("scanner.cc"))
         .compile("tree_sitter_elm_scanner");
 
+    // go
+    //
+    // like pascal below, this is patched from ABI 14 to 13.
+    let go_dir: PathBuf = ["vendor", "tree-sitter-go", "src"].iter().collect();
+
+    println!("cargo:rerun-if-changed=vendor/tree-sitter-go/src/parser.c");
+    cc::Build::new()
+        .include(&go_dir)
+        .warnings(false)
+        .file(go_dir.join("parser.c"))
+        .compile("tree-sitter-go");
+
     // haskell
     let haskell_dir: PathBuf = ["vendor", "tree-sitter-haskell", "src"].iter().collect();
 
@@ -93,6 +152,44 @@ fn main() {
         .file(javascript_dir.join("scanner.c"))
         .compile("tree_sitter_javascript_scanner");
 
+    // mermaid
+    //
+    // the crates.io release is generated for ABI 15, which changes more
+    // than we can patch around, so `parser.c` (and `node-types.json` and
+    // `tree_sitter/parser.h` with it) is regenerated from the release's
+    // `grammar.json` with `tree-sitter generate --abi 13` from tree-sitter
+    // CLI 0.20.7. `scanner.c` is as released.
+    let mermaid_dir: PathBuf = ["vendor", "tree-sitter-mermaid", "src"].iter().collect();
+
+    println!("cargo:rerun-if-changed=vendor/tree-sitter-mermaid/src/parser.c");
+    cc::Build::new()
+        .include(&mermaid_dir)
+        .warnings(false)
+        .file(mermaid_dir.join("parser.c"))
+        .compile("tree-sitter-mermaid");
+
+    println!("cargo:rerun-if-changed=vendor/tree-sitter-mermaid/src/scanner.c");
+    cc::Build::new()
+        .include(&mermaid_dir)
+        .warnings(false)
+        .file(mermaid_dir.join("scanner.c"))
+        .compile("tree_sitter_mermaid_scanner");
+
+    // pascal
+    //
+    // this comes from the grammar's crates.io release, which is generated
+    // for ABI 14. The only thing ABI 14 adds over 13 is a field at the end of
+    // `TSLanguage` that our tree-sitter doesn't read, so `parser.c` is
+    // patched to say it's ABI 13. Re-apply that after updating the grammar!
+    let pascal_dir: PathBuf = ["vendor", "tree-sitter-pascal", "src"].iter().collect();
+
+    println!("cargo:rerun-if-changed=vendor/tree-sitter-pascal/src/parser.c");
+    cc::Build::new()
+        .include(&pascal_dir)
+        .warnings(false)
+        .file(pascal_dir.join("parser.c"))
+        .compile("tree-sitter-pascal");
+
     // php
     let php_dir: PathBuf = ["vendor", "tree-sitter-php", "src"].iter().collect();
 
@@ -111,6 +208,25 @@ fn main() {
         .file(php_dir.join("scanner.cc"))
         .compile("tree_sitter_php_scanner");
 
+    // python
+    //
+    // like pascal above, this is patched from ABI 14 to 13.
+    let python_dir: PathBuf = ["vendor", "tree-sitter-python", "src"].iter().collect();
+
+    println!("cargo:rerun-if-changed=vendor/tree-sitter-python/src/parser.c");
+    cc::Build::new()
+        .include(&python_dir)
+        .warnings(false)
+        .file(python_dir.join("parser.c"))
+        .compile("tree-sitter-python");
+
+    println!("cargo:rerun-if-changed=vendor/tree-sitter-python/src/scanner.c");
+    cc::Build::new()
+        .include(&python_dir)
+        .warnings(false)
+        .file(python_dir.join("scanner.c"))
+        .compile("tree_sitter_python_scanner");
+
     // ruby
     let ruby_dir: PathBuf = ["vendor", "tree-sitter-ruby", "src"].iter().collect();
 
@@ -164,4 +280,16 @@ fn main() {
         .warnings(false)
         .file(typescript_dir.join("scanner.c"))
         .compile("tree_sitter_typescript_scanner");
+
+    // zig
+    //
+    // like pascal above, this is patched from ABI 14 to 13.
+    let zig_dir: PathBuf = ["vendor", "tree-sitter-zig", "src"].iter().collect();
+
+    println!("cargo:rerun-if-changed=vendor/tree-sitter-zig/src/parser.c");
+    cc::Build::new()
+        .include(&zig_dir)
+        .warnings(false)
+        .file(zig_dir.join("parser.c"))
+        .compile("tree-sitter-zig");
 }
@@ -0,0 +1,112 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Remembers which files had no matches last time, so that on the next run
+/// with the same arguments we can skip them without parsing if they haven't
+/// changed. Most files in a big repo don't match most queries, so this skips
+/// most of the work.
+///
+/// We can't save parse trees, and our JSON output leaves out things some
+/// formats need, so files that did match are searched again every time.
+pub struct Cache {
+    path: PathBuf,
+    unmatched: BTreeMap<PathBuf, u64>,
+    /// What we found this run: the file's hash if it had no matches, or
+    /// `None` if it did.
+    updates: Mutex<BTreeMap<PathBuf, Option<u64>>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Saved {
+    unmatched: BTreeMap<PathBuf, u64>,
+}
+
+impl Cache {
+    /// Load the cache for this set of arguments from `dir`. Different
+    /// arguments (a different query, say) get different caches, since they
+    /// could match different files.
+    pub fn load(dir: &Path, args: &[String]) -> Result<Cache> {
+        let mut hasher = DefaultHasher::new();
+        // grammars can change between versions, and with them what matches.
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        args.hash(&mut hasher);
+        let path = dir.join(format!("{:016x}.json", hasher.finish()));
+
+        let unmatched = match fs::read_to_string(&path) {
+            Ok(contents) => match serde_json::from_str::<Saved>(&contents) {
+                Ok(saved) => saved.unmatched,
+                // a cache we can't read is the same as no cache
+                Err(_) => BTreeMap::new(),
+            },
+            Err(_) => BTreeMap::new(),
+        };
+
+        Ok(Cache {
+            path,
+            unmatched,
+            updates: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// Did this file have no matches last time, with exactly this source?
+    pub fn is_unmatched(&self, path: &Path, source: &[u8]) -> bool {
+        self.unmatched.get(path) == Some(&hash(source))
+    }
+
+    /// Remember whether the file with this source had any matches.
+    pub fn record(&self, path: &Path, source: &[u8], matched: bool) {
+        let entry = if matched { None } else { Some(hash(source)) };
+
+        if let Ok(mut updates) = self.updates.lock() {
+            updates.insert(path.to_path_buf(), entry);
+        }
+    }
+
+    /// Write what we found this run (along with anything from earlier runs
+    /// that we didn't look at) back to disk.
+    pub fn save(self) -> Result<()> {
+        let Cache {
+            path,
+            mut unmatched,
+            updates,
+        } = self;
+        let updates = updates
+            .into_inner()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        for (path, entry) in updates {
+            match entry {
+                Some(hash) => unmatched.insert(path, hash),
+                None => unmatched.remove(&path),
+            };
+        }
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("could not create {}", dir.display()))?;
+        }
+
+        // write to a temporary file and move it into place, so a run that
+        // gets interrupted never leaves half a cache behind.
+        let temp = path.with_extension(format!("tmp-{}", std::process::id()));
+        fs::write(
+            &temp,
+            serde_json::to_vec(&Saved { unmatched }).context("could not serialize the cache")?,
+        )
+        .with_context(|| format!("could not write {}", temp.display()))?;
+        fs::rename(&temp, &path)
+            .with_context(|| format!("could not move the cache into {}", path.display()))
+    }
+}
+
+fn hash(source: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    hasher.finish()
+}
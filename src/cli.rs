@@ -1,16 +1,25 @@
+use crate::dynamic_grammar;
 use crate::extractor::Extractor;
 use crate::extractor_chooser::ExtractorChooser;
 use crate::language::Language;
+use crate::replace::Replacement;
 use anyhow::{bail, Context, Error, Result};
 use clap::{crate_authors, crate_version, App, Arg, ArgMatches};
 use itertools::Itertools;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::env;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
+use tree_sitter::Point;
 
 pub enum Invocation {
-    DoQuery(QueryOpts),
+    DoQuery(Box<QueryOpts>),
     ShowLanguages,
+    ShowGrammarVersions,
+    ShowFields(Language, String),
 }
 
 #[derive(Debug)]
@@ -18,18 +27,66 @@ pub struct QueryOpts {
     pub extractors: Vec<Extractor>,
     pub paths: Vec<PathBuf>,
     pub git_ignore: bool,
+    pub follow_links: bool,
+    pub hidden: bool,
+    pub max_depth: Option<usize>,
+    pub threads: usize,
     pub format: QueryFormat,
     pub sort: bool,
+    pub jsonl_with_summary: bool,
+    pub line_number: bool,
+    pub replacement: Option<Replacement>,
+    pub project_root: bool,
+    pub modified_since: Option<Duration>,
+    pub modified_until: Option<Duration>,
+    pub sample: Option<usize>,
+    pub seed: Option<u64>,
+    pub unique_capture: Option<String>,
+    pub forced_languages: Vec<(Language, PathBuf)>,
+    pub output_per_file: Option<PathBuf>,
+    pub deadline: Option<Duration>,
+    pub max_allowed: Option<usize>,
+    pub reverse: bool,
+    pub key_value: Option<(String, String)>,
+    pub collapse_lines: bool,
+    pub search_compressed: bool,
+    pub atomic: bool,
+    pub staged: bool,
+    pub merge: Vec<PathBuf>,
+    pub inventory: bool,
+    pub query_test: Option<PathBuf>,
+    pub check: bool,
+    pub quiet: bool,
+    pub dry_run: bool,
+    pub color: bool,
+    pub time_files: bool,
+    pub cache: Option<(PathBuf, Vec<String>)>,
+    pub stdin: Option<Language>,
+    pub language_marker: Option<String>,
+    pub language_overrides: Vec<(String, Language)>,
+    pub socket: Option<PathBuf>,
+    pub count: bool,
+    pub group_by_dir: Option<usize>,
+    pub coverage: bool,
+    pub files_with_matches: bool,
+    pub file_kinds: bool,
+    pub context_lines: bool,
 }
 
 impl QueryOpts {
     pub fn extractor_chooser(&self) -> Result<ExtractorChooser> {
-        ExtractorChooser::from_extractors(&self.extractors)
+        let mut chooser = ExtractorChooser::from_extractors(&self.extractors)?;
+        chooser.set_language_marker(self.language_marker.clone());
+        chooser.set_overrides(self.language_overrides.clone());
+
+        Ok(chooser)
     }
 }
 
 impl Invocation {
     pub fn from_args(args: Vec<String>) -> Result<Self> {
+        let all_args = args.clone();
+
         // I'm not super happy with this! I would love for LANGUAGE and QUERY to
         // be taken positionally when there is just one so we don't always have
         // to specify `-q`. However, I also want to get working on the rest of
@@ -50,31 +107,215 @@ impl Invocation {
                     .long("query")
                     .help("a language and query to perform")
                     .long_help(
-                        "a language and query to perform (at least one is required.) See https://tree-sitter.github.io for information on writing queries. Run tree-grepper --languages for a list of languages.",
+                        "a language and query to perform (at least one is required.) See https://tree-sitter.github.io for information on writing queries. Run tree-grepper --languages for a list of languages. Use `auto` as the language to run the query in every language it compiles in, skipping files in the rest. Since node types differ between grammars, this is best for quick exploration.",
                     )
                     .number_of_values(2)
                     .value_names(&["LANGUAGE", "QUERY"])
-                    .required_unless_present("languages")
+                    .required_unless_present_any(["languages", "grammar-versions", "fields", "list-errors", "comments-only", "query-file", "no-query", "merge"])
                     .multiple_values(true)
+                    .multiple_occurrences(true)
+            )
+            .arg(
+                Arg::new("query-file")
+                    .long("query-file")
+                    .help("a language and a file containing a query to perform")
+                    .long_help("a language and a file containing a query to perform, for queries that are too long to comfortably write on the command line. This works the same as giving the file's contents with -q, and can be given multiple times. Use either -q or --query-file, not both.")
+                    .number_of_values(2)
+                    .value_names(&["LANGUAGE", "PATH"])
+                    .multiple_occurrences(true)
+                    .conflicts_with("additional-query")
+            )
+            .arg(
+                Arg::new("namespace-captures")
+                    .long("namespace-captures")
+                    .visible_alias("merge-queries")
+                    .help("prefix capture names with the name of the query file they came from")
+                    .long_help("prefix capture names in each --query-file with the file's name (without extension), so that `@name` in `functions.scm` becomes `@functions.name`. All the queries for a language always run together in one pass, so without this, captures with the same name in different files are indistinguishable in the output.")
+                    .requires("query-file")
             )
             .arg(
                 Arg::new("no-gitignore")
                     .long("no-gitignore")
                     .help("don't use git's ignore and exclude files to filter files")
             )
+            .arg(
+                Arg::new("hidden")
+                    .long("hidden")
+                    .help("search hidden files and directories (ones starting with `.`) too")
+                    .long_help("search hidden files and directories (ones starting with `.`, like `.github`) too. These are skipped by default. Hidden files that git ignores are still skipped unless you also pass --no-gitignore.")
+            )
+            .arg(
+                Arg::new("threads")
+                    .long("threads")
+                    .short('j')
+                    .help("use at most N threads for walking and searching files (0 means one per core)")
+                    .long_help("use at most N threads for walking and searching files. 0 (the default) means we pick for ourselves, which is usually one per core. Lower this to leave some CPU for other things, like on shared CI runners.")
+                    .value_name("N")
+            )
+            .arg(
+                Arg::new("max-depth")
+                    .long("max-depth")
+                    .help("don't descend more than N directories below the paths we're given")
+                    .long_help("don't descend more than N directories below the paths we're given. 0 means only the paths themselves (so a directory isn't searched at all), and 1 means the files directly inside directories we're given, but not their subdirectories.")
+                    .value_name("N")
+            )
+            .arg(
+                Arg::new("follow")
+                    .long("follow")
+                    .short('L')
+                    .help("follow symbolic links while walking directories")
+                    .long_help("follow symbolic links while walking directories. Links that loop back to a directory we're already in are skipped, and files we can reach by more than one path are only searched once.")
+            )
             .arg(
                 Arg::new("PATHS")
                     .default_value(".")
                     .help("places to search for matches")
                     .multiple_values(true)
             )
+            .arg(
+                Arg::new("as")
+                .long("as")
+                .help("search PATH as LANGUAGE, whatever its extension")
+                .long_help("search PATH as LANGUAGE, whatever its extension. This is useful for files with missing or misleading extensions. You still need to give a query for LANGUAGE with -q. If you use this without giving any other paths, we only search the files given with --as. Can be given multiple times.")
+                .number_of_values(2)
+                .value_names(&["LANGUAGE", "PATH"])
+                .multiple_occurrences(true)
+            )
+            .arg(
+                Arg::new("language-override")
+                .long("language-override")
+                .help("search files with extension EXT (or named exactly EXT) as LANGUAGE")
+                .long_help("search files with extension EXT (or named exactly EXT, like `Jenkinsfile`) as LANGUAGE, instead of the language we'd usually pick for them. For example, `--language-override ts:javascript` searches `.ts` files as JavaScript. Unlike --as, this applies to every matching file we find while walking. Can be given multiple times.")
+                .value_name("EXT:LANGUAGE")
+                .multiple_occurrences(true)
+            )
+            .arg(
+                Arg::new("language-marker")
+                .long("language-marker")
+                .help("let files name their language on their first line after MARKER")
+                .long_help("let files name their language on their first line after MARKER, overriding their extension. For example, with `--language-marker '#lang '`, a file starting with `#lang ruby` is searched as Ruby whatever it's called. The language has to be one you gave a query for. Files without the marker are chosen by extension as usual.")
+                .value_name("MARKER")
+            )
+            .arg(
+                Arg::new("stdin")
+                .long("stdin")
+                .help("search what's piped to stdin as LANGUAGE instead of walking paths")
+                .long_help("search what's piped to stdin as LANGUAGE instead of walking paths. You still need to give a query for LANGUAGE with -q. Matches are reported as coming from `<stdin>`. For example: `cat foo.rs | tree-grepper -q rust '(function_item)' --stdin rust`")
+                .value_name("LANGUAGE")
+                .conflicts_with_all(&["as", "replace"])
+            )
+            .arg(
+                Arg::new("staged")
+                .long("staged")
+                .help("search the versions of files staged in git instead of the ones on disk")
+                .long_help("search the versions of files staged in git (what would be committed right now) instead of the ones on disk, for checking things in pre-commit hooks. We ask `git` for the staged files under each path, and report paths relative to the root of the repository. Files with unresolved merge conflicts are skipped.")
+                .conflicts_with_all(&["stdin", "as", "replace"])
+            )
+            .arg(
+                Arg::new("search-compressed")
+                .long("search-compressed")
+                .help("also search gzipped files, like `foo.rs.gz`")
+                .long_help("also search gzipped files, choosing their language by the name they have without `.gz` (so `foo.rs.gz` is searched as Rust.) Match positions are in the decompressed source. We can't write compressed files, so this doesn't work with --replace.")
+                .conflicts_with("replace")
+            )
+            .arg(
+                Arg::new("project-root")
+                .long("project-root")
+                .help("if no paths are given, search from the project root instead of the current directory")
+                .long_help("if no paths are given, search from the project root instead of the current directory. The project root is the closest directory at or above the current one containing a marker like `.git`, `Cargo.toml`, or `package.json`. If we can't find one, we search the current directory.")
+            )
+            .arg(
+                Arg::new("modified-since")
+                .long("modified-since")
+                .help("only search files modified within this long ago (like 30m, 12h, or 7d)")
+                .long_help("only search files modified within this long ago. Durations are a whole number followed by a unit: `s` for seconds, `m` for minutes, `h` for hours, `d` for days, or `w` for weeks. For example, `--modified-since 7d` searches files changed in the last week.")
+                .value_name("DURATION")
+            )
+            .arg(
+                Arg::new("modified-until")
+                .long("modified-until")
+                .alias("until")
+                .help("only search files last modified at least this long ago (like 30m, 12h, or 7d)")
+                .long_help("only search files last modified at least this long ago, using the same durations as --modified-since. Together they make a window: `--modified-since 14d --modified-until 7d` searches files changed in the week before last.")
+                .value_name("DURATION")
+            )
             .arg(
                 Arg::new("FORMAT")
                 .long("format")
                 .short('f')
-                .possible_values(&["lines", "json", "json-lines", "pretty-json"])
+                .possible_values(["lines", "json", "json-lines", "pretty-json", "gnu", "folded", "annotated-tree", "spans", "ctags", "csv", "sarif", "null-file-list", "html", "patch", "msgpack"])
                 .default_value("lines")
                 .help("what format should we output lines in?")
+                .long_help("what format should we output lines in? `folded` counts matches by the kinds of their ancestors as `kind;kind;kind count` lines, which you can feed to flamegraph tools. `annotated-tree` prints the whole tree of each file with matches, marking matched nodes with a `*`. `spans` prints `path start_byte length` for each match, for extracting matches with tools like `dd`. `ctags` prints a sorted tags file with an entry for everything captured as `@name`. `csv` prints a header and then `path,row,column,end_row,end_column,match_text` rows, with positions as in JSON output. `sarif` prints a single SARIF 2.1.0 document (for tools like GitHub code scanning) with a result for every match, using capture names as rule IDs. `null-file-list` prints just the paths of files with matches, each followed by a NUL byte, for `xargs -0`. `html` prints a self-contained page with syntax-highlighted matches grouped by file, for sharing. `patch` only works with --replace: instead of changing files, it prints a git-style patch (with `diff --git` and `index` lines) that you can check and apply later with `git apply`. `msgpack` prints the same data as `json` as a single MessagePack array, which is smaller and quicker to read in other programs.")
+            )
+            .arg(
+                Arg::new("color")
+                .long("color")
+                .possible_values(["auto", "always", "never"])
+                .default_value("auto")
+                .help("when should we color lines and pretty JSON output?")
+                .long_help("when should we color lines and pretty JSON output? `auto` colors output when printing to a terminal, unless the `NO_COLOR` environment variable is set. `always` colors even when printing to a file or pipe. In lines output, we color paths, positions, and matched text like `grep --color`. In pretty JSON, we color keys, strings, and numbers.")
+                .value_name("WHEN")
+            )
+            .arg(
+                Arg::new("socket")
+                .long("socket")
+                .help("send matches to the Unix domain socket at PATH as we find them, instead of to stdout")
+                .long_help("send matches to the Unix domain socket at PATH as we find them, instead of to stdout. Something needs to be listening there already. Each file with matches is sent as one line of JSON, just like `--format json-lines`, as soon as we're done searching it, so the order is unpredictable.")
+                .value_name("PATH")
+                .conflicts_with_all(&["replace", "output-per-file", "unique-capture", "kv", "sample", "sort", "reverse"])
+            )
+            .arg(
+                Arg::new("output-per-file")
+                .long("output-per-file")
+                .help("write each file's matches to its own file under DIR instead of stdout")
+                .long_help("write each file's matches to its own file under DIR instead of stdout, mirroring the layout of the files we searched. For example, matches in `src/main.rs` go in `DIR/src/main.rs.json` for JSON formats or `DIR/src/main.rs.txt` otherwise. Each file contains exactly what we would print if we'd only searched that one file. Directories are created as needed.")
+                .value_name("DIR")
+            )
+            .arg(
+                Arg::new("collapse-lines")
+                .long("collapse-lines")
+                .help("in lines output, put all the matches starting on the same line together")
+                .long_help("in lines output, put all the matches starting on the same line of a file on one line of output, separated by ` | `, like `file:line:col:name:text | col:name:text`.")
+            )
+            .arg(
+                Arg::new("after-context")
+                .long("after-context")
+                .short('A')
+                .help("in lines output, show N lines of source after each match")
+                .value_name("N")
+                .conflicts_with("collapse-lines")
+            )
+            .arg(
+                Arg::new("before-context")
+                .long("before-context")
+                .short('B')
+                .help("in lines output, show N lines of source before each match")
+                .value_name("N")
+                .conflicts_with("collapse-lines")
+            )
+            .arg(
+                Arg::new("context")
+                .long("context")
+                .short('C')
+                .help("in lines output, show N lines of source before and after each match")
+                .long_help("in lines output, show N lines of source before and after each match, like grep. Context lines look like `file-line-text` to tell them apart from matches, and when the context around nearby matches overlaps, we only show it once. -A and -B override this for their side.")
+                .value_name("N")
+                .conflicts_with("collapse-lines")
+            )
+            .arg(
+                Arg::new("jsonl-with-summary")
+                .long("jsonl-with-summary")
+                .help("end json-lines output with a summary record")
+                .long_help("end json-lines output with a summary record containing the number of distinct files with matches (`files`) and the total number of matches (`matches`), so consumers know the stream is complete. When this is set, every record gets a `type` field (`file` or `summary`) to tell them apart.")
+            )
+            .arg(
+                Arg::new("line-number")
+                .long("line-number")
+                .short('n')
+                .help("start each line of lines output with the line number of the match")
+                .long_help("start each line of lines output with the 1-based line number the match starts on, like `grep -n`. The usual `path:line:column:` prefix comes after it, so this is mostly for tools that look for a line number at the start of a line.")
+                .conflicts_with_all(&["collapse-lines", "after-context", "before-context", "context"])
             )
             .arg(
                 Arg::new("sort")
@@ -82,36 +323,733 @@ impl Invocation {
                 .help("sort matches stably")
                 .long_help("sort matches stably. If this is not specified, output ordering will vary because due to parallelism. Caution: this adds a worst-case `O(n * log(n))` overhead, where `n` is the number of files matched. Avoid it if possible if you care about performance.")
             )
+            .arg(
+                Arg::new("count")
+                .long("count")
+                .short('c')
+                .help("print how many matches each file has instead of the matches themselves")
+                .long_help("print how many matches each file has instead of the matches themselves, as `path:count` lines, followed by a `total:count` line for all the files together. Files without matches aren't listed.")
+                .conflicts_with_all(&["unique-capture", "kv"])
+            )
+            .arg(
+                Arg::new("group-by-dir")
+                .long("group-by-dir")
+                .help("print how many matches each directory has instead of the matches themselves")
+                .long_help("print how many matches each directory has instead of the matches themselves, as `directory:count` lines, followed by a `total:count` line. DEPTH says how many levels of directories under each path we searched to keep (1 if you don't say), so `--group-by-dir=2` over `src` rolls up `src/a/b/c.rs` into `src/a/b`. Files closer to the top than that count towards the directory they're in. Directories without matches aren't listed.")
+                .value_name("DEPTH")
+                .min_values(0)
+                .require_equals(true)
+                .conflicts_with_all(&["count", "unique-capture", "kv", "replace"])
+            )
+            .arg(
+                Arg::new("coverage")
+                .long("coverage")
+                .help("print how much of each file the matches cover instead of the matches themselves")
+                .long_help("print how much of each file the matches cover instead of the matches themselves, as `path:percent%` lines, followed by a `total:percent%` line for all the files together. We count each byte once, even if it's inside several matches, so this works well with broad queries to see how much of the code a pattern touches. Files without matches aren't listed, or counted in the total.")
+                .conflicts_with_all(&["count", "unique-capture", "kv"])
+            )
+            .arg(
+                Arg::new("files-with-matches")
+                .long("files-with-matches")
+                .short('l')
+                .help("only print the paths of files with matches")
+                .long_help("only print the paths of files with matches, once each. With JSON formats, this prints an array of paths instead (or a JSON string per line for json-lines.)")
+                .conflicts_with_all(&["count", "coverage", "unique-capture", "kv"])
+            )
+            .arg(
+                Arg::new("file-kinds")
+                .long("file-kinds")
+                .help("print the distinct kinds of nodes matched in each file instead of the matches")
+                .long_help("print the distinct kinds of nodes matched in each file instead of the matches, sorted, as `path:kind kind kind` lines. With JSON formats, this prints an object mapping each path to a sorted array of kinds instead (or one `{\"file\": ..., \"kinds\": [...]}` object per line for json-lines.) Try it with `(_)` for a quick fingerprint of each file's structure.")
+                .conflicts_with_all(&["count", "coverage", "files-with-matches", "unique-capture", "kv"])
+            )
+            .arg(
+                Arg::new("unique-capture")
+                .long("unique-capture")
+                .help("print each distinct text captured by NAME once, with a count")
+                .long_help("instead of printing every match, print each distinct text captured by NAME (across every file we search) once, preceded by how many times we saw it. For example, `-q elm '(import_clause (upper_case_qid)@module)' --unique-capture module` lists every module imported in a project.")
+                .value_name("NAME")
+            )
+            .arg(
+                Arg::new("kv")
+                .long("kv")
+                .help("print a JSON object mapping the text of one capture to another")
+                .long_help("instead of printing matches, print a JSON object mapping the text of KEY to the text of VALUE, wherever they're captured in the same match. For example, `-q rust '(function_item name: (identifier)@name return_type: (_)@type)' --kv name:type` maps function names to their return types. Keys with different values in different matches get an array of every value we saw.")
+                .value_name("KEY:VALUE")
+                .conflicts_with("unique-capture")
+            )
+            .arg(
+                Arg::new("sample")
+                .long("sample")
+                .help("only show N randomly-chosen matches")
+                .long_help("only show N matches, chosen at random from everything we found. This is handy for eyeballing a representative slice of a query with lots of results. Sampled output is always sorted, so that the same --seed gives the same matches.")
+                .value_name("N")
+            )
+            .arg(
+                Arg::new("seed")
+                .long("seed")
+                .help("seed the random choice for --sample, to get the same matches every time")
+                .value_name("SEED")
+                .requires("sample")
+            )
+            .arg(
+                Arg::new("reverse")
+                .long("reverse")
+                .help("show matches from the bottom of each file up")
+                .long_help("show matches from the bottom of each file up, and files in reverse order too. Combine with --sort to get files in reverse path order.")
+            )
+            .arg(
+                Arg::new("cache")
+                .long("cache")
+                .help("remember which files didn't match in DIR, and skip them next time if they haven't changed")
+                .long_help("remember which files didn't match in DIR, and skip them next time if they haven't changed. This speeds up running the same search over a big repo again and again, since most files usually don't match. The cache only applies to runs with exactly the same arguments (including paths), and we compare file contents rather than modification times, so changed files are always searched again. Files that matched are searched every time.")
+                .value_name("DIR")
+                .conflicts_with_all(&["replace", "stdin", "staged", "merge", "no-query", "query-test"])
+            )
+            .arg(
+                Arg::new("time-files")
+                .long("time-files")
+                .help("print how long each file took to parse and search to stderr, slowest first")
+                .long_help("print how long each file took to parse and search to stderr, slowest first, as `milliseconds path` lines. This is handy for finding files (or grammars) that slow searches down. It doesn't change what we print to stdout.")
+            )
+            .arg(
+                Arg::new("embedded")
+                .long("embedded")
+                .help("also query inside nodes of another language (experimental)")
+                .long_help("re-parse the contents of every NODE_TYPE node captured by a file's own query as LANGUAGE and run LANGUAGE's query (from -q) on it, reporting matches at their position in the original file. For example, `-q python '(string)@_js' -q javascript '(identifier)@id' --embedded javascript:string` finds JavaScript identifiers inside Python strings. Captures starting with `_` are re-parsed but not reported, so they're handy for picking out the nodes to look inside. Quotes around string literals are stripped before parsing. Embedded matches go through the same filters as any other (like --capture, --byte-range, --max-node-depth, and --ignore-parse-errors), using their position in the original file. Note that files are only searched if you have given a query for their language. Can be given multiple times.")
+                .value_name("LANGUAGE:NODE_TYPE")
+                .multiple_occurrences(true)
+            )
+            .arg(
+                Arg::new("comments-only")
+                .long("comments-only")
+                .help("search comments in every language instead of running a query")
+                .long_help("search comments in every language instead of running a query. We know what each grammar calls its comments, so you don't have to! Combine with --comment-regex to find things like TODO or FIXME.")
+                .conflicts_with_all(&["additional-query", "query-file"])
+            )
+            .arg(
+                Arg::new("comment-regex")
+                .long("comment-regex")
+                .help("only match comments matching this regex (with --comments-only)")
+                .value_name("REGEX")
+                .requires("comments-only")
+            )
+            .arg(
+                Arg::new("list-errors")
+                .long("list-errors")
+                .help("list syntax errors instead of query matches")
+                .long_help("list every ERROR and MISSING node in the files we search instead of query matches. This is handy for checking that files parse (or that a grammar handles them.) If you don't give any queries, we'll look for errors in files of every language we know about.")
+            )
+            .arg(
+                Arg::new("quiet")
+                .long("quiet")
+                .help("don't print anything, just exit successfully if anything matched")
+                .long_help("don't print anything, just exit successfully if anything matched. We stop searching as soon as we find a match, so this is faster than throwing the output away. Whether or not you use this, we exit with 0 if anything matched, 1 if nothing did, and 2 if something went wrong, like grep. (There's no -q for this like in grep, since that's how you give queries.)")
+                .conflicts_with_all(&["replace", "no-query", "query-test", "max-allowed"])
+            )
+            .arg(
+                Arg::new("check")
+                .long("check")
+                .help("check that the queries compile, without searching anything")
+                .long_help("check that the queries compile, without searching anything. We exit successfully (printing nothing) if they do, or print tree-sitter's error (including the row and column where it went wrong) and exit with an error if they don't. This is handy for linting a library of queries in CI.")
+            )
+            .arg(
+                Arg::new("query-test")
+                .long("query-test")
+                .help("check that the queries match fixtures as many times as SPEC says, instead of searching")
+                .long_help("check that the queries match fixtures as many times as SPEC says, instead of searching. SPEC is a JSON file mapping fixture paths (relative to SPEC) to the number of matches we expect for each capture, like `{\"fixtures/basic.rs\": {\"name\": 2}}`. Captures that aren't mentioned aren't checked. We print `ok` or `FAILED` for each fixture and exit with an error if any failed, so you can use this to keep queries from regressing.")
+                .value_name("SPEC")
+                .conflicts_with_all(&["no-query", "replace", "stdin", "staged"])
+            )
+            .arg(
+                Arg::new("merge")
+                .long("merge")
+                .help("combine results saved with a JSON format instead of searching")
+                .long_help("combine results saved with `--format json`, `pretty-json`, or `json-lines` instead of searching, for putting together scans of a project that were split into shards. Each file shows up once in the output (in the format you choose), with matches that appear in several inputs only listed once. Can be given multiple times.")
+                .value_name("FILE")
+                .multiple_occurrences(true)
+                .conflicts_with_all(&["additional-query", "query-file", "comments-only", "list-errors", "no-query", "query-test", "replace", "stdin", "staged"])
+            )
+            .arg(
+                Arg::new("no-query")
+                .long("no-query")
+                .help("list the files we can parse in each language instead of searching them")
+                .long_help("list the files we can parse in each language instead of searching them, for taking inventory of a project before writing queries. For each language, we print a `language count` line followed by the paths of the files that parsed without errors, indented. With JSON formats, this prints an object mapping each language to a sorted array of paths instead.")
+                .conflicts_with_all(&["additional-query", "query-file", "comments-only", "list-errors", "replace"])
+            )
+            .arg(
+                Arg::new("ignore-parse-errors")
+                .long("ignore-parse-errors")
+                .help("drop matches inside parts of files that failed to parse")
+                .long_help("drop any match inside an ERROR node. When part of a file fails to parse, the tree there is the parser's best guess at what's going on, so matches inside it are less reliable. Use --list-errors to see where these regions are.")
+            )
+            .arg(
+                Arg::new("explain")
+                .long("explain")
+                .help("say which pattern and capture produced each match")
+                .long_help("say which pattern and capture produced each match. In JSON output, this adds `pattern_index` and `capture_index` fields; in lines output, it adds a `(pattern N, capture N)` suffix. Indexes are in the order patterns and captures appear in the query (after combining queries for the same language.)")
+            )
+            .arg(
+                Arg::new("relative-to")
+                .long("relative-to")
+                .help("also report match positions relative to their nearest ancestor of this node type")
+                .long_help("also report match positions relative to their nearest ancestor of this node type (for example `function_item` in Rust.) These show up in JSON output in a `relative` field, as zero-based row and column offsets from the start of the ancestor. Matches without an ancestor of this type don't get a `relative` field.")
+                .value_name("TYPE")
+            )
+            .arg(
+                Arg::new("deadline")
+                .long("deadline")
+                .help("stop searching after this many seconds, printing what we found so far")
+                .long_help("stop searching after this many seconds (fractions like 0.5 are fine), printing whatever we found so far. If we hit the deadline, we say so on stderr and exit with code 124 (like `timeout`) so you can tell the output is incomplete.")
+                .value_name("SECONDS")
+            )
+            .arg(
+                Arg::new("max-allowed")
+                .long("max-allowed")
+                .help("exit with an error if there are more than this many matches")
+                .long_help("exit with an error if there are more than this many matches in total, after printing them as usual. We exit with 3 when that happens, so it can be told apart from finding nothing (1) or any other error (2). This is handy for keeping a budget for some pattern in CI, like only letting a few `unsafe` blocks in.")
+                .value_name("N")
+                .conflicts_with("replace")
+            )
+            .arg(
+                Arg::new("qualified-name")
+                .long("qualified-name")
+                .help("include the qualified name of the items each match is in (like `module::Type::method`) in JSON output")
+                .long_help("include the qualified name of the items each match is in (like `module::Type::method` in Rust or `Class.method` in JavaScript) in JSON output, as a `qualified_name` field. We build these from the names of things like modules, classes, and functions around the match (including the match itself.) Matches outside any of those don't get the field.")
+            )
+            .arg(
+                Arg::new("with-indent")
+                .long("with-indent")
+                .help("include the whitespace at the start of each match's line in JSON output")
+                .long_help("include the whitespace at the start of the line each match starts on in JSON output, as an `indent` field. This is the actual whitespace, so you can tell tabs from spaces.")
+            )
+            .arg(
+                Arg::new("max-node-depth")
+                .long("max-node-depth")
+                .help("drop matches nested more than N levels deep in the tree")
+                .long_help("drop matches nested more than N levels deep in the tree, counting the root node as level 0. This cuts down the noise from broad queries like `(_)` when you only care about the overall structure of files.")
+                .value_name("N")
+            )
+            .arg(
+                Arg::new("byte-range")
+                .long("byte-range")
+                .help("only report matches starting between these byte offsets in each file")
+                .long_help("only report matches starting between these byte offsets in each file, given as START:END (START is included, END is not.) We still parse the whole file, so the tree is the same as usual. Since each match is in exactly one range, you can search a huge file in chunks by running with adjacent ranges like 0:1000000 and 1000000:2000000.")
+                .value_name("START:END")
+            )
+            .arg(
+                Arg::new("at")
+                .long("at")
+                .help("only report the smallest match containing this position in each file")
+                .long_help("only report the smallest match containing this position in each file, given as LINE:COL (counting from 1, like our output.) This is meant for editor integrations that want to know what's under the cursor: use a query like `(_)@node` to get the innermost node at the position, whatever it is. If the same node is captured more than once, we report every capture.")
+                .value_name("LINE:COL")
+            )
+            .arg(
+                Arg::new("normalize-newlines")
+                .long("normalize-newlines")
+                .help("convert CRLF and CR line endings to LF before parsing")
+                .long_help("convert CRLF and CR line endings to LF before parsing. Some grammars trip over CR, and tree-sitter only counts LF as a line break, so this gives the same results for a file whatever platform it was saved on. Positions, byte offsets, and match text all refer to the converted source rather than the bytes on disk.")
+            )
+            .arg(
+                Arg::new("capture")
+                .long("capture")
+                .help("only output captures with this name")
+                .long_help("only output captures with this name (without the `@`), in every output format. For example, `(function_item name: (identifier)@name)@func` with `--capture name` outputs only function names. It's an error if the query doesn't have a capture with this name.")
+                .value_name("NAME")
+            )
+            .arg(
+                Arg::new("capture-index")
+                .long("capture-index")
+                .help("only output the Nth capture of each match, counting from 0")
+                .long_help("only output the Nth capture of each match, counting from 0. Captures are numbered in the order they appear in the match, so this lets you pick between captures that share a name. For example, `(arguments (identifier)@arg (identifier)@arg)` with `--capture-index 1` outputs only the second argument.")
+                .value_name("N")
+            )
+            .arg(
+                Arg::new("max-count")
+                .long("max-count")
+                .short('m')
+                .help("only output the first N matches in each file")
+                .long_help("only output the first N matches in each file, in the order they appear in the source. This is a limit for each file, not for all the files together, so it keeps huge generated files from flooding the output without hiding matches elsewhere. Counts (like in `--count` or the json-lines summary) only include the matches we kept.")
+                .value_name("N")
+            )
+            .arg(
+                Arg::new("max-lines")
+                .long("max-lines")
+                .help("skip files with more than this many lines")
+                .long_help("skip files with more than this many lines. Parsing cost grows with file size, so this is a cheap way to avoid spending a lot of time on huge generated or vendored files. Use --verbose to see which files were skipped.")
+                .value_name("N")
+            )
+            .arg(
+                Arg::new("verbose")
+                .long("verbose")
+                .short('v')
+                .help("print what we're skipping and why to stderr")
+            )
+            .arg(
+                Arg::new("replace")
+                .long("replace")
+                .help("replace the nodes captured by CAPTURE (with --with), editing files in place")
+                .long_help("replace the nodes captured by CAPTURE with the template given in --with, editing files in place. For example, `-q rust '(function_item name: (identifier)@name)' --replace @name --with 'new_{{name}}'` renames every function. The paths of files we change are printed.")
+                .value_name("CAPTURE")
+            )
+            .arg(
+                Arg::new("with")
+                .long("with")
+                .help("the replacement for --replace. Use {{name}} to insert the text of a capture")
+                .long_help("the replacement for --replace. Use {{name}} to insert the text of a capture. If the query has several patterns, you can also use captures from other patterns that match too, as long as one of their captures covers exactly the same source as the node being replaced. Use ${counter} to insert a number that goes up by one for each replaced node, starting at 0. We number nodes in source order, going through files in path order (or starting over in each file with --counter-per-file.)")
+                .value_name("TEMPLATE")
+                .requires("replace")
+            )
+            .arg(
+                Arg::new("delete")
+                .long("delete")
+                .help("delete the nodes captured by --replace instead of replacing them")
+                .requires("replace")
+                .conflicts_with("with")
+            )
+            .arg(
+                Arg::new("delete-trailing")
+                .long("delete-trailing")
+                .help("with --delete, also delete a `;` after each node and the rest of its line if it's blank")
+                .long_help("with --delete, also delete a `;` right after each node and the rest of its line if that's only whitespace. If the node was the only thing on its line, the whole line goes.")
+                .requires("delete")
+            )
+            .arg(
+                Arg::new("escape-strings")
+                .long("escape-strings")
+                .help("with --replace, escape captures inserted into string literals")
+                .long_help("with --replace, escape the text of captures inserted into string literals (when the replaced node is a string or inside one) so that quotes and backslashes in them don't break the string, and things like `${` or `#{` don't start an interpolation. Raw strings can't escape anything, so we refuse to insert text that would end one early. Text written directly in the --with template is left as it is.")
+                .requires("replace")
+            )
+            .arg(
+                Arg::new("include-trailing-comment")
+                .long("include-trailing-comment")
+                .help("with --replace, also replace or delete a comment after each node on the same line")
+                .long_help("with --replace, also replace or delete a comment after each node on the same line, so comments about the old code don't hang around. Without this, those comments are left where they are.")
+                .requires("replace")
+            )
+            .arg(
+                Arg::new("counter-per-file")
+                .long("counter-per-file")
+                .help("with --replace, start ${counter} over at 0 in every file")
+                .long_help("with --replace, start ${counter} over at 0 in every file. Without this, the counter carries on from one file to the next, so we have to rewrite files one at a time.")
+                .requires("replace")
+            )
+            .arg(
+                Arg::new("dry-run")
+                .long("dry-run")
+                .help("with --replace, print a diff of the changes instead of making them")
+                .long_help("with --replace, print a unified diff of the changes instead of making them. Paths in the diff start with `a/` and `b/` like `git diff`, so you can apply it later with `git apply` or `patch -p1`.")
+                .requires("replace")
+                .conflicts_with("atomic")
+            )
+            .arg(
+                Arg::new("atomic")
+                .long("atomic")
+                .help("with --replace, only change files if every rewrite parses cleanly")
+                .long_help("with --replace, work out every rewrite before changing anything, and only write them if none of them introduce parse errors (files that already had errors are allowed to keep them.) If any rewrite fails, no files are changed.")
+                .requires("replace")
+            )
+            .arg(
+                Arg::new("auto-indent")
+                .long("auto-indent")
+                .help("indent multi-line replacements to match the line they're inserted on")
+                .long_help("indent every line of a multi-line replacement after the first to match the line the replaced node starts on. We copy the actual indentation characters, so tabs stay tabs and spaces stay spaces.")
+                .requires("replace")
+            )
+            .arg(
+                Arg::new("grammar")
+                .long("grammar")
+                .help("load a tree-sitter grammar from a shared library")
+                .long_help("load a tree-sitter grammar from a shared library (like the ones `tree-sitter build` or `tree-sitter test` produce), given as NAME=PATH. We call the library's `tree_sitter_NAME` function to get the grammar, and you can use NAME anywhere you'd use a built-in language. We don't know which files are in the new language, so use --language-override to say (for example, `--grammar mydsl=./mydsl.so --language-override dsl:mydsl`.) Can be given multiple times.")
+                .value_name("NAME=PATH")
+                .multiple_occurrences(true)
+            )
             .arg(
                 Arg::new("languages")
                 .long("languages")
                 .help("print the language names tree-grepper knows about")
             )
+            .arg(
+                Arg::new("grammar-versions")
+                .long("grammar-versions")
+                .help("print the version of each language's grammar tree-grepper was built with")
+                .long_help("print the version of each language's grammar tree-grepper was built with, as `language version` lines. Grammar updates can change the trees (and so the query results) you get, so this is useful for figuring out why results changed between builds.")
+            )
+            .arg(
+                Arg::new("fields")
+                .long("fields")
+                .help("print the field names you can use in queries for a node type")
+                .long_help("print the field names you can use in queries for a node type. For example, `--fields rust function_item` includes `name`, so you can write queries like `(function_item name: (identifier))`.")
+                .number_of_values(2)
+                .value_names(&["LANGUAGE", "NODE_TYPE"])
+            )
             .try_get_matches_from(args)
             .context("could not parse args")?;
 
+        // everything else that takes a language name might need one of these.
+        Self::load_grammars(&matches)?;
+
         if matches.is_present("languages") {
             Ok(Self::ShowLanguages)
+        } else if matches.is_present("grammar-versions") {
+            Ok(Self::ShowGrammarVersions)
+        } else if let Some(mut values) = matches.values_of("fields") {
+            match (values.next(), values.next()) {
+                (Some(raw_lang), Some(node_type)) => Ok(Self::ShowFields(
+                    Language::from_str(raw_lang).context("could not parse language")?,
+                    node_type.to_string(),
+                )),
+                _ => bail!("--fields needs a language and a node type"),
+            }
         } else {
-            Ok(Self::DoQuery(QueryOpts {
-                extractors: Self::extractors(&matches)?,
-                paths: Self::paths(&matches)?,
+            let format =
+                QueryFormat::from_str(matches.value_of("FORMAT").context("format not provided")?)
+                    .context("could not set format")?;
+
+            if let QueryFormat::Patch = format {
+                if !matches.is_present("replace") {
+                    bail!("--format=patch only works with --replace")
+                }
+                if matches.is_present("atomic") {
+                    bail!("--format=patch doesn't change files, so it can't be used with --atomic")
+                }
+            }
+
+            let jsonl_with_summary = matches.is_present("jsonl-with-summary");
+            if jsonl_with_summary && !matches!(format, QueryFormat::JsonLines) {
+                bail!("--jsonl-with-summary only works with --format=json-lines")
+            }
+
+            let line_number = matches.is_present("line-number");
+            if line_number && !matches!(format, QueryFormat::Lines) {
+                bail!("--line-number only works with --format=lines")
+            }
+
+            let stdin = match matches.value_of("stdin") {
+                Some(raw_lang) => {
+                    if matches.occurrences_of("PATHS") > 0 {
+                        bail!("--stdin searches stdin instead of paths, so you can't give both")
+                    }
+
+                    Some(
+                        Language::from_str(raw_lang)
+                            .context("could not parse language for --stdin")?,
+                    )
+                }
+                None => None,
+            };
+
+            Ok(Self::DoQuery(Box::new(QueryOpts {
+                extractors: Self::extractors(&matches, &format)?,
+                // if we're only given files with --as, we shouldn't also go
+                // searching the current directory.
+                paths: if matches.is_present("as") && matches.occurrences_of("PATHS") == 0 {
+                    Vec::new()
+                } else {
+                    Self::paths(&matches)?
+                },
+                forced_languages: Self::forced_languages(&matches)?,
+                output_per_file: matches.value_of("output-per-file").map(PathBuf::from),
+                reverse: matches.is_present("reverse"),
+                time_files: matches.is_present("time-files"),
+                cache: matches
+                    .value_of("cache")
+                    .map(|dir| (PathBuf::from(dir), all_args.clone())),
+                stdin,
+                language_marker: matches.value_of("language-marker").map(String::from),
+                language_overrides: Self::language_overrides(&matches)?,
+                socket: matches.value_of("socket").map(PathBuf::from),
+                count: matches.is_present("count"),
+                group_by_dir: if matches.is_present("group-by-dir") {
+                    match matches.value_of("group-by-dir") {
+                        Some(raw) => Some(
+                            raw.parse::<usize>()
+                                .context("could not parse --group-by-dir as a number")?,
+                        ),
+                        None => Some(1),
+                    }
+                } else {
+                    None
+                },
+                coverage: matches.is_present("coverage"),
+                files_with_matches: matches.is_present("files-with-matches"),
+                file_kinds: matches.is_present("file-kinds"),
+                context_lines: Self::context_lines(&matches)? != (0, 0),
+                collapse_lines: matches.is_present("collapse-lines"),
+                search_compressed: matches.is_present("search-compressed"),
+                atomic: matches.is_present("atomic"),
+                staged: matches.is_present("staged"),
+                merge: matches
+                    .values_of("merge")
+                    .map(|values| values.map(PathBuf::from).collect())
+                    .unwrap_or_default(),
+                inventory: matches.is_present("no-query"),
+                query_test: matches.value_of("query-test").map(PathBuf::from),
+                check: matches.is_present("check"),
+                quiet: matches.is_present("quiet"),
+                dry_run: matches.is_present("dry-run"),
+                color: match matches.value_of("color") {
+                    Some("always") => true,
+                    Some("never") => false,
+                    // with --output-per-file, stdout being a terminal doesn't
+                    // tell us anything about the files we're writing.
+                    _ => {
+                        env::var_os("NO_COLOR").is_none()
+                            && !matches.is_present("output-per-file")
+                            && atty::is(atty::Stream::Stdout)
+                    }
+                },
+                key_value: match matches.value_of("kv") {
+                    Some(raw) => match raw.split_once(':') {
+                        Some((key, value)) => Some((
+                            key.trim_start_matches('@').to_string(),
+                            value.trim_start_matches('@').to_string(),
+                        )),
+                        None => bail!("--kv needs two capture names, like KEY:VALUE"),
+                    },
+                    None => None,
+                },
+                deadline: match matches.value_of("deadline") {
+                    Some(raw) => Some(parse_seconds(raw).context("could not parse --deadline")?),
+                    None => None,
+                },
+                max_allowed: match matches.value_of("max-allowed") {
+                    Some(raw) => Some(
+                        raw.parse::<usize>()
+                            .context("could not parse --max-allowed as a number")?,
+                    ),
+                    None => None,
+                },
                 git_ignore: !matches.is_present("no-gitignore"),
-                format: QueryFormat::from_str(
-                    matches.value_of("FORMAT").context("format not provided")?,
-                )
-                .context("could not set format")?,
+                follow_links: matches.is_present("follow"),
+                hidden: matches.is_present("hidden"),
+                threads: match matches.value_of("threads") {
+                    Some(raw) => raw
+                        .parse::<usize>()
+                        .context("could not parse --threads as a number")?,
+                    None => 0,
+                },
+                max_depth: match matches.value_of("max-depth") {
+                    Some(raw) => Some(
+                        raw.parse::<usize>()
+                            .context("could not parse --max-depth as a number")?,
+                    ),
+                    None => None,
+                },
+                format,
                 sort: matches.is_present("sort"),
-            }))
+                jsonl_with_summary,
+                line_number,
+                replacement: Self::replacement(&matches)?,
+                // an explicit path always wins over looking for the project root
+                project_root: matches.is_present("project-root")
+                    && matches.occurrences_of("PATHS") == 0,
+                modified_since: match matches.value_of("modified-since") {
+                    Some(raw) => {
+                        Some(parse_duration(raw).context("could not parse --modified-since")?)
+                    }
+                    None => None,
+                },
+                modified_until: match matches.value_of("modified-until") {
+                    Some(raw) => {
+                        Some(parse_duration(raw).context("could not parse --modified-until")?)
+                    }
+                    None => None,
+                },
+                sample: match matches.value_of("sample") {
+                    Some(raw) => Some(
+                        raw.parse::<usize>()
+                            .context("could not parse --sample as a number")?,
+                    ),
+                    None => None,
+                },
+                unique_capture: matches
+                    .value_of("unique-capture")
+                    .map(|name| name.trim_start_matches('@').to_string()),
+                seed: match matches.value_of("seed") {
+                    Some(raw) => Some(
+                        raw.parse::<u64>()
+                            .context("could not parse --seed as a number")?,
+                    ),
+                    None => None,
+                },
+            })))
         }
     }
 
-    fn extractors(matches: &ArgMatches) -> Result<Vec<Extractor>> {
-        let values = match matches.values_of("additional-query") {
-            Some(values) => values,
-            None => bail!("queries were required but not provided. This indicates an internal error and you should report it!"),
+    fn extractors(matches: &ArgMatches, format: &QueryFormat) -> Result<Vec<Extractor>> {
+        let list_errors = matches.is_present("list-errors");
+        let max_lines = match matches.value_of("max-lines") {
+            Some(raw) => Some(
+                raw.parse::<usize>()
+                    .context("could not parse --max-lines as a number")?,
+            ),
+            None => None,
+        };
+        let verbose = matches.is_present("verbose");
+        let max_node_depth = match matches.value_of("max-node-depth") {
+            Some(raw) => Some(
+                raw.parse::<usize>()
+                    .context("could not parse --max-node-depth as a number")?,
+            ),
+            None => None,
+        };
+        let max_count = match matches.value_of("max-count") {
+            Some(raw) => Some(
+                raw.parse::<usize>()
+                    .context("could not parse --max-count as a number")?,
+            ),
+            None => None,
+        };
+        let capture_index = match matches.value_of("capture-index") {
+            Some(raw) => Some(
+                raw.parse::<usize>()
+                    .context("could not parse --capture-index as a number")?,
+            ),
+            None => None,
+        };
+
+        let byte_range = match matches.value_of("byte-range") {
+            Some(raw) => Some(parse_byte_range(raw).context("could not parse --byte-range")?),
+            None => None,
+        };
+
+        let at = match matches.value_of("at") {
+            Some(raw) => Some(parse_position(raw).context("could not parse --at")?),
+            None => None,
+        };
+
+        let context_lines = match format {
+            QueryFormat::Lines => Self::context_lines(matches)?,
+            _ => (0, 0),
         };
 
+        // embedded extractors get the same settings as the ones for whole
+        // files, so their matches are filtered the same way.
+        let configure = |extractor: &mut Extractor| -> Result<()> {
+            extractor.set_list_errors(list_errors);
+            extractor.set_max_lines(max_lines);
+            extractor.set_verbose(verbose);
+            extractor.set_explain(matches.is_present("explain"));
+            extractor.set_max_node_depth(max_node_depth);
+            extractor.set_capture_index(capture_index);
+            extractor.set_only_capture(matches.value_of("capture"))?;
+            extractor.set_max_count(max_count);
+            extractor.set_byte_range(byte_range.clone());
+            extractor.set_at(at);
+            extractor.set_normalize_newlines(matches.is_present("normalize-newlines"));
+            extractor.set_ignore_parse_errors(matches.is_present("ignore-parse-errors"));
+            extractor.set_record_ancestors(matches!(format, QueryFormat::Folded));
+            extractor.set_record_indents(matches.is_present("with-indent"));
+            extractor.set_qualified_names(matches.is_present("qualified-name"));
+            extractor.set_record_lines(matches!(format, QueryFormat::Ctags));
+            extractor.set_highlight(matches!(format, QueryFormat::Html));
+            extractor.set_annotate_tree(matches!(format, QueryFormat::AnnotatedTree));
+            extractor.set_relative_to(matches.value_of("relative-to").map(String::from));
+            extractor.set_context_lines(context_lines.0, context_lines.1);
+
+            Ok(())
+        };
+
+        let query_strings = Self::query_strings(matches)?;
+        let embeds = Self::embeds(matches)?;
+
+        let mut out = Vec::with_capacity(query_strings.len());
+        for (lang, raw_query) in &query_strings {
+            let query = lang
+                .parse_query(raw_query)
+                .context("could not parse combined query")?;
+
+            let mut extractor = Extractor::new(*lang, query);
+            configure(&mut extractor)?;
+
+            for (embedded_lang, node_kind) in &embeds {
+                let embedded_query = match query_strings.get(embedded_lang) {
+                    Some(embedded_query) => embedded_query,
+                    None => bail!(
+                        "I need a query for {} to search embedded {} nodes. Add one with -q",
+                        embedded_lang,
+                        node_kind
+                    ),
+                };
+
+                let mut embedded = Extractor::new(
+                    *embedded_lang,
+                    embedded_lang
+                        .parse_query(embedded_query)
+                        .context("could not parse embedded query")?,
+                );
+                configure(&mut embedded)?;
+
+                extractor.embed(node_kind.to_string(), embedded)
+            }
+
+            out.push(extractor)
+        }
+
+        Ok(out)
+    }
+
+    fn query_strings(matches: &ArgMatches) -> Result<HashMap<Language, String>> {
+        if matches.is_present("comments-only") {
+            let pattern = matches.value_of("comment-regex");
+
+            return Ok(Language::all()
+                .into_iter()
+                .map(|lang| (lang, lang.comments_query(pattern)))
+                .collect());
+        }
+
+        let mut queries: Vec<(Language, String)> = Vec::new();
+
+        if let Some(values) = matches.values_of("additional-query") {
+            for (raw_lang, raw_query) in values.tuples() {
+                for lang in Self::query_languages(raw_lang, raw_query)? {
+                    queries.push((lang, raw_query.to_string()))
+                }
+            }
+        }
+
+        if let Some(values) = matches.values_of("query-file") {
+            for (raw_lang, raw_path) in values.tuples() {
+                let mut query = fs::read_to_string(raw_path)
+                    .with_context(|| format!("could not read a query from {}", raw_path))?;
+
+                if matches.is_present("namespace-captures") {
+                    let namespace = Path::new(raw_path)
+                        .file_stem()
+                        .and_then(|stem| stem.to_str())
+                        .with_context(|| format!("could not get a name for {}", raw_path))?;
+
+                    query = namespace_captures(&query, namespace);
+                }
+
+                for lang in Self::query_languages(raw_lang, &query)? {
+                    queries.push((lang, query.clone()));
+                }
+            }
+        }
+
+        if queries.is_empty() {
+            // without a query, we look for errors (or take inventory) in every
+            // language we know about.
+            if matches.is_present("list-errors") || matches.is_present("no-query") {
+                return Ok(Language::all()
+                    .into_iter()
+                    .map(|lang| (lang, String::new()))
+                    .collect());
+            }
+
+            // merging reads results we already have, so there's nothing to
+            // query.
+            if matches.is_present("merge") {
+                return Ok(HashMap::new());
+            }
+
+            bail!("queries were required but not provided. This indicates an internal error and you should report it!")
+        }
+
         // the most common case is going to be one query, so let's allocate
         // that immediately...
         let mut query_strings: HashMap<Language, String> = HashMap::with_capacity(1);
@@ -125,13 +1063,11 @@ impl Invocation {
         // can't specify queries across multiple languages! Nobody should ever
         // notice, except that they won't see as much of a slowdown for adding
         // new queries to an invocation as they might expect. (Well, hopefully!)
-        for (raw_lang, raw_query) in values.tuples() {
-            let lang = Language::from_str(raw_lang).context("could not parse language")?;
-
-            let mut query_out = String::from(raw_query);
+        for (lang, raw_query) in queries {
+            let mut query_out = raw_query.clone();
 
             let temp_query = lang
-                .parse_query(raw_query)
+                .parse_query(&raw_query)
                 .context("could not parse query")?;
 
             if temp_query.capture_names().is_empty() {
@@ -145,16 +1081,150 @@ impl Invocation {
             }
         }
 
-        let mut out = Vec::with_capacity(query_strings.len());
-        for (lang, raw_query) in query_strings {
-            let query = lang
-                .parse_query(&raw_query)
-                .context("could not parse combined query")?;
+        Ok(query_strings)
+    }
 
-            out.push(Extractor::new(lang, query))
+    /// The languages to run a query in. `auto` means every language we know
+    /// about where the query compiles, so one query can search a project in
+    /// several languages as long as their grammars share the node types.
+    /// Files in the other languages get skipped.
+    fn query_languages(raw_lang: &str, query: &str) -> Result<Vec<Language>> {
+        if raw_lang != "auto" {
+            return Ok(vec![
+                Language::from_str(raw_lang).context("could not parse language")?
+            ]);
         }
 
-        Ok(out)
+        let langs: Vec<Language> = Language::all()
+            .into_iter()
+            .chain(
+                dynamic_grammar::names()
+                    .iter()
+                    .filter_map(|name| dynamic_grammar::find(name))
+                    .map(Language::Dynamic),
+            )
+            .filter(|lang| lang.parse_query(query).is_ok())
+            .collect();
+
+        if langs.is_empty() {
+            bail!("the query didn't compile in any language we know about, so `auto` has nothing to search")
+        }
+
+        Ok(langs)
+    }
+
+    /// How many lines of context to show before and after each match.
+    fn context_lines(matches: &ArgMatches) -> Result<(usize, usize)> {
+        let lines = |name: &str| -> Result<Option<usize>> {
+            match matches.value_of(name) {
+                Some(raw) => {
+                    Ok(Some(raw.parse::<usize>().with_context(|| {
+                        format!("could not parse --{} as a number", name)
+                    })?))
+                }
+                None => Ok(None),
+            }
+        };
+
+        let both = lines("context")?.unwrap_or(0);
+
+        Ok((
+            lines("before-context")?.unwrap_or(both),
+            lines("after-context")?.unwrap_or(both),
+        ))
+    }
+
+    fn replacement(matches: &ArgMatches) -> Result<Option<Replacement>> {
+        let mut replacement = match (matches.value_of("replace"), matches.value_of("with")) {
+            (Some(capture), Some(template)) => Replacement::new(capture, template.to_string()),
+            (Some(capture), None) if matches.is_present("delete") => Replacement::delete(capture),
+            (Some(_), None) => bail!("--replace needs either --with or --delete"),
+            _ => return Ok(None),
+        };
+
+        replacement.set_auto_indent(matches.is_present("auto-indent"));
+        replacement.set_delete_trailing(matches.is_present("delete-trailing"));
+        replacement.set_include_trailing_comment(matches.is_present("include-trailing-comment"));
+        replacement.set_escape_strings(matches.is_present("escape-strings"));
+        replacement.set_counter_per_file(matches.is_present("counter-per-file"));
+
+        Ok(Some(replacement))
+    }
+
+    fn embeds(matches: &ArgMatches) -> Result<Vec<(Language, String)>> {
+        match matches.values_of("embedded") {
+            Some(values) => values
+                .map(|raw| match raw.split_once(':') {
+                    Some((raw_lang, node_kind)) => Ok((
+                        Language::from_str(raw_lang)
+                            .context("could not parse embedded language")?,
+                        node_kind.to_string(),
+                    )),
+                    None => bail!(
+                        "could not parse `{}` as an embedded language. Use LANGUAGE:NODE_TYPE",
+                        raw
+                    ),
+                })
+                .collect(),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn forced_languages(matches: &ArgMatches) -> Result<Vec<(Language, PathBuf)>> {
+        match matches.values_of("as") {
+            Some(values) => values
+                .tuples()
+                .map(|(raw_lang, raw_path)| {
+                    Ok((
+                        Language::from_str(raw_lang)
+                            .context("could not parse language for --as")?,
+                        PathBuf::from(raw_path),
+                    ))
+                })
+                .collect(),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn load_grammars(matches: &ArgMatches) -> Result<()> {
+        if let Some(values) = matches.values_of("grammar") {
+            for raw in values {
+                let (name, path) = match raw.split_once('=') {
+                    Some(parts) => parts,
+                    None => bail!("could not parse `{}` as a grammar. Use NAME=PATH", raw),
+                };
+
+                if name == "auto" {
+                    bail!("auto is reserved for running a query in every language")
+                } else if let Ok(Language::Dynamic(_)) | Err(_) = Language::from_str(name) {
+                    dynamic_grammar::load(name, Path::new(path))
+                        .with_context(|| format!("could not load the {} grammar", name))?;
+                } else {
+                    bail!("{} is already a built-in language", name)
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn language_overrides(matches: &ArgMatches) -> Result<Vec<(String, Language)>> {
+        match matches.values_of("language-override") {
+            Some(values) => values
+                .map(|raw| match raw.rsplit_once(':') {
+                    Some((name, raw_lang)) if !name.is_empty() => Ok((
+                        name.to_string(),
+                        Language::from_str(raw_lang)
+                            .context("could not parse language for --language-override")?,
+                    )),
+                    _ => bail!(
+                        "could not parse `{}` as a language override. Use EXT:LANGUAGE",
+                        raw
+                    ),
+                })
+                .collect(),
+            None => Ok(Vec::new()),
+        }
     }
 
     fn paths(matches: &ArgMatches) -> Result<Vec<PathBuf>> {
@@ -169,12 +1239,148 @@ impl Invocation {
     }
 }
 
+/// Prefix every capture name in the query with `namespace.`, leaving alone
+/// anything that looks like a capture inside a string or comment. Captures
+/// starting with `_` stay as they are so they're still ignored.
+fn namespace_captures(query: &str, namespace: &str) -> String {
+    let mut out = String::with_capacity(query.len());
+    let mut chars = query.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        out.push(c);
+
+        match c {
+            '"' => {
+                while let Some(c) = chars.next() {
+                    out.push(c);
+
+                    if c == '\\' {
+                        out.extend(chars.next());
+                    } else if c == '"' {
+                        break;
+                    }
+                }
+            }
+
+            ';' => {
+                for c in chars.by_ref() {
+                    out.push(c);
+
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+
+            '@' if matches!(chars.peek(), Some(next) if *next != '_') => {
+                out.push_str(namespace);
+                out.push('.');
+            }
+
+            _ => (),
+        }
+    }
+
+    out
+}
+
+/// Parse a (possibly fractional) number of seconds.
+fn parse_seconds(raw: &str) -> Result<Duration> {
+    let seconds: f64 = raw
+        .parse()
+        .with_context(|| format!("could not parse a number of seconds from {}", raw))?;
+
+    if !seconds.is_finite() || seconds < 0.0 {
+        bail!("{} isn't a number of seconds we can wait", raw)
+    }
+
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+/// Parse byte ranges like `100:200`. The end is exclusive.
+pub fn parse_byte_range(raw: &str) -> Result<Range<usize>> {
+    let (start, end) = raw
+        .split_once(':')
+        .with_context(|| format!("{} needs to look like START:END", raw))?;
+
+    let start: usize = start
+        .parse()
+        .with_context(|| format!("could not parse a start offset from {}", raw))?;
+    let end: usize = end
+        .parse()
+        .with_context(|| format!("could not parse an end offset from {}", raw))?;
+
+    if start > end {
+        bail!("the range {} ends before it starts", raw)
+    }
+
+    Ok(start..end)
+}
+
+/// Parse positions like `12:5`. Both parts count from 1, like our output,
+/// but the point we return counts from 0, like tree-sitter.
+pub fn parse_position(raw: &str) -> Result<Point> {
+    let (line, column) = raw
+        .split_once(':')
+        .with_context(|| format!("{} needs to look like LINE:COL", raw))?;
+
+    let line: usize = line
+        .parse()
+        .with_context(|| format!("could not parse a line from {}", raw))?;
+    let column: usize = column
+        .parse()
+        .with_context(|| format!("could not parse a column from {}", raw))?;
+
+    if line == 0 || column == 0 {
+        bail!("lines and columns in {} start at 1", raw)
+    }
+
+    Ok(Point::new(line - 1, column - 1))
+}
+
+/// Parse durations like `30s`, `15m`, `12h`, `7d`, or `2w`.
+pub fn parse_duration(raw: &str) -> Result<Duration> {
+    let split = raw
+        .find(|c: char| !c.is_ascii_digit())
+        .with_context(|| format!("{} needs a unit (s, m, h, d, or w)", raw))?;
+    let (amount, unit) = raw.split_at(split);
+
+    let amount: u64 = amount
+        .parse()
+        .with_context(|| format!("could not parse a number from {}", raw))?;
+
+    let seconds_per_unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        "w" => 60 * 60 * 24 * 7,
+        _ => bail!("unknown unit {} (expected s, m, h, d, or w)", unit),
+    };
+
+    match amount.checked_mul(seconds_per_unit) {
+        Some(seconds) => Ok(Duration::from_secs(seconds)),
+        None => bail!("{} is too long a duration", raw),
+    }
+}
+
 #[derive(Debug)]
 pub enum QueryFormat {
     Lines,
     Json,
     JsonLines,
     PrettyJson,
+    Gnu,
+    Folded,
+    AnnotatedTree,
+    Spans,
+    Ctags,
+    Csv,
+    Sarif,
+    NullFileList,
+    Html,
+    Patch,
+    Msgpack,
 }
 
 impl FromStr for QueryFormat {
@@ -186,6 +1392,17 @@ impl FromStr for QueryFormat {
             "json" => Ok(QueryFormat::Json),
             "json-lines" => Ok(QueryFormat::JsonLines),
             "pretty-json" => Ok(QueryFormat::PrettyJson),
+            "gnu" => Ok(QueryFormat::Gnu),
+            "folded" => Ok(QueryFormat::Folded),
+            "annotated-tree" => Ok(QueryFormat::AnnotatedTree),
+            "spans" => Ok(QueryFormat::Spans),
+            "ctags" => Ok(QueryFormat::Ctags),
+            "csv" => Ok(QueryFormat::Csv),
+            "sarif" => Ok(QueryFormat::Sarif),
+            "null-file-list" => Ok(QueryFormat::NullFileList),
+            "html" => Ok(QueryFormat::Html),
+            "patch" => Ok(QueryFormat::Patch),
+            "msgpack" => Ok(QueryFormat::Msgpack),
             _ => bail!("unknown format. See --help for valid formats."),
         }
     }
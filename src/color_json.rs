@@ -0,0 +1,204 @@
+use serde::Serialize;
+use serde_json::ser::{Formatter, PrettyFormatter, Serializer};
+use std::io::{self, Write};
+
+const KEY: &[u8] = b"\x1b[1;34m";
+const STRING: &[u8] = b"\x1b[32m";
+const NUMBER: &[u8] = b"\x1b[36m";
+const RESET: &[u8] = b"\x1b[0m";
+
+/// Pretty-print `value` like `serde_json::to_writer_pretty`, but with ANSI
+/// colors for keys, strings, and numbers.
+pub fn to_writer_pretty<W, T>(writer: W, value: &T) -> serde_json::Result<()>
+where
+    W: Write,
+    T: ?Sized + Serialize,
+{
+    let mut serializer = Serializer::with_formatter(
+        writer,
+        ColorFormatter {
+            pretty: PrettyFormatter::new(),
+            in_key: false,
+        },
+    );
+
+    value.serialize(&mut serializer)
+}
+
+/// Wraps `PrettyFormatter` (which keeps track of indentation for us) and
+/// surrounds scalar values with color codes.
+struct ColorFormatter<'a> {
+    pretty: PrettyFormatter<'a>,
+    in_key: bool,
+}
+
+impl<'a> ColorFormatter<'a> {
+    fn number<W, F>(&mut self, writer: &mut W, write: F) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+        F: FnOnce(&mut PrettyFormatter<'a>, &mut W) -> io::Result<()>,
+    {
+        writer.write_all(NUMBER)?;
+        write(&mut self.pretty, writer)?;
+        writer.write_all(RESET)
+    }
+}
+
+impl<'a> Formatter for ColorFormatter<'a> {
+    fn write_i64<W>(&mut self, writer: &mut W, value: i64) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.number(writer, |pretty, writer| pretty.write_i64(writer, value))
+    }
+
+    fn write_u64<W>(&mut self, writer: &mut W, value: u64) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.number(writer, |pretty, writer| pretty.write_u64(writer, value))
+    }
+
+    fn write_f64<W>(&mut self, writer: &mut W, value: f64) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.number(writer, |pretty, writer| pretty.write_f64(writer, value))
+    }
+
+    fn begin_string<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        writer.write_all(if self.in_key { KEY } else { STRING })?;
+        self.pretty.begin_string(writer)
+    }
+
+    fn end_string<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.pretty.end_string(writer)?;
+        writer.write_all(RESET)
+    }
+
+    fn begin_array<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.pretty.begin_array(writer)
+    }
+
+    fn end_array<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.pretty.end_array(writer)
+    }
+
+    fn begin_array_value<W>(&mut self, writer: &mut W, first: bool) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.pretty.begin_array_value(writer, first)
+    }
+
+    fn end_array_value<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.pretty.end_array_value(writer)
+    }
+
+    fn begin_object<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.pretty.begin_object(writer)
+    }
+
+    fn end_object<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.pretty.end_object(writer)
+    }
+
+    fn begin_object_key<W>(&mut self, writer: &mut W, first: bool) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.in_key = true;
+        self.pretty.begin_object_key(writer, first)
+    }
+
+    fn end_object_key<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.in_key = false;
+        self.pretty.end_object_key(writer)
+    }
+
+    fn begin_object_value<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.pretty.begin_object_value(writer)
+    }
+
+    fn end_object_value<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.pretty.end_object_value(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn colored(value: &serde_json::Value) -> String {
+        let mut out = Vec::new();
+        to_writer_pretty(&mut out, value).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn strip_colors(colored: &str) -> String {
+        let mut out = String::new();
+        let mut in_escape = false;
+
+        for c in colored.chars() {
+            match c {
+                '\x1b' => in_escape = true,
+                'm' if in_escape => in_escape = false,
+                _ if in_escape => (),
+                _ => out.push(c),
+            }
+        }
+
+        out
+    }
+
+    #[test]
+    fn colors_keys_strings_and_numbers() {
+        let value = serde_json::json!({"name": "foo", "row": 1});
+
+        let out = colored(&value);
+
+        assert!(out.contains("\x1b[1;34m\"name\"\x1b[0m"));
+        assert!(out.contains("\x1b[32m\"foo\"\x1b[0m"));
+        assert!(out.contains("\x1b[36m1\x1b[0m"));
+    }
+
+    #[test]
+    fn matches_plain_pretty_output_without_colors() {
+        let value = serde_json::json!([{"a": [1, 2.5, "x"], "b": {"c": null}}, true]);
+
+        assert_eq!(
+            strip_colors(&colored(&value)),
+            serde_json::to_string_pretty(&value).unwrap()
+        );
+    }
+}
@@ -0,0 +1,376 @@
+use std::fmt::Write;
+
+/// How many unchanged lines to show around each change, like `diff -u`.
+const CONTEXT: usize = 3;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Op {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Make a unified diff (like `diff -u` or `git diff`) between two versions of
+/// the file at `path`, or an empty string if they're the same.
+pub fn unified(path: &str, old: &str, new: &str) -> String {
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+    let ops = diff(&old_lines, &new_lines);
+
+    let mut out = String::new();
+
+    for hunk in hunks(&ops) {
+        if out.is_empty() {
+            // writing to a String can't fail
+            let _ = writeln!(out, "--- a/{}\n+++ b/{}", path, path);
+        }
+
+        let (old_start, old_len, new_start, new_len) = hunk_range(hunk);
+        let _ = writeln!(
+            out,
+            "@@ -{} +{} @@",
+            range(old_start, old_len),
+            range(new_start, new_len)
+        );
+
+        for op in hunk {
+            match op {
+                Op::Equal(old_index, _) => push_line(&mut out, ' ', old_lines[*old_index]),
+                Op::Delete(old_index) => push_line(&mut out, '-', old_lines[*old_index]),
+                Op::Insert(new_index) => push_line(&mut out, '+', new_lines[*new_index]),
+            }
+        }
+    }
+
+    out
+}
+
+/// Make a patch in the format `git diff --full-index` uses, which `git apply`
+/// can check against the blobs it was made from. `mode` is the file's mode
+/// as git records it, like `100644`.
+pub fn git_patch(path: &str, old: &[u8], new: &[u8], mode: &str) -> String {
+    let diff = unified(
+        path,
+        &String::from_utf8_lossy(old),
+        &String::from_utf8_lossy(new),
+    );
+    if diff.is_empty() {
+        return diff;
+    }
+
+    format!(
+        "diff --git a/{} b/{}\nindex {}..{} {}\n{}",
+        path,
+        path,
+        blob_id(old),
+        blob_id(new),
+        mode,
+        diff
+    )
+}
+
+/// The object ID git would give a blob with these contents.
+fn blob_id(contents: &[u8]) -> String {
+    let mut object = format!("blob {}\0", contents.len()).into_bytes();
+    object.extend_from_slice(contents);
+
+    sha1(&object)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// SHA-1, which is what git names objects with. This isn't for anything
+/// security-sensitive, just for matching git's object IDs.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut words = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            words[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            words[i] = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in words.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (value, add) in state.iter_mut().zip([a, b, c, d, e].iter()) {
+            *value = value.wrapping_add(*add);
+        }
+    }
+
+    let mut out = [0u8; 20];
+    for (chunk, value) in out.chunks_mut(4).zip(state.iter()) {
+        chunk.copy_from_slice(&value.to_be_bytes());
+    }
+    out
+}
+
+fn push_line(out: &mut String, prefix: char, line: &str) {
+    out.push(prefix);
+    out.push_str(line);
+
+    if !line.ends_with('\n') {
+        out.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+/// Hunk ranges are 1-based, except that an empty range points at the line
+/// before where it would be.
+fn range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
+
+/// Where a hunk starts in both files (0-based) and how many lines it covers.
+fn hunk_range(hunk: &[Op]) -> (usize, usize, usize, usize) {
+    let (mut old_start, mut new_start) = (None, None);
+    let (mut old_len, mut new_len) = (0, 0);
+    // for hunks that only add or only remove lines, we need to know where
+    // they go in the other file.
+    let (mut old_next, mut new_next) = (0, 0);
+
+    for op in hunk {
+        match op {
+            Op::Equal(old_index, new_index) => {
+                old_start.get_or_insert(*old_index);
+                new_start.get_or_insert(*new_index);
+                old_len += 1;
+                new_len += 1;
+                old_next = old_index + 1;
+                new_next = new_index + 1;
+            }
+            Op::Delete(old_index) => {
+                old_start.get_or_insert(*old_index);
+                old_len += 1;
+                old_next = old_index + 1;
+            }
+            Op::Insert(new_index) => {
+                new_start.get_or_insert(*new_index);
+                new_len += 1;
+                new_next = new_index + 1;
+            }
+        }
+    }
+
+    (
+        old_start.unwrap_or(old_next),
+        old_len,
+        new_start.unwrap_or(new_next),
+        new_len,
+    )
+}
+
+/// Split the diff into hunks of changes with up to `CONTEXT` unchanged lines
+/// around them, joining changes that are close enough to share context.
+fn hunks(ops: &[Op]) -> Vec<&[Op]> {
+    let changes: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| !matches!(op, Op::Equal(_, _)))
+        .map(|(index, _)| index)
+        .collect();
+
+    let mut out = Vec::new();
+    let mut index = 0;
+
+    while index < changes.len() {
+        let start = changes[index].saturating_sub(CONTEXT);
+        let mut end = changes[index] + 1;
+
+        while index + 1 < changes.len() && changes[index + 1] - end <= CONTEXT * 2 {
+            index += 1;
+            end = changes[index] + 1;
+        }
+
+        out.push(&ops[start..(end + CONTEXT).min(ops.len())]);
+        index += 1;
+    }
+
+    out
+}
+
+/// Find the shortest way to edit `old` into `new` with Myers' algorithm.
+/// This takes time proportional to the length of the files times the number
+/// of changed lines, which is fine since replacements usually don't change
+/// much.
+fn diff(old: &[&str], new: &[&str]) -> Vec<Op> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = n + m;
+    let offset = max + 1;
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+
+    // the furthest point we got to on each diagonal before each step, for
+    // walking back to find the path we took.
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    'search: for d in 0..=max {
+        trace.push(v.clone());
+
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d
+                || (k != d && v[(offset + k - 1) as usize] < v[(offset + k + 1) as usize])
+            {
+                v[(offset + k + 1) as usize]
+            } else {
+                v[(offset + k - 1) as usize] + 1
+            };
+            let mut y = x - k;
+
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+
+            v[(offset + k) as usize] = x;
+
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut x, mut y) = (n, m);
+
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+
+        let previous_k =
+            if k == -d || (k != d && v[(offset + k - 1) as usize] < v[(offset + k + 1) as usize]) {
+                k + 1
+            } else {
+                k - 1
+            };
+        let previous_x = v[(offset + previous_k) as usize];
+        let previous_y = previous_x - previous_k;
+
+        while x > previous_x && y > previous_y {
+            x -= 1;
+            y -= 1;
+            ops.push(Op::Equal(x as usize, y as usize));
+        }
+
+        if d > 0 {
+            if x == previous_x {
+                ops.push(Op::Insert(previous_y as usize));
+            } else {
+                ops.push(Op::Delete(previous_x as usize));
+            }
+        }
+
+        x = previous_x;
+        y = previous_y;
+    }
+
+    ops.reverse();
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_is_empty() {
+        assert_eq!(unified("x", "a\nb\n", "a\nb\n"), "");
+    }
+
+    #[test]
+    fn test_changed_line() {
+        assert_eq!(
+            unified("x.rs", "a\nb\nc\n", "a\nB\nc\n"),
+            "--- a/x.rs\n+++ b/x.rs\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n"
+        );
+    }
+
+    #[test]
+    fn test_far_apart_changes_get_their_own_hunks() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+        let new = "one\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\ntwelve\n";
+
+        assert_eq!(
+            unified("x", old, new),
+            "--- a/x\n+++ b/x\n\
+             @@ -1,4 +1,4 @@\n-1\n+one\n 2\n 3\n 4\n\
+             @@ -9,4 +9,4 @@\n 9\n 10\n 11\n-12\n+twelve\n"
+        );
+    }
+
+    #[test]
+    fn test_removed_and_added_lines() {
+        assert_eq!(
+            unified("x", "a\nb\n", "a\n"),
+            "--- a/x\n+++ b/x\n@@ -1,2 +1 @@\n a\n-b\n"
+        );
+
+        assert_eq!(
+            unified("x", "", "a\n"),
+            "--- a/x\n+++ b/x\n@@ -0,0 +1 @@\n+a\n"
+        );
+    }
+
+    #[test]
+    fn test_blob_ids_match_git() {
+        // from `printf '' | git hash-object --stdin` and
+        // `printf 'hello\n' | git hash-object --stdin`
+        assert_eq!(blob_id(b""), "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391");
+        assert_eq!(
+            blob_id(b"hello\n"),
+            "ce013625030ba8dba906f756967f9e9ca394464a"
+        );
+    }
+
+    #[test]
+    fn test_git_patch() {
+        assert_eq!(
+            git_patch("x.rs", b"a\n", b"b\n", "100644"),
+            "diff --git a/x.rs b/x.rs\n\
+             index 78981922613b2afb6025042ff6bd878ac1994e85..61780798228d17af2d34fce4cfbdf35556832472 100644\n\
+             --- a/x.rs\n+++ b/x.rs\n@@ -1 +1 @@\n-a\n+b\n"
+        );
+
+        assert_eq!(git_patch("x.rs", b"a\n", b"a\n", "100644"), "");
+    }
+
+    #[test]
+    fn test_missing_newline_at_end() {
+        assert_eq!(
+            unified("x", "a", "b"),
+            "--- a/x\n+++ b/x\n@@ -1 +1 @@\n-a\n\\ No newline at end of file\n+b\n\\ No newline at end of file\n"
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use anyhow::{anyhow, bail, Context, Result};
+use libloading::{Library, Symbol};
+use once_cell::sync::Lazy;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::sync::RwLock;
+
+/// Grammars loaded with `--grammar`. They're leaked so `Language` can keep
+/// pointing at them without a lifetime, which is fine since we only load
+/// them once, when we start.
+static GRAMMARS: Lazy<RwLock<Vec<&'static DynamicGrammar>>> = Lazy::new(|| RwLock::new(Vec::new()));
+
+/// A tree-sitter grammar loaded from a shared library at runtime instead of
+/// being built in.
+#[derive(Debug)]
+pub struct DynamicGrammar {
+    name: String,
+    language: tree_sitter::Language,
+}
+
+impl DynamicGrammar {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn language(&self) -> tree_sitter::Language {
+        self.language
+    }
+}
+
+// names are unique (see `load`), so they're all we need to compare.
+impl PartialEq for DynamicGrammar {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for DynamicGrammar {}
+
+impl Hash for DynamicGrammar {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state)
+    }
+}
+
+/// Load the grammar called `name` from the shared library at `path`, the
+/// same way the tree-sitter CLI does: by calling its `tree_sitter_NAME`
+/// function (with dashes in the name turned into underscores.) After this,
+/// `find(name)` returns the grammar.
+pub fn load(name: &str, path: &Path) -> Result<&'static DynamicGrammar> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        bail!(
+            "`{}` can't be a grammar name. Use letters, numbers, `_`, and `-`",
+            name
+        )
+    }
+
+    let symbol = format!("tree_sitter_{}", name.replace('-', "_"));
+
+    // Safety: loading a library runs its initializers and calling the symbol
+    // runs its code, neither of which we can check. This is the same trust
+    // the tree-sitter CLI extends to grammars, and the user asked us to load
+    // this one.
+    let language = unsafe {
+        let library = Library::new(path)
+            .with_context(|| format!("could not load a grammar from {}", path.display()))?;
+
+        let language = {
+            let constructor: Symbol<unsafe extern "C" fn() -> tree_sitter::Language> =
+                library.get(symbol.as_bytes()).with_context(|| {
+                    format!("{} doesn't have a {} function", path.display(), symbol)
+                })?;
+
+            constructor()
+        };
+
+        // the language points into the library's memory, so the library has
+        // to stay loaded as long as we might parse with it.
+        std::mem::forget(library);
+
+        language
+    };
+
+    let version = language.version();
+    if !(tree_sitter::MIN_COMPATIBLE_LANGUAGE_VERSION..=tree_sitter::LANGUAGE_VERSION)
+        .contains(&version)
+    {
+        bail!(
+            "the {} grammar in {} was generated for tree-sitter ABI version {}, but we can only use versions {} through {}",
+            name,
+            path.display(),
+            version,
+            tree_sitter::MIN_COMPATIBLE_LANGUAGE_VERSION,
+            tree_sitter::LANGUAGE_VERSION,
+        )
+    }
+
+    let grammar: &'static DynamicGrammar = Box::leak(Box::new(DynamicGrammar {
+        name: name.to_string(),
+        language,
+    }));
+
+    let mut grammars = GRAMMARS
+        .write()
+        .map_err(|_| anyhow!("the list of loaded grammars was poisoned"))?;
+    // loading a grammar again replaces it, so names stay unique.
+    grammars.retain(|existing| existing.name != grammar.name);
+    grammars.push(grammar);
+
+    Ok(grammar)
+}
+
+/// Get a grammar loaded with `load`.
+pub fn find(name: &str) -> Option<&'static DynamicGrammar> {
+    GRAMMARS
+        .read()
+        .ok()?
+        .iter()
+        .find(|grammar| grammar.name == name)
+        .copied()
+}
+
+/// The names of every grammar loaded with `load`.
+pub fn names() -> Vec<String> {
+    match GRAMMARS.read() {
+        Ok(grammars) => grammars
+            .iter()
+            .map(|grammar| grammar.name.clone())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names_must_be_identifiers() {
+        let err = load("not a name", Path::new("/nonexistent.so")).unwrap_err();
+
+        assert!(err.to_string().contains("can't be a grammar name"))
+    }
+
+    #[test]
+    fn test_missing_library() {
+        let err = load("missing", Path::new("/nonexistent/grammar.so")).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "could not load a grammar from /nonexistent/grammar.so"
+        );
+        assert!(find("missing").is_none());
+    }
+}
@@ -1,12 +1,18 @@
 use crate::language::Language;
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use flate2::read::GzDecoder;
+use memmap2::Mmap;
 use serde::ser::{SerializeStruct, Serializer};
-use serde::Serialize;
-use std::collections::HashSet;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::ffi::OsStr;
 use std::fmt::{self, Display};
 use std::fs;
+use std::io::Read;
+use std::ops::{Deref, Range};
 use std::path::{Path, PathBuf};
-use tree_sitter::{Parser, Point, Query, QueryCursor};
+use tree_sitter::{Node, Parser, Point, Query, QueryCursor, Tree};
 
 #[derive(Debug)]
 pub struct Extractor {
@@ -15,6 +21,42 @@ pub struct Extractor {
     query: Query,
     captures: Vec<String>,
     ignores: HashSet<usize>,
+    embeds: Vec<Embed>,
+    list_errors: bool,
+    max_lines: Option<usize>,
+    verbose: bool,
+    relative_to: Option<String>,
+    explain: bool,
+    record_ancestors: bool,
+    record_lines: bool,
+    highlight: bool,
+    record_indents: bool,
+    qualified_names: bool,
+    annotate_tree: bool,
+    ignore_parse_errors: bool,
+    max_node_depth: Option<usize>,
+    capture_index: Option<usize>,
+    only_capture: Option<u32>,
+    max_count: Option<usize>,
+    byte_range: Option<Range<usize>>,
+    at: Option<Point>,
+    normalize_newlines: bool,
+    context_before: usize,
+    context_after: usize,
+}
+
+#[derive(Debug)]
+struct Embed {
+    node_kind: String,
+    extractor: Extractor,
+}
+
+/// Where some source sits in the file we're searching. This is all zeroes
+/// unless the source is embedded in another language.
+#[derive(Debug, Default, Clone, Copy)]
+struct Offset {
+    bytes: usize,
+    depth: usize,
 }
 
 impl Extractor {
@@ -34,9 +76,170 @@ impl Extractor {
             query,
             captures,
             ignores,
+            embeds: Vec::new(),
+            list_errors: false,
+            max_lines: None,
+            verbose: false,
+            relative_to: None,
+            explain: false,
+            record_ancestors: false,
+            record_lines: false,
+            highlight: false,
+            record_indents: false,
+            qualified_names: false,
+            annotate_tree: false,
+            ignore_parse_errors: false,
+            max_node_depth: None,
+            capture_index: None,
+            only_capture: None,
+            max_count: None,
+            byte_range: None,
+            at: None,
+            normalize_newlines: false,
+            context_before: 0,
+            context_after: 0,
         }
     }
 
+    /// Report ERROR and MISSING nodes instead of query matches.
+    pub fn set_list_errors(&mut self, list_errors: bool) {
+        self.list_errors = list_errors
+    }
+
+    /// Skip (without parsing) any source with more than this many lines.
+    pub fn set_max_lines(&mut self, max_lines: Option<usize>) {
+        self.max_lines = max_lines
+    }
+
+    /// Also report where each match is relative to its nearest ancestor of
+    /// this node type.
+    pub fn set_relative_to(&mut self, relative_to: Option<String>) {
+        self.relative_to = relative_to
+    }
+
+    /// Say which pattern and capture produced each match.
+    pub fn set_explain(&mut self, explain: bool) {
+        self.explain = explain
+    }
+
+    /// Remember the kinds of each match's ancestors, for folded output.
+    pub fn set_record_ancestors(&mut self, record_ancestors: bool) {
+        self.record_ancestors = record_ancestors
+    }
+
+    /// Remember the line each match starts on, for ctags output.
+    pub fn set_record_lines(&mut self, record_lines: bool) {
+        self.record_lines = record_lines
+    }
+
+    /// Render each match as syntax-highlighted HTML, for HTML output.
+    pub fn set_highlight(&mut self, highlight: bool) {
+        self.highlight = highlight
+    }
+
+    /// Report the whitespace at the start of each match's first line.
+    pub fn set_record_indents(&mut self, record_indents: bool) {
+        self.record_indents = record_indents
+    }
+
+    /// Report the names of the items each match is inside, like
+    /// `module::Type::method`.
+    pub fn set_qualified_names(&mut self, qualified_names: bool) {
+        self.qualified_names = qualified_names
+    }
+
+    /// Render the whole tree of each file with matches, marking matched nodes.
+    pub fn set_annotate_tree(&mut self, annotate_tree: bool) {
+        self.annotate_tree = annotate_tree
+    }
+
+    /// Drop matches inside ERROR nodes, since the tree there is the parser's
+    /// best guess rather than what the source actually means.
+    pub fn set_ignore_parse_errors(&mut self, ignore_parse_errors: bool) {
+        self.ignore_parse_errors = ignore_parse_errors
+    }
+
+    /// Drop matches more than this many levels below the root of the tree
+    /// (where the root itself is at depth 0.)
+    pub fn set_max_node_depth(&mut self, max_node_depth: Option<usize>) {
+        self.max_node_depth = max_node_depth
+    }
+
+    /// Only keep the capture at this position (counting from 0) in each
+    /// match. This lets you pick out captures that share a name.
+    pub fn set_capture_index(&mut self, capture_index: Option<usize>) {
+        self.capture_index = capture_index
+    }
+
+    /// Only output captures with this name. It's an error if the query
+    /// doesn't have a capture by that name, since then we'd never output
+    /// anything.
+    pub fn set_only_capture(&mut self, name: Option<&str>) -> Result<()> {
+        self.only_capture = match name {
+            Some(name) => match self.captures.iter().position(|capture| capture == name) {
+                Some(index) => Some(index as u32),
+                None => bail!(
+                    "the {} query doesn't have a capture named @{} (it has {})",
+                    self.language,
+                    name,
+                    self.captures
+                        .iter()
+                        .map(|capture| format!("@{}", capture))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                ),
+            },
+            None => None,
+        };
+
+        Ok(())
+    }
+
+    /// Only keep the first this-many matches (in source order) in each file.
+    pub fn set_max_count(&mut self, max_count: Option<usize>) {
+        self.max_count = max_count
+    }
+
+    /// Only report matches that start inside this range of bytes. We still
+    /// parse the whole source so the tree is the same as usual.
+    pub fn set_byte_range(&mut self, byte_range: Option<Range<usize>>) {
+        self.byte_range = byte_range
+    }
+
+    /// Only report the smallest match containing this point. If the same
+    /// node is captured more than once, we keep all of them.
+    pub fn set_at(&mut self, at: Option<Point>) {
+        self.at = at
+    }
+
+    /// Convert CRLF and lone CR line endings to LF before parsing. Positions
+    /// and match text then refer to the converted source, not the bytes on
+    /// disk.
+    pub fn set_normalize_newlines(&mut self, normalize_newlines: bool) {
+        self.normalize_newlines = normalize_newlines
+    }
+
+    /// Keep this many lines of source before and after each match, for
+    /// showing matches in context.
+    pub fn set_context_lines(&mut self, before: usize, after: usize) {
+        self.context_before = before;
+        self.context_after = after;
+    }
+
+    /// Say what we're skipping and why on stderr.
+    pub fn set_verbose(&mut self, verbose: bool) {
+        self.verbose = verbose
+    }
+
+    /// Re-parse the contents of every `node_kind` node with the given
+    /// extractor's language and include its matches alongside our own.
+    pub fn embed(&mut self, node_kind: String, extractor: Extractor) {
+        self.embeds.push(Embed {
+            node_kind,
+            extractor,
+        })
+    }
+
     pub fn language(&self) -> &Language {
         &self.language
     }
@@ -46,42 +249,268 @@ impl Extractor {
         path: &Path,
         parser: &mut Parser,
     ) -> Result<Option<ExtractedFile>> {
-        let source = fs::read(&path).context("could not read file")?;
+        let source = read_source(path)?;
 
         self.extract_from_text(Some(path), &source, parser)
     }
 
-    pub fn extract_from_text(
-        &self,
-        path: Option<&Path>,
-        source: &[u8],
-        parser: &mut Parser,
-    ) -> Result<Option<ExtractedFile>> {
+    /// Check whether the file parses without any ERROR or MISSING nodes.
+    pub fn parses_cleanly(&self, path: &Path, parser: &mut Parser) -> Result<bool> {
+        let source = read_source(path)?;
+
+        Ok(!self.parse(&source, parser)?.root_node().has_error())
+    }
+
+    pub fn query(&self) -> &Query {
+        &self.query
+    }
+
+    pub fn parse(&self, source: &[u8], parser: &mut Parser) -> Result<Tree> {
         parser
             .set_language(self.ts_language)
             .context("could not set language")?;
 
-        let tree = parser
-            .parse(&source, None)
+        parser
+            .parse(source, None)
             // note: this could be a timeout or cancellation, but we don't set
             // that so we know it's always a language error. Buuuut we also
             // always set the language above so if this happens we also know
             // it's an internal error.
-            .context(
-                "could not parse to a tree. This is an internal error and should be reported.",
-            )?;
+            .context("could not parse to a tree. This is an internal error and should be reported.")
+    }
 
+    pub fn extract_from_text(
+        &self,
+        path: Option<&Path>,
+        source: &[u8],
+        parser: &mut Parser,
+    ) -> Result<Option<ExtractedFile>> {
+        let source = if self.normalize_newlines {
+            normalize_newlines(source)
+        } else {
+            Cow::Borrowed(source)
+        };
+        let source = &*source;
+
+        if let Some(max_lines) = self.max_lines {
+            // counting newlines is way cheaper than parsing, so we can afford
+            // to do it up front.
+            let lines = count_lines(source);
+            if lines > max_lines {
+                if self.verbose {
+                    eprintln!(
+                        "skipping {}: {} lines is more than --max-lines {}",
+                        path.map(|p| p.display().to_string())
+                            .unwrap_or_else(|| String::from("NO FILE")),
+                        lines,
+                        max_lines
+                    );
+                }
+
+                return Ok(None);
+            }
+        }
+
+        let tree = self.parse(source, parser)?;
+
+        if self.list_errors {
+            return Ok(self.extracted_file(path, source, error_matches(tree.root_node(), source)?));
+        }
+
+        let mut extracted_matches = self.query_matches(&tree, source, Offset::default())?;
+
+        for embed in &self.embeds {
+            for node in self.captured_nodes_of_kind(&tree, source, &embed.node_kind) {
+                if self.ignore_parse_errors && inside_error(node) {
+                    continue;
+                }
+
+                let (content_start, content_end) = string_contents(&source[node.byte_range()]);
+                let base = Point::new(
+                    node.start_position().row,
+                    node.start_position().column + content_start,
+                );
+
+                let contents =
+                    &source[node.start_byte() + content_start..node.start_byte() + content_end];
+                let embedded = embed
+                    .extractor
+                    .parse(contents, parser)
+                    .and_then(|embedded_tree| {
+                        embed.extractor.query_matches(
+                            &embedded_tree,
+                            contents,
+                            Offset {
+                                bytes: node.start_byte() + content_start,
+                                // the embedded root stands in for the node
+                                depth: depth(node),
+                            },
+                        )
+                    })
+                    .with_context(|| {
+                        format!(
+                            "could not extract embedded {} from {}",
+                            embed.extractor.language, embed.node_kind
+                        )
+                    })?;
+
+                if !embedded.is_empty() {
+                    // match indexes start over in every embedded extraction,
+                    // so we shift them past the ones we already have to keep
+                    // them unique in this file.
+                    let first_match_index = extracted_matches
+                        .iter()
+                        .map(|extraction| extraction.match_index + 1)
+                        .max()
+                        .unwrap_or(0);
+
+                    extracted_matches.extend(embedded.into_iter().map(|extraction| {
+                        let start_byte = node.start_byte() + content_start + extraction.start_byte;
+
+                        ExtractedMatch {
+                            start: offset_point(base, extraction.start),
+                            end: offset_point(base, extraction.end),
+                            // the embedded extractor only saw the inside of
+                            // the node, but we want the line in this file.
+                            indent: extraction
+                                .indent
+                                .as_ref()
+                                .map(|_| line_indent(source, start_byte)),
+                            line: extraction
+                                .line
+                                .as_ref()
+                                .map(|_| line_containing(source, start_byte)),
+                            match_index: first_match_index + extraction.match_index,
+                            start_byte,
+                            end_byte: node.start_byte() + content_start + extraction.end_byte,
+                            ..extraction
+                        }
+                    }));
+                }
+            }
+        }
+
+        if let Some(at) = self.at {
+            extracted_matches.retain(|extraction| extraction.start <= at && at < extraction.end);
+
+            if let Some(smallest) = extracted_matches
+                .iter()
+                .map(|extraction| extraction.end_byte - extraction.start_byte)
+                .min()
+            {
+                extracted_matches
+                    .retain(|extraction| extraction.end_byte - extraction.start_byte == smallest);
+            }
+        }
+
+        if let Some(max) = self.max_count {
+            if extracted_matches.len() > max {
+                // captures (and embedded matches) don't always come out in
+                // source order, so we have to put them in order before we can
+                // tell which ones come first.
+                extracted_matches.sort_by_key(|extraction| extraction.start_byte);
+                extracted_matches.truncate(max);
+            }
+        }
+
+        let mut extracted_file = self.extracted_file(path, source, extracted_matches);
+
+        if self.context_before > 0 || self.context_after > 0 {
+            if let Some(file) = &mut extracted_file {
+                let mut rows = HashSet::new();
+                for extraction in &file.matches {
+                    rows.extend(
+                        extraction.start.row.saturating_sub(self.context_before)
+                            ..=extraction.end.row + self.context_after,
+                    );
+                }
+
+                file.context_lines = source
+                    .split(|byte| *byte == b'\n')
+                    .enumerate()
+                    .filter(|(row, _)| rows.contains(row))
+                    .map(|(row, line)| {
+                        let line = line.strip_suffix(b"\r").unwrap_or(line);
+                        (row, String::from_utf8_lossy(line).into_owned())
+                    })
+                    .collect();
+            }
+        }
+
+        if self.annotate_tree {
+            if let Some(file) = &mut extracted_file {
+                let matched: HashSet<usize> = QueryCursor::new()
+                    .captures(&self.query, tree.root_node(), source)
+                    .map(|(query_match, index)| query_match.captures[index])
+                    .filter(|capture| !self.ignores.contains(&(capture.index as usize)))
+                    .map(|capture| capture.node.id())
+                    .collect();
+
+                let mut annotated = String::new();
+                annotate_tree(tree.root_node(), None, &matched, 0, &mut annotated);
+                file.annotated_tree = Some(annotated);
+            }
+        }
+
+        Ok(extracted_file)
+    }
+
+    /// Run our query over a parsed tree and turn the captures we want into
+    /// matches. `offset` says where `source` is in the file we're searching,
+    /// so filters on positions work the same for embedded code.
+    fn query_matches<'query>(
+        &'query self,
+        tree: &Tree,
+        source: &[u8],
+        offset: Offset,
+    ) -> Result<Vec<ExtractedMatch<'query>>> {
         let mut cursor = QueryCursor::new();
+        if let Some(byte_range) = &self.byte_range {
+            cursor.set_byte_range(
+                byte_range.start.saturating_sub(offset.bytes)
+                    ..byte_range.end.saturating_sub(offset.bytes),
+            );
+        }
 
-        let extracted_matches = cursor
+        cursor
             .matches(&self.query, tree.root_node(), source)
-            .flat_map(|query_match| query_match.captures)
+            .enumerate()
+            .flat_map(|(match_index, query_match)| {
+                let pattern_index = query_match.pattern_index;
+                query_match
+                    .captures
+                    .iter()
+                    .enumerate()
+                    .filter(move |(position, _)| match self.capture_index {
+                        Some(wanted) => *position == wanted,
+                        None => true,
+                    })
+                    .map(move |(_, capture)| (match_index, pattern_index, capture))
+            })
             // note: the casts here could potentially break if run on a 16-bit
             // microcontroller. I don't think this is a huge problem, though,
             // since even the gnarliest queries I've written have something on
             // the order of 20 matches. Nowhere close to 2^16!
-            .filter(|capture| !self.ignores.contains(&(capture.index as usize)))
-            .map(|capture| {
+            .filter(|(_, _, capture)| !self.ignores.contains(&(capture.index as usize)))
+            .filter(|(_, _, capture)| match self.only_capture {
+                Some(only) => capture.index == only,
+                None => true,
+            })
+            // the cursor gives us everything that overlaps the range, but we
+            // only want nodes that start in it, so that searching adjacent
+            // ranges never reports the same node twice.
+            .filter(|(_, _, capture)| match &self.byte_range {
+                Some(byte_range) => {
+                    byte_range.contains(&(offset.bytes + capture.node.start_byte()))
+                }
+                None => true,
+            })
+            .filter(|(_, _, capture)| !(self.ignore_parse_errors && inside_error(capture.node)))
+            .filter(|(_, _, capture)| match self.max_node_depth {
+                Some(max) => offset.depth + depth(capture.node) <= max,
+                None => true,
+            })
+            .map(|(match_index, pattern_index, capture)| {
                 let name = &self.captures[capture.index as usize];
                 let node = capture.node;
                 let text = match node
@@ -93,143 +522,2052 @@ impl Extractor {
                     Err(problem) => return Err(problem),
                 };
 
+                let relative = self
+                    .relative_to
+                    .as_ref()
+                    .and_then(|kind| Relative::to_ancestor(node, kind));
+
+                let explanation = if self.explain {
+                    Some(Explanation {
+                        pattern_index,
+                        capture_index: capture.index,
+                    })
+                } else {
+                    None
+                };
+
+                let ancestors = if self.record_ancestors {
+                    ancestor_kinds(node)
+                } else {
+                    Vec::new()
+                };
+
+                let indent = if self.record_indents {
+                    Some(line_indent(source, node.start_byte()))
+                } else {
+                    None
+                };
+
+                let qualified_name = if self.qualified_names {
+                    qualified_name(node, self.language, source)
+                } else {
+                    None
+                };
+
+                let line = if self.record_lines {
+                    Some(line_containing(source, node.start_byte()))
+                } else {
+                    None
+                };
+
+                let highlighted = if self.highlight {
+                    Some(highlight(node, self.language, source))
+                } else {
+                    None
+                };
+
                 Ok(ExtractedMatch {
                     kind: node.kind(),
                     name,
                     text,
                     start: node.start_position(),
                     end: node.end_position(),
+                    relative,
+                    indent,
+                    qualified_name,
+                    explanation,
+                    ancestors,
+                    line,
+                    highlighted,
+                    match_index,
+                    start_byte: node.start_byte(),
+                    end_byte: node.end_byte(),
                 })
             })
-            .collect::<Result<Vec<ExtractedMatch>>>()?;
+            .collect()
+    }
+
+    /// The nodes of the given kind that our query captured (including ones
+    /// we don't report, like `@_sql`), each once and in document order.
+    fn captured_nodes_of_kind<'tree>(
+        &self,
+        tree: &'tree Tree,
+        source: &[u8],
+        kind: &str,
+    ) -> Vec<Node<'tree>> {
+        let mut seen = HashSet::new();
+
+        QueryCursor::new()
+            .captures(&self.query, tree.root_node(), source)
+            .map(|(query_match, index)| query_match.captures[index].node)
+            .filter(|node| node.kind() == kind && seen.insert(node.id()))
+            .collect()
+    }
 
-        if extracted_matches.is_empty() {
-            Ok(None)
+    fn extracted_file<'query>(
+        &'query self,
+        path: Option<&Path>,
+        source: &[u8],
+        matches: Vec<ExtractedMatch<'query>>,
+    ) -> Option<ExtractedFile<'query>> {
+        if matches.is_empty() {
+            None
         } else {
-            Ok(Some(ExtractedFile {
+            Some(ExtractedFile {
                 file: path.map(|p| p.to_owned()),
                 file_type: self.language.to_string(),
-                matches: extracted_matches,
-            }))
+                matches,
+                annotated_tree: None,
+                context_lines: BTreeMap::new(),
+                source_len: source.len(),
+            })
         }
     }
 }
 
-#[derive(Debug, Serialize, PartialEq, Eq, PartialOrd, Ord)]
-pub struct ExtractedFile<'query> {
-    file: Option<PathBuf>,
-    file_type: String,
-    matches: Vec<ExtractedMatch<'query>>,
+fn error_matches<'query>(root: Node, source: &[u8]) -> Result<Vec<ExtractedMatch<'query>>> {
+    let mut out = Vec::new();
+
+    if !root.has_error() {
+        return Ok(out);
+    }
+
+    for node in descendants(root) {
+        let name = if node.is_error() {
+            "error"
+        } else if node.is_missing() {
+            "missing"
+        } else {
+            continue;
+        };
+
+        out.push(ExtractedMatch {
+            kind: node.kind(),
+            name,
+            text: node
+                .utf8_text(source)
+                .context("could not extract text from error")?
+                .to_string(),
+            start: node.start_position(),
+            end: node.end_position(),
+            relative: None,
+            indent: None,
+            qualified_name: None,
+            explanation: None,
+            ancestors: Vec::new(),
+            line: None,
+            highlighted: None,
+            match_index: 0,
+            start_byte: node.start_byte(),
+            end_byte: node.end_byte(),
+        })
+    }
+
+    Ok(out)
 }
 
-impl<'query> Display for ExtractedFile<'query> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        // TODO: is there a better way to do this unwrapping? This implementation
-        // turns non-UTF-8 paths into "NON-UTF8 FILENAME". I don't know exactly
-        // what circumstances that could happen in... maybe we should just wait
-        // for bug reports?
-        let filename = self
-            .file
-            .as_ref()
-            .map(|f| f.to_str().unwrap_or("NON-UTF8 FILENAME"))
-            .unwrap_or("NO FILE");
+/// The whitespace at the start of the line containing `byte`. We use the
+/// actual characters (rather than a column count) so files indented with
+/// tabs stay indented with tabs.
+pub fn line_indent(source: &[u8], byte: usize) -> String {
+    let line_start = source[..byte]
+        .iter()
+        .rposition(|c| *c == b'\n')
+        .map(|newline| newline + 1)
+        .unwrap_or(0);
 
-        for extraction in &self.matches {
-            writeln!(
-                f,
-                "{}:{}:{}:{}:{}",
-                filename,
-                extraction.start.row + 1,
-                extraction.start.column + 1,
-                extraction.name,
-                extraction.text
-            )?
+    source[line_start..byte]
+        .iter()
+        .take_while(|c| **c == b' ' || **c == b'\t')
+        .map(|c| *c as char)
+        .collect()
+}
+
+/// Render the source of `node` as HTML, wrapping comments, strings, numbers,
+/// and keywords in `<span>`s with a class for each so a stylesheet can color
+/// them.
+fn highlight(node: Node, language: Language, source: &[u8]) -> String {
+    let mut out = String::new();
+    let mut at = node.start_byte();
+
+    highlight_node(node, language, source, &mut at, &mut out);
+    out.push_str(&html_escape(&String::from_utf8_lossy(
+        &source[at..node.end_byte()],
+    )));
+
+    out
+}
+
+/// Highlight `node` and everything in it, starting from `at` (everything
+/// before that is already in `out`.)
+fn highlight_node(node: Node, language: Language, source: &[u8], at: &mut usize, out: &mut String) {
+    let kind = node.kind();
+
+    let class = if language.comment_node_types().contains(&kind) {
+        Some("comment")
+    } else if language.string_node_types().contains(&kind) {
+        Some("string")
+    } else if node.child_count() > 0 {
+        None
+    } else if ["integer", "float", "number"]
+        .iter()
+        .any(|numeric| kind.contains(numeric))
+    {
+        Some("number")
+    } else if !node.is_named() && kind.chars().all(|c| c.is_alphabetic() || c == '_') {
+        Some("keyword")
+    } else {
+        None
+    };
+
+    match class {
+        Some(class) => {
+            out.push_str(&html_escape(&String::from_utf8_lossy(
+                &source[*at..node.start_byte()],
+            )));
+            out.push_str(&format!(
+                "<span class=\"{}\">{}</span>",
+                class,
+                html_escape(&String::from_utf8_lossy(
+                    &source[node.start_byte()..node.end_byte()]
+                ))
+            ));
+            *at = node.end_byte();
+        }
+        None => {
+            for child in node.children(&mut node.walk()) {
+                highlight_node(child, language, source, at, out);
+            }
         }
+    }
+}
 
-        Ok(())
+fn html_escape(text: &str) -> Cow<'_, str> {
+    if text.contains(&['&', '<', '>', '"'][..]) {
+        Cow::Owned(
+            text.replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+                .replace('"', "&quot;"),
+        )
+    } else {
+        Cow::Borrowed(text)
     }
 }
 
-#[derive(Debug, Serialize, PartialEq, Eq, PartialOrd, Ord)]
-pub struct ExtractedMatch<'query> {
-    kind: &'static str,
-    name: &'query str,
-    text: String,
-    #[serde(serialize_with = "serialize_point")]
-    start: Point,
-    #[serde(serialize_with = "serialize_point")]
-    end: Point,
+/// Files at least this big get memory-mapped instead of read into memory,
+/// so big generated files don't churn through allocations.
+const MMAP_THRESHOLD: u64 = 1024 * 1024;
+
+/// The contents of a file, either read into memory or mapped from disk.
+pub enum Source {
+    Read(Vec<u8>),
+    Mapped(Mmap),
 }
 
-fn serialize_point<S>(point: &Point, sz: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    let mut out = sz.serialize_struct("Point", 2)?;
-    out.serialize_field("row", &(point.row + 1))?;
-    out.serialize_field("column", &(point.column + 1))?;
-    out.end()
+impl Deref for Source {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Source::Read(bytes) => bytes,
+            Source::Mapped(map) => map,
+        }
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::language::Language;
-    use tree_sitter::Parser;
+/// Read a file, decompressing it if it's gzipped.
+pub fn read_source(path: &Path) -> Result<Source> {
+    let mut file = fs::File::open(path).context("could not open file")?;
 
-    #[test]
-    fn test_matches_are_extracted() {
-        let lang = Language::Elm;
-        let query = lang
-            .parse_query("(import_clause (upper_case_qid)@import)")
-            .unwrap();
-        let extractor = Extractor::new(lang, query);
+    if path.extension() == Some(OsStr::new("gz")) {
+        let mut source = Vec::new();
+        GzDecoder::new(file)
+            .read_to_end(&mut source)
+            .context("could not decompress file")?;
 
-        let extracted = extractor
-            .extract_from_text(None, b"import Html.Styled", &mut Parser::new())
-            // From Result<Option<ExtractedFile>>
-            .unwrap()
-            // From Option<ExtractedFile>
-            .unwrap();
+        return Ok(Source::Read(source));
+    }
 
-        assert_eq!(extracted.matches.len(), 1);
-        assert_eq!(extracted.matches[0].name, "import");
-        assert_eq!(extracted.matches[0].text, "Html.Styled");
+    let len = file
+        .metadata()
+        .context("could not read file metadata")?
+        .len();
+    if len >= MMAP_THRESHOLD {
+        // Safety: the map is only valid as long as nobody truncates the file
+        // while we're using it. If we can't map the file at all (say it's on
+        // a filesystem that doesn't support mapping) or its size changed
+        // between looking at it and mapping it, something is writing to it,
+        // so we read it like any other instead.
+        //
+        // That still leaves a window: if the file gets truncated after the
+        // second look but before we're done parsing, reading the missing
+        // part of the map kills the process with SIGBUS. That's the same
+        // risk other search tools take on for big files, and it's rare
+        // enough to be worth it.
+        if let Ok(map) = unsafe { Mmap::map(&file) } {
+            let unchanged = match file.metadata() {
+                Ok(metadata) => metadata.len() == len && map.len() as u64 == len,
+                Err(_) => false,
+            };
+
+            if unchanged {
+                return Ok(Source::Mapped(map));
+            }
+        }
     }
 
-    #[test]
-    fn test_underscore_names_are_ignored() {
-        let lang = Language::Elm;
-        let query = lang
-            .parse_query("(import_clause (upper_case_qid)@_import)")
-            .unwrap();
-        let extractor = Extractor::new(lang, query);
+    let mut source = Vec::with_capacity(len as usize);
+    file.read_to_end(&mut source)
+        .context("could not read file")?;
 
-        let extracted = extractor
-            .extract_from_text(None, b"import Html.Styled", &mut Parser::new())
-            // From Result<Option<ExtractedFile>>
-            .unwrap();
+    Ok(Source::Read(source))
+}
 
-        assert_eq!(extracted, None);
+/// The full line containing `byte`, without its line ending.
+fn line_containing(source: &[u8], byte: usize) -> String {
+    let start = source[..byte]
+        .iter()
+        .rposition(|c| *c == b'\n')
+        .map(|newline| newline + 1)
+        .unwrap_or(0);
+
+    let end = source[byte..]
+        .iter()
+        .position(|c| *c == b'\n')
+        .map(|newline| byte + newline)
+        .unwrap_or_else(|| source.len());
+
+    String::from_utf8_lossy(&source[start..end])
+        .trim_end_matches('\r')
+        .to_string()
+}
+
+/// The names of the containers (in the sense of
+/// `Language::qualified_name_containers`) this node is in, including itself,
+/// joined outermost first.
+fn qualified_name(node: Node, language: Language, source: &[u8]) -> Option<String> {
+    let containers = language.qualified_name_containers();
+    let mut parts = Vec::new();
+    let mut current = Some(node);
+
+    while let Some(node) = current {
+        if let Some((_, field)) = containers.iter().find(|(kind, _)| *kind == node.kind()) {
+            if let Some(name) = node
+                .child_by_field_name(field)
+                .and_then(|name| name.utf8_text(source).ok())
+            {
+                parts.push(name);
+            }
+        }
+
+        current = node.parent();
     }
 
-    #[test]
-    fn test_underscore_names_can_still_be_used_in_matchers() {
-        let lang = Language::JavaScript;
-        let query = lang
-            .parse_query("(call_expression (identifier)@_fn (arguments . (string)@import .) (#eq? @_fn require))")
-            .unwrap();
-        let extractor = Extractor::new(lang, query);
+    if parts.is_empty() {
+        return None;
+    }
 
-        let extracted = extractor
-            .extract_from_text(None, b"let foo = require(\"foo.js\")", &mut Parser::new())
-            // From Result<Option<ExtractedFile>>
-            .unwrap()
-            // From Option<ExtractedFile>
-            .unwrap();
+    parts.reverse();
+    Some(parts.join(language.qualified_name_separator()))
+}
 
-        assert_eq!(extracted.matches.len(), 1);
-        assert_eq!(extracted.matches[0].name, "import");
-        assert_eq!(extracted.matches[0].text, "\"foo.js\"");
+/// How many ancestors this node has.
+fn depth(node: Node) -> usize {
+    let mut depth = 0;
+    let mut current = node.parent();
+
+    while let Some(node) = current {
+        depth += 1;
+        current = node.parent();
+    }
+
+    depth
+}
+
+/// Is this node an ERROR node, or inside one?
+fn inside_error(node: Node) -> bool {
+    let mut current = Some(node);
+
+    while let Some(node) = current {
+        if node.is_error() {
+            return true;
+        }
+
+        current = node.parent();
+    }
+
+    false
+}
+
+/// Write an indented s-expression for the named nodes in the tree under
+/// `node`, marking the ones in `matched` with a `*`.
+fn annotate_tree(
+    node: Node,
+    field: Option<&str>,
+    matched: &HashSet<usize>,
+    depth: usize,
+    out: &mut String,
+) {
+    if !node.is_named() {
+        return;
+    }
+
+    if depth > 0 {
+        out.push('\n');
+    }
+
+    for _ in 0..depth {
+        out.push_str("  ");
+    }
+
+    if let Some(field) = field {
+        out.push_str(field);
+        out.push_str(": ");
+    }
+
+    if matched.contains(&node.id()) {
+        out.push('*');
+    }
+
+    out.push('(');
+    out.push_str(node.kind());
+
+    for i in 0..node.child_count() {
+        if let Some(child) = node.child(i) {
+            annotate_tree(
+                child,
+                node.field_name_for_child(i as u32),
+                matched,
+                depth + 1,
+                out,
+            );
+        }
+    }
+
+    out.push(')');
+}
+
+/// The kinds of all of this node's ancestors, starting at the root.
+fn ancestor_kinds(node: Node) -> Vec<&'static str> {
+    let mut out = Vec::new();
+
+    let mut ancestor = node.parent();
+    while let Some(parent) = ancestor {
+        out.push(parent.kind());
+        ancestor = parent.parent();
+    }
+
+    out.reverse();
+    out
+}
+
+fn normalize_newlines(source: &[u8]) -> Cow<'_, [u8]> {
+    if !source.contains(&b'\r') {
+        return Cow::Borrowed(source);
+    }
+
+    let mut out = Vec::with_capacity(source.len());
+    let mut bytes = source.iter().peekable();
+    while let Some(&byte) = bytes.next() {
+        if byte == b'\r' {
+            bytes.next_if_eq(&&b'\n');
+            out.push(b'\n');
+        } else {
+            out.push(byte);
+        }
+    }
+
+    Cow::Owned(out)
+}
+
+fn count_lines(source: &[u8]) -> usize {
+    let newlines = source.iter().filter(|c| **c == b'\n').count();
+
+    match source.last() {
+        Some(b'\n') | None => newlines,
+        Some(_) => newlines + 1,
+    }
+}
+
+/// All the nodes in the tree under `root` (including `root` itself) in
+/// document order.
+fn descendants(root: Node) -> Vec<Node> {
+    let mut out = Vec::new();
+    let mut cursor = root.walk();
+
+    loop {
+        out.push(cursor.node());
+
+        if cursor.goto_first_child() {
+            continue;
+        }
+
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                return out;
+            }
+        }
+    }
+}
+
+/// Find the start and end of the contents of a string literal by skipping any
+/// prefix (like Rust's `r#`) and the quotes themselves. If there aren't any
+/// quotes, we just use the whole text.
+fn string_contents(text: &[u8]) -> (usize, usize) {
+    let quote_start = match text
+        .iter()
+        .position(|c| *c == b'"' || *c == b'\'' || *c == b'`')
+    {
+        Some(pos) => pos,
+        None => return (0, text.len()),
+    };
+
+    if !text[..quote_start]
+        .iter()
+        .all(|c| c.is_ascii_alphanumeric() || *c == b'#')
+    {
+        return (0, text.len());
+    }
+
+    let quote = text[quote_start];
+    let mut quotes = text[quote_start..]
+        .iter()
+        .take(3)
+        .take_while(|c| **c == quote)
+        .count();
+
+    // `""` is an empty string, not the start of a triple-quoted one
+    if quotes == 2 {
+        quotes = 1;
+    }
+
+    let hashes = text[..quote_start].iter().filter(|c| **c == b'#').count();
+    let start = quote_start + quotes;
+    let end = text.len().saturating_sub(quotes + hashes).max(start);
+
+    (start, end)
+}
+
+/// Translate a point relative to some embedded text back into the coordinates
+/// of the text it was embedded in.
+fn offset_point(base: Point, point: Point) -> Point {
+    if point.row == 0 {
+        Point::new(base.row, base.column + point.column)
+    } else {
+        Point::new(base.row + point.row, point.column)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ExtractedFile<'query> {
+    file: Option<PathBuf>,
+    file_type: String,
+    matches: Vec<ExtractedMatch<'query>>,
+    #[serde(skip)]
+    annotated_tree: Option<String>,
+    #[serde(skip)]
+    context_lines: BTreeMap<usize, String>,
+    #[serde(skip)]
+    source_len: usize,
+}
+
+impl<'query> ExtractedFile<'query> {
+    pub fn filename(&self) -> &str {
+        // TODO: is there a better way to do this unwrapping? This implementation
+        // turns non-UTF-8 paths into "NON-UTF8 FILENAME". I don't know exactly
+        // what circumstances that could happen in... maybe we should just wait
+        // for bug reports?
+        self.file
+            .as_ref()
+            .map(|f| f.to_str().unwrap_or("NON-UTF8 FILENAME"))
+            .unwrap_or("NO FILE")
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    /// How many bytes of the source matches cover, counting bytes covered
+    /// by overlapping or nested matches only once, and how many bytes there
+    /// are in total.
+    pub fn covered_bytes(&self) -> (usize, usize) {
+        let mut spans: Vec<(usize, usize)> = self
+            .matches
+            .iter()
+            .map(|extraction| (extraction.start_byte, extraction.end_byte))
+            .collect();
+        spans.sort_unstable();
+
+        let mut covered = 0;
+        let mut covered_until = 0;
+        for (start, end) in spans {
+            let start = start.max(covered_until);
+            if end > start {
+                covered += end - start;
+                covered_until = end;
+            }
+        }
+
+        (covered, self.source_len)
+    }
+
+    /// Add the matches from another set of results for the same file,
+    /// keeping them in order and dropping any we already had.
+    pub fn merge(&mut self, other: ExtractedFile<'query>) {
+        self.matches.extend(other.matches);
+        // the derived order compares kinds and names first, which would
+        // scatter the matches through the file. Anything still tied falls
+        // back to it so duplicates end up next to each other for `dedup`.
+        self.matches.sort_by(|a, b| {
+            (a.start, a.end, a.kind, a.name)
+                .cmp(&(b.start, b.end, b.kind, b.name))
+                .then_with(|| a.cmp(b))
+        });
+        self.matches.dedup();
+    }
+
+    pub fn matches_len(&self) -> usize {
+        self.matches.len()
+    }
+
+    /// The text of every match for the named capture.
+    pub fn captured_texts<'file>(
+        &'file self,
+        name: &'file str,
+    ) -> impl Iterator<Item = &'file str> + 'file {
+        self.matches
+            .iter()
+            .filter(move |extraction| extraction.name == name)
+            .map(|extraction| extraction.text.as_str())
+    }
+
+    /// Pairs of the texts captured by `key` and `value` in the same match.
+    /// Matches missing either capture are skipped.
+    pub fn key_values<'file>(&'file self, key: &str, value: &str) -> Vec<(&'file str, &'file str)> {
+        let mut by_match: BTreeMap<usize, (Option<&str>, Option<&str>)> = BTreeMap::new();
+
+        for extraction in &self.matches {
+            let pair = by_match.entry(extraction.match_index).or_default();
+
+            if extraction.name == key && pair.0.is_none() {
+                pair.0 = Some(&extraction.text)
+            } else if extraction.name == value && pair.1.is_none() {
+                pair.1 = Some(&extraction.text)
+            }
+        }
+
+        by_match
+            .into_values()
+            .filter_map(|pair| match pair {
+                (Some(key), Some(value)) => Some((key, value)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn reverse_matches(&mut self) {
+        self.matches.reverse()
+    }
+
+    /// Keep only the matches whose position in this file passes `keep`.
+    pub fn retain_matches(&mut self, mut keep: impl FnMut(usize) -> bool) {
+        let mut index = 0;
+        self.matches.retain(|_| {
+            index += 1;
+            keep(index - 1)
+        })
+    }
+
+    /// Each match's ancestry as a flamegraph-style stack, like
+    /// `source_file;function_item;identifier`. This is only interesting if the
+    /// extractor was recording ancestors.
+    pub fn folded_stacks(&self) -> impl Iterator<Item = String> + '_ {
+        self.matches.iter().map(|extraction| {
+            let mut stack = extraction.ancestors.join(";");
+            if !stack.is_empty() {
+                stack.push(';');
+            }
+            stack.push_str(extraction.kind);
+
+            stack
+        })
+    }
+
+    /// Tags-file entries (`name<TAB>file<TAB>/^line$/;"`) for every match
+    /// captured as `@name`. This is only interesting if the extractor was
+    /// recording lines.
+    pub fn ctags(&self) -> impl Iterator<Item = String> + '_ {
+        let filename = self.filename();
+
+        self.matches
+            .iter()
+            .filter(|extraction| extraction.name == "name")
+            .map(move |extraction| {
+                format!(
+                    "{}\t{}\t/^{}$/;\"",
+                    extraction.text,
+                    filename,
+                    extraction
+                        .line
+                        .as_deref()
+                        .unwrap_or("")
+                        .replace('\\', "\\\\")
+                        .replace('/', "\\/")
+                )
+            })
+    }
+
+    /// SARIF `result` objects for every match, using the capture name as the
+    /// rule ID. Positions are one-based, and the end column points just past
+    /// the end of the match, which is what SARIF expects.
+    pub fn sarif_results(&self) -> impl Iterator<Item = serde_json::Value> + '_ {
+        let uri = self.filename().replace('\\', "/");
+
+        self.matches.iter().map(move |extraction| {
+            serde_json::json!({
+                "ruleId": extraction.name,
+                "level": "note",
+                "message": {
+                    "text": extraction.text,
+                },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": {
+                            "uri": uri,
+                        },
+                        "region": {
+                            "startLine": extraction.start.row + 1,
+                            "startColumn": extraction.start.column + 1,
+                            "endLine": extraction.end.row + 1,
+                            "endColumn": extraction.end.column + 1,
+                        },
+                    },
+                }],
+            })
+        })
+    }
+
+    /// The capture name of every match, in order.
+    pub fn capture_names(&self) -> impl Iterator<Item = &'query str> + '_ {
+        self.matches.iter().map(|extraction| extraction.name)
+    }
+
+    /// The distinct kinds of the nodes we matched in this file, sorted.
+    pub fn kinds(&self) -> BTreeSet<&'static str> {
+        self.matches
+            .iter()
+            .map(|extraction| extraction.kind)
+            .collect()
+    }
+
+    pub fn line_numbered(&self) -> LineNumbered<'_, 'query> {
+        LineNumbered(self)
+    }
+
+    pub fn colored(&self) -> Colored<'_, 'query> {
+        Colored(self, false)
+    }
+
+    pub fn colored_line_numbered(&self) -> Colored<'_, 'query> {
+        Colored(self, true)
+    }
+
+    pub fn in_context(&self) -> InContext<'_, 'query> {
+        InContext(self)
+    }
+
+    pub fn collapsed_lines(&self) -> CollapsedLines<'_, 'query> {
+        CollapsedLines(self)
+    }
+
+    pub fn gnu(&self) -> Gnu<'_, 'query> {
+        Gnu(self)
+    }
+
+    pub fn spans(&self) -> Spans<'_, 'query> {
+        Spans(self)
+    }
+
+    pub fn annotated_tree(&self) -> AnnotatedTree<'_, 'query> {
+        AnnotatedTree(self)
+    }
+
+    pub fn csv(&self) -> Csv<'_, 'query> {
+        Csv(self)
+    }
+
+    fn write_lines(&self, f: &mut fmt::Formatter, line_numbers: bool) -> fmt::Result {
+        let filename = self.filename();
+
+        for extraction in &self.matches {
+            if line_numbers {
+                write!(f, "{}:", extraction.start.row + 1)?;
+            }
+
+            write!(f, "{}:{}:", filename, extraction.start.row + 1)?;
+            write_column_name_and_text(f, extraction)?;
+            writeln!(f)?
+        }
+
+        Ok(())
+    }
+}
+
+/// The part of a line of lines output after the filename and row.
+fn write_column_name_and_text(f: &mut fmt::Formatter, extraction: &ExtractedMatch) -> fmt::Result {
+    write!(
+        f,
+        "{}:{}:{}",
+        extraction.start.column + 1,
+        extraction.name,
+        extraction.text
+    )?;
+
+    if let Some(explanation) = &extraction.explanation {
+        write!(
+            f,
+            " (pattern {}, capture {})",
+            explanation.pattern_index, explanation.capture_index
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Displays lines output with ANSI colors, like `grep --color`: the path in
+/// magenta, the position in green, and the matched text in bold red. When
+/// the flag is set, lines start with the line number in green too.
+pub struct Colored<'file, 'query>(&'file ExtractedFile<'query>, bool);
+
+impl<'file, 'query> Display for Colored<'file, 'query> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let filename = self.0.filename();
+
+        for extraction in &self.0.matches {
+            if self.1 {
+                write!(f, "\x1b[32m{}\x1b[0m:", extraction.start.row + 1)?;
+            }
+
+            write!(
+                f,
+                "\x1b[35m{}\x1b[0m:\x1b[32m{}\x1b[0m:\x1b[32m{}\x1b[0m:{}:",
+                filename,
+                extraction.start.row + 1,
+                extraction.start.column + 1,
+                extraction.name,
+            )?;
+
+            // color each line separately so the color doesn't leak into
+            // anything a terminal or pager puts at the start of a line.
+            for (index, line) in extraction.text.split('\n').enumerate() {
+                if index > 0 {
+                    writeln!(f)?
+                }
+
+                if !line.is_empty() {
+                    write!(f, "\x1b[1;31m{}\x1b[0m", line)?
+                }
+            }
+
+            if let Some(explanation) = &extraction.explanation {
+                write!(
+                    f,
+                    " (pattern {}, capture {})",
+                    explanation.pattern_index, explanation.capture_index
+                )?;
+            }
+
+            writeln!(f)?
+        }
+
+        Ok(())
+    }
+}
+
+/// Displays lines output with the context lines the extractor kept around
+/// each match, like `grep -C`. Context lines look like `file-row-text`, and we
+/// separate windows of context that don't touch with `--`.
+pub struct InContext<'file, 'query>(&'file ExtractedFile<'query>);
+
+impl<'file, 'query> Display for InContext<'file, 'query> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let file = self.0;
+        let filename = file.filename();
+
+        let mut starting_on: BTreeMap<usize, Vec<&ExtractedMatch>> = BTreeMap::new();
+        let mut inside_matches = HashSet::new();
+        for extraction in &file.matches {
+            starting_on
+                .entry(extraction.start.row)
+                .or_default()
+                .push(extraction);
+
+            // the text of a multi-line match already shows these lines
+            inside_matches.extend(extraction.start.row + 1..=extraction.end.row);
+        }
+
+        let mut previous_row = None;
+        for (row, line) in &file.context_lines {
+            match previous_row {
+                Some(previous) if previous + 1 != *row => writeln!(f, "--")?,
+                _ => (),
+            }
+            previous_row = Some(*row);
+
+            if let Some(extractions) = starting_on.get(row) {
+                for extraction in extractions {
+                    write!(f, "{}:{}:", filename, row + 1)?;
+                    write_column_name_and_text(f, extraction)?;
+                    writeln!(f)?
+                }
+            } else if !inside_matches.contains(row) {
+                writeln!(f, "{}-{}-{}", filename, row + 1, line)?
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Displays lines output, but with all the matches starting on the same row
+/// on one line, separated by ` | `.
+pub struct CollapsedLines<'file, 'query>(&'file ExtractedFile<'query>);
+
+impl<'file, 'query> Display for CollapsedLines<'file, 'query> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let filename = self.0.filename();
+
+        let mut rows: BTreeMap<usize, Vec<&ExtractedMatch>> = BTreeMap::new();
+        for extraction in &self.0.matches {
+            rows.entry(extraction.start.row)
+                .or_default()
+                .push(extraction);
+        }
+
+        for (row, extractions) in rows {
+            write!(f, "{}:{}:", filename, row + 1)?;
+
+            for (i, extraction) in extractions.into_iter().enumerate() {
+                if i > 0 {
+                    write!(f, " | ")?;
+                }
+
+                write_column_name_and_text(f, extraction)?;
+            }
+
+            writeln!(f)?
+        }
+
+        Ok(())
+    }
+}
+
+impl<'query> Display for ExtractedFile<'query> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_lines(f, false)
+    }
+}
+
+/// Displays lines output with each line starting with the line number of
+/// its match, like `grep -n`.
+pub struct LineNumbered<'file, 'query>(&'file ExtractedFile<'query>);
+
+impl<'file, 'query> Display for LineNumbered<'file, 'query> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.write_lines(f, true)
+    }
+}
+
+/// Displays matches in the GNU `file:line:col: message` style that editors
+/// and generic error parsers know how to jump to.
+pub struct Gnu<'file, 'query>(&'file ExtractedFile<'query>);
+
+impl<'file, 'query> Display for Gnu<'file, 'query> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let filename = self.0.filename();
+
+        for extraction in &self.0.matches {
+            // error parsers expect one message per line, so we only show the
+            // first line of multi-line matches.
+            writeln!(
+                f,
+                "{}:{}:{}: [{}] {}",
+                filename,
+                extraction.start.row + 1,
+                extraction.start.column + 1,
+                extraction.kind,
+                extraction.text.lines().next().unwrap_or("")
+            )?
+        }
+
+        Ok(())
+    }
+}
+
+/// The header row for CSV output, naming the columns `Csv` writes.
+pub const CSV_HEADER: &str = "path,row,column,end_row,end_column,match_text";
+
+/// Displays matches as CSV rows. Positions are the same one-based ones we use
+/// in JSON output.
+pub struct Csv<'file, 'query>(&'file ExtractedFile<'query>);
+
+impl<'file, 'query> Display for Csv<'file, 'query> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let filename = csv_field(self.0.filename());
+
+        for extraction in &self.0.matches {
+            writeln!(
+                f,
+                "{},{},{},{},{},{}",
+                filename,
+                extraction.start.row + 1,
+                extraction.start.column + 1,
+                extraction.end.row + 1,
+                extraction.end.column + 1,
+                csv_field(&extraction.text),
+            )?
+        }
+
+        Ok(())
+    }
+}
+
+/// Displays a self-contained HTML page for sharing matches with people who'd
+/// rather not run tree-grepper themselves. There's a list of files at the top,
+/// and every file and match has an anchor to link to.
+pub struct HtmlPage<'files, 'query>(pub &'files [ExtractedFile<'query>]);
+
+const HTML_STYLE: &str = "body { font-family: sans-serif; margin: 2em; }
+pre { background: #f6f8fa; padding: 0.5em; overflow-x: auto; }
+.capture { color: #6a737d; }
+.comment { color: #6a737d; font-style: italic; }
+.string { color: #032f62; }
+.number { color: #005cc5; }
+.keyword { color: #d73a49; font-weight: bold; }";
+
+impl<'files, 'query> Display for HtmlPage<'files, 'query> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "<!DOCTYPE html>")?;
+        writeln!(f, "<html>")?;
+        writeln!(f, "<head>")?;
+        writeln!(f, "<meta charset=\"utf-8\">")?;
+        writeln!(f, "<title>tree-grepper matches</title>")?;
+        writeln!(f, "<style>\n{}\n</style>", HTML_STYLE)?;
+        writeln!(f, "</head>")?;
+        writeln!(f, "<body>")?;
+
+        writeln!(f, "<ul>")?;
+        for (file_index, file) in self.0.iter().enumerate() {
+            writeln!(
+                f,
+                "<li><a href=\"#file-{}\">{}</a> ({})</li>",
+                file_index,
+                html_escape(file.filename()),
+                file.matches.len()
+            )?;
+        }
+        writeln!(f, "</ul>")?;
+
+        for (file_index, file) in self.0.iter().enumerate() {
+            let filename = html_escape(file.filename());
+
+            writeln!(f, "<section id=\"file-{}\">", file_index)?;
+            writeln!(
+                f,
+                "<h2><a href=\"#file-{}\">{}</a></h2>",
+                file_index, filename
+            )?;
+
+            for (match_index, extraction) in file.matches.iter().enumerate() {
+                writeln!(
+                    f,
+                    "<h3 id=\"file-{}-{}\"><a href=\"#file-{}-{}\">{}:{}:{}</a> <span class=\"capture\">@{}</span></h3>",
+                    file_index,
+                    match_index,
+                    file_index,
+                    match_index,
+                    filename,
+                    extraction.start.row + 1,
+                    extraction.start.column + 1,
+                    html_escape(extraction.name),
+                )?;
+
+                writeln!(
+                    f,
+                    "<pre><code>{}</code></pre>",
+                    match &extraction.highlighted {
+                        Some(highlighted) => Cow::Borrowed(highlighted.as_str()),
+                        None => html_escape(&extraction.text),
+                    }
+                )?;
+            }
+
+            writeln!(f, "</section>")?;
+        }
+
+        writeln!(f, "</body>")?;
+        writeln!(f, "</html>")
+    }
+}
+
+/// Quote a CSV field if it needs it, doubling any quotes inside.
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// Displays matches as `file start_byte length`, which is all you need to
+/// pull them back out with `dd` or similar.
+pub struct Spans<'file, 'query>(&'file ExtractedFile<'query>);
+
+impl<'file, 'query> Display for Spans<'file, 'query> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let filename = self.0.filename();
+
+        for extraction in &self.0.matches {
+            writeln!(
+                f,
+                "{} {} {}",
+                filename,
+                extraction.start_byte,
+                extraction.end_byte - extraction.start_byte
+            )?
+        }
+
+        Ok(())
+    }
+}
+
+/// Displays the file's whole tree with matched nodes marked. This is only
+/// interesting if the extractor was annotating trees.
+pub struct AnnotatedTree<'file, 'query>(&'file ExtractedFile<'query>);
+
+impl<'file, 'query> Display for AnnotatedTree<'file, 'query> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.0.filename())?;
+
+        if let Some(tree) = &self.0.annotated_tree {
+            writeln!(f, "{}", tree)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ExtractedMatch<'query> {
+    #[serde(deserialize_with = "deserialize_leaked")]
+    kind: LeakedStr<'static>,
+    #[serde(deserialize_with = "deserialize_leaked")]
+    name: LeakedStr<'query>,
+    text: String,
+    #[serde(
+        serialize_with = "serialize_point",
+        deserialize_with = "deserialize_point"
+    )]
+    start: Point,
+    #[serde(
+        serialize_with = "serialize_point",
+        deserialize_with = "deserialize_point"
+    )]
+    end: Point,
+    // results saved before we had these won't have them, so `--merge` has
+    // to make do without.
+    #[serde(default)]
+    start_byte: usize,
+    #[serde(default)]
+    end_byte: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    relative: Option<Relative>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    indent: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    qualified_name: Option<String>,
+    #[serde(flatten)]
+    explanation: Option<Explanation>,
+    #[serde(skip)]
+    ancestors: Vec<&'static str>,
+    #[serde(skip)]
+    line: Option<String>,
+    #[serde(skip)]
+    highlighted: Option<String>,
+    #[serde(skip)]
+    match_index: usize,
+}
+
+/// Which part of the query produced a match, for debugging complex queries.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Explanation {
+    pattern_index: usize,
+    capture_index: u32,
+}
+
+/// A match's position relative to the start of one of its ancestors. Unlike
+/// the other positions we report, these are zero-based offsets: a match
+/// starting right where the ancestor starts is at row 0, column 0.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Relative {
+    #[serde(deserialize_with = "deserialize_leaked")]
+    ancestor: LeakedStr<'static>,
+    #[serde(
+        serialize_with = "serialize_offset",
+        deserialize_with = "deserialize_offset"
+    )]
+    start: Point,
+    #[serde(
+        serialize_with = "serialize_offset",
+        deserialize_with = "deserialize_offset"
+    )]
+    end: Point,
+}
+
+impl Relative {
+    fn to_ancestor(node: Node, kind: &str) -> Option<Relative> {
+        let mut ancestor = node.parent();
+        while let Some(candidate) = ancestor {
+            if candidate.kind() == kind {
+                let base = candidate.start_position();
+
+                return Some(Relative {
+                    ancestor: candidate.kind(),
+                    start: relative_point(base, node.start_position()),
+                    end: relative_point(base, node.end_position()),
+                });
+            }
+
+            ancestor = candidate.parent();
+        }
+
+        None
+    }
+}
+
+/// The opposite of `offset_point`: where `point` is if `base` is the origin.
+fn relative_point(base: Point, point: Point) -> Point {
+    if point.row == base.row {
+        Point::new(0, point.column - base.column)
+    } else {
+        Point::new(point.row - base.row, point.column)
+    }
+}
+
+fn serialize_offset<S>(point: &Point, sz: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let mut out = sz.serialize_struct("Point", 2)?;
+    out.serialize_field("row", &point.row)?;
+    out.serialize_field("column", &point.column)?;
+    out.end()
+}
+
+fn serialize_point<S>(point: &Point, sz: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let mut out = sz.serialize_struct("Point", 2)?;
+    out.serialize_field("row", &(point.row + 1))?;
+    out.serialize_field("column", &(point.column + 1))?;
+    out.end()
+}
+
+/// The row and column fields of a serialized `Point`, for reading them back.
+#[derive(Deserialize)]
+struct RawPoint {
+    row: usize,
+    column: usize,
+}
+
+fn deserialize_offset<'de, D>(dz: D) -> Result<Point, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw = RawPoint::deserialize(dz)?;
+    Ok(Point::new(raw.row, raw.column))
+}
+
+fn deserialize_point<'de, D>(dz: D) -> Result<Point, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw = RawPoint::deserialize(dz)?;
+    if raw.row == 0 || raw.column == 0 {
+        return Err(serde::de::Error::custom(
+            "rows and columns start at 1, but got 0",
+        ));
+    }
+
+    Ok(Point::new(raw.row - 1, raw.column - 1))
+}
+
+/// Node kinds and capture names usually point into a grammar or query, which
+/// we don't have when reading results back in (for `--merge`.) We only read
+/// results once per run, right before exiting, so we leak them instead of
+/// making every match own its strings.
+///
+/// serde insists on borrowing any field it sees spelled `&str` from the
+/// input, even with `deserialize_with`, so fields using this have to be
+/// spelled as `LeakedStr` instead.
+type LeakedStr<'a> = &'a str;
+
+fn deserialize_leaked<'de, 'a, D>(dz: D) -> Result<&'a str, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let owned = String::deserialize(dz)?;
+    Ok(Box::leak(owned.into_boxed_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::language::Language;
+    use crate::temp_dir::TempDir;
+    use tree_sitter::Parser;
+
+    #[test]
+    fn test_matches_are_extracted() {
+        let lang = Language::Elm;
+        let query = lang
+            .parse_query("(import_clause (upper_case_qid)@import)")
+            .unwrap();
+        let extractor = Extractor::new(lang, query);
+
+        let extracted = extractor
+            .extract_from_text(None, b"import Html.Styled", &mut Parser::new())
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        assert_eq!(extracted.matches.len(), 1);
+        assert_eq!(extracted.matches[0].name, "import");
+        assert_eq!(extracted.matches[0].text, "Html.Styled");
+    }
+
+    #[test]
+    fn test_underscore_names_are_ignored() {
+        let lang = Language::Elm;
+        let query = lang
+            .parse_query("(import_clause (upper_case_qid)@_import)")
+            .unwrap();
+        let extractor = Extractor::new(lang, query);
+
+        let extracted = extractor
+            .extract_from_text(None, b"import Html.Styled", &mut Parser::new())
+            // From Result<Option<ExtractedFile>>
+            .unwrap();
+
+        assert_eq!(extracted, None);
+    }
+
+    #[test]
+    fn test_underscore_names_can_still_be_used_in_matchers() {
+        let lang = Language::JavaScript;
+        let query = lang
+            .parse_query("(call_expression (identifier)@_fn (arguments . (string)@import .) (#eq? @_fn require))")
+            .unwrap();
+        let extractor = Extractor::new(lang, query);
+
+        let extracted = extractor
+            .extract_from_text(None, b"let foo = require(\"foo.js\")", &mut Parser::new())
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        assert_eq!(extracted.matches.len(), 1);
+        assert_eq!(extracted.matches[0].name, "import");
+        assert_eq!(extracted.matches[0].text, "\"foo.js\"");
+    }
+
+    #[test]
+    fn test_embedded_matches_are_extracted() {
+        let mut extractor = Extractor::new(
+            Language::Rust,
+            Language::Rust
+                .parse_query("(string_literal)@_string")
+                .unwrap(),
+        );
+        extractor.embed(
+            String::from("string_literal"),
+            Extractor::new(
+                Language::JavaScript,
+                Language::JavaScript
+                    .parse_query("(call_expression (identifier)@fn)")
+                    .unwrap(),
+            ),
+        );
+
+        let extracted = extractor
+            .extract_from_text(
+                None,
+                b"fn main() {\n    let script = \"alert(document.cookie)\";\n}",
+                &mut Parser::new(),
+            )
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        assert_eq!(extracted.matches.len(), 1);
+        assert_eq!(extracted.matches[0].name, "fn");
+        assert_eq!(extracted.matches[0].text, "alert");
+        assert_eq!(extracted.matches[0].start, Point::new(1, 18));
+        assert_eq!(extracted.matches[0].end, Point::new(1, 23));
+    }
+
+    #[test]
+    fn test_comments_query_finds_todos() {
+        let lang = Language::Rust;
+        let query = lang
+            .parse_query(&lang.comments_query(Some("TODO")))
+            .unwrap();
+        let extractor = Extractor::new(lang, query);
+
+        let extracted = extractor
+            .extract_from_text(
+                None,
+                b"// TODO: fix\nfn main() {\n    // just a comment\n    /* TODO: this too */\n}\n",
+                &mut Parser::new(),
+            )
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        assert_eq!(extracted.matches.len(), 2);
+        assert_eq!(extracted.matches[0].name, "comment");
+        assert_eq!(extracted.matches[0].text, "// TODO: fix");
+        assert_eq!(extracted.matches[1].text, "/* TODO: this too */");
+    }
+
+    #[test]
+    fn test_explain() {
+        let lang = Language::Rust;
+        let query = lang
+            .parse_query(
+                "(function_item name: (identifier)@name body: (_)@body)
+                 [(integer_literal) (string_literal)]@literal",
+            )
+            .unwrap();
+        let mut extractor = Extractor::new(lang, query);
+        extractor.set_explain(true);
+
+        let extracted = extractor
+            .extract_from_text(None, b"fn main() { 1; }", &mut Parser::new())
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        let explained: Vec<(&str, usize, u32)> = extracted
+            .matches
+            .iter()
+            .map(|extraction| {
+                let explanation = extraction.explanation.as_ref().unwrap();
+                (
+                    extraction.name,
+                    explanation.pattern_index,
+                    explanation.capture_index,
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            explained,
+            vec![("name", 0, 0), ("body", 0, 1), ("literal", 1, 2)]
+        );
+        assert_eq!(
+            extracted.to_string().lines().next(),
+            Some("NO FILE:1:4:name:main (pattern 0, capture 0)")
+        );
+    }
+
+    #[test]
+    fn test_relative_to_ancestor() {
+        let lang = Language::Rust;
+        let query = lang
+            .parse_query("(let_declaration pattern: (identifier)@name)")
+            .unwrap();
+        let mut extractor = Extractor::new(lang, query);
+        extractor.set_relative_to(Some(String::from("function_item")));
+
+        let extracted = extractor
+            .extract_from_text(
+                None,
+                b"const X: u8 = 1;\n\n  fn main() { let a = 1;\n    let bb = 2;\n}\n",
+                &mut Parser::new(),
+            )
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        assert_eq!(extracted.matches.len(), 2);
+        assert_eq!(
+            extracted.matches[0].relative,
+            Some(Relative {
+                ancestor: "function_item",
+                start: Point::new(0, 16),
+                end: Point::new(0, 17),
+            })
+        );
+        assert_eq!(
+            extracted.matches[1].relative,
+            Some(Relative {
+                ancestor: "function_item",
+                start: Point::new(1, 8),
+                end: Point::new(1, 10),
+            })
+        );
+    }
+
+    #[test]
+    fn test_relative_to_missing_ancestor() {
+        let lang = Language::Rust;
+        let query = lang
+            .parse_query("(const_item name: (identifier)@name)")
+            .unwrap();
+        let mut extractor = Extractor::new(lang, query);
+        extractor.set_relative_to(Some(String::from("function_item")));
+
+        let extracted = extractor
+            .extract_from_text(None, b"const X: u8 = 1;", &mut Parser::new())
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        assert_eq!(extracted.matches[0].relative, None);
+    }
+
+    #[test]
+    fn test_max_lines_skips_long_sources() {
+        let lang = Language::Elm;
+        let query = lang.parse_query("(import_clause)@import").unwrap();
+        let mut extractor = Extractor::new(lang, query);
+        extractor.set_max_lines(Some(2));
+
+        let source = b"import Html\nimport Html.Attributes\nimport Html.Events\n";
+
+        let extracted = extractor
+            .extract_from_text(None, source, &mut Parser::new())
+            // From Result<Option<ExtractedFile>>
+            .unwrap();
+
+        assert_eq!(extracted, None);
+
+        extractor.set_max_lines(Some(3));
+
+        let extracted = extractor
+            .extract_from_text(None, source, &mut Parser::new())
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        assert_eq!(extracted.matches.len(), 3);
+    }
+
+    #[test]
+    fn test_ignore_parse_errors() {
+        let lang = Language::Rust;
+        let query = lang.parse_query("(call_expression)@call").unwrap();
+        let mut extractor = Extractor::new(lang, query);
+
+        let source = b"fn ok() { a(); }\nfn broken() { if { b(); } }\n";
+
+        let extracted = extractor
+            .extract_from_text(None, source, &mut Parser::new())
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        assert_eq!(extracted.matches.len(), 2);
+
+        extractor.set_ignore_parse_errors(true);
+
+        let extracted = extractor
+            .extract_from_text(None, source, &mut Parser::new())
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        assert_eq!(extracted.matches.len(), 1);
+        assert_eq!(extracted.matches[0].text, "a()");
+    }
+
+    #[test]
+    fn test_key_values() {
+        let lang = Language::Rust;
+        let query = lang
+            .parse_query("(function_item name: (identifier)@name return_type: (_)? @type)")
+            .unwrap();
+        let extractor = Extractor::new(lang, query);
+
+        let extracted = extractor
+            .extract_from_text(
+                None,
+                b"fn one() -> i32 { 1 }\nfn none() {}\nfn two() -> u8 { 2 }\n",
+                &mut Parser::new(),
+            )
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        assert_eq!(
+            extracted.key_values("name", "type"),
+            vec![("one", "i32"), ("two", "u8")]
+        );
+    }
+
+    #[test]
+    fn test_record_indents() {
+        let lang = Language::Rust;
+        let query = lang.parse_query("(call_expression)@call").unwrap();
+        let mut extractor = Extractor::new(lang, query);
+        extractor.set_record_indents(true);
+
+        let extracted = extractor
+            .extract_from_text(
+                None,
+                b"fn main() {\n\tgo();\n    if x { stop(); }\n}\n",
+                &mut Parser::new(),
+            )
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        assert_eq!(extracted.matches[0].indent, Some(String::from("\t")));
+        assert_eq!(extracted.matches[1].indent, Some(String::from("    ")));
+
+        let json = serde_json::to_value(&extracted.matches[0]).unwrap();
+        assert_eq!(json["indent"], "\t");
+    }
+
+    #[test]
+    fn test_qualified_names() {
+        let lang = Language::Rust;
+        let query = lang
+            .parse_query("(function_item name: (identifier)@name)")
+            .unwrap();
+        let mut extractor = Extractor::new(lang, query);
+        extractor.set_qualified_names(true);
+
+        let extracted = extractor
+            .extract_from_text(
+                None,
+                b"fn top() {}\nmod outer {\n    struct Thing;\n    impl Thing {\n        fn method(&self) {}\n    }\n}\n",
+                &mut Parser::new(),
+            )
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        assert_eq!(
+            extracted.matches[0].qualified_name,
+            Some(String::from("top"))
+        );
+        assert_eq!(
+            extracted.matches[1].qualified_name,
+            Some(String::from("outer::Thing::method"))
+        );
+    }
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a, b"), "\"a, b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("one\ntwo"), "\"one\ntwo\"");
+    }
+
+    #[test]
+    fn test_max_node_depth() {
+        let lang = Language::Rust;
+        let query = lang.parse_query("(identifier)@ident").unwrap();
+        let mut extractor = Extractor::new(lang, query);
+        // source_file > function_item > identifier is depth 2, but the call
+        // inside the body is much deeper.
+        extractor.set_max_node_depth(Some(2));
+
+        let extracted = extractor
+            .extract_from_text(None, b"fn main() { go(); }", &mut Parser::new())
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        assert_eq!(extracted.matches.len(), 1);
+        assert_eq!(extracted.matches[0].text, "main");
+
+        extractor.set_max_node_depth(None);
+
+        let extracted = extractor
+            .extract_from_text(None, b"fn main() { go(); }", &mut Parser::new())
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        assert_eq!(extracted.matches.len(), 2);
+    }
+
+    #[test]
+    fn test_colored() {
+        let lang = Language::Rust;
+        let query = lang.parse_query("(call_expression)@call").unwrap();
+        let extractor = Extractor::new(lang, query);
+
+        let extracted = extractor
+            .extract_from_text(
+                Some(Path::new("test.rs")),
+                b"fn main() {\n    go(\n    );\n}\n",
+                &mut Parser::new(),
+            )
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        assert_eq!(
+            extracted.colored().to_string(),
+            "\x1b[35mtest.rs\x1b[0m:\x1b[32m2\x1b[0m:\x1b[32m5\x1b[0m:call:\x1b[1;31mgo(\x1b[0m\n\
+             \x1b[1;31m    )\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn test_context_lines() {
+        let lang = Language::Rust;
+        let query = lang.parse_query("(call_expression)@call").unwrap();
+        let mut extractor = Extractor::new(lang, query);
+        extractor.set_context_lines(1, 1);
+
+        let extracted = extractor
+            .extract_from_text(
+                Some(Path::new("test.rs")),
+                b"fn main() {\n    one();\n    two();\n\n\n\n    three();\n}\n",
+                &mut Parser::new(),
+            )
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        assert_eq!(
+            extracted.in_context().to_string(),
+            "test.rs-1-fn main() {\n\
+             test.rs:2:5:call:one()\n\
+             test.rs:3:5:call:two()\n\
+             test.rs-4-\n\
+             --\n\
+             test.rs-6-\n\
+             test.rs:7:5:call:three()\n\
+             test.rs-8-}\n"
+        );
+    }
+
+    #[test]
+    fn test_byte_range() {
+        let lang = Language::Rust;
+        let query = lang
+            .parse_query("(function_item name: (identifier)@name)")
+            .unwrap();
+        let mut extractor = Extractor::new(lang, query);
+        // "fn one() {}\n" is 12 bytes, so this covers `two` and stops in the
+        // middle of `three`'s function item, but before its name.
+        extractor.set_byte_range(Some(12..27));
+
+        let extracted = extractor
+            .extract_from_text(
+                None,
+                b"fn one() {}\nfn two() {}\nfn three() {}\n",
+                &mut Parser::new(),
+            )
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        assert_eq!(
+            extracted.captured_texts("name").collect::<Vec<_>>(),
+            vec!["two"]
+        );
+    }
+
+    #[test]
+    fn test_covered_bytes() {
+        let lang = Language::Rust;
+        let query = lang
+            .parse_query("(function_item name: (identifier)@name)@func (block)@block")
+            .unwrap();
+        let extractor = Extractor::new(lang, query);
+
+        let extracted = extractor
+            .extract_from_text(None, b"fn main() {}\n\nstruct Foo;\n", &mut Parser::new())
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        // the name and block are both inside the function, so only the 12
+        // bytes of `fn main() {}` count.
+        assert_eq!(extracted.covered_bytes(), (12, 26));
+    }
+
+    #[test]
+    fn test_normalize_newlines() {
+        let lang = Language::Rust;
+        let source = b"fn a() {}\rfn b() {\r\n}\r\n";
+
+        let mut extractor = Extractor::new(lang, lang.parse_query("(function_item)@f").unwrap());
+        let raw = extractor
+            .extract_from_text(None, source, &mut Parser::new())
+            .unwrap()
+            .unwrap();
+
+        let (raw_start, raw_text) = (raw.matches[1].start, raw.matches[1].text.clone());
+
+        extractor.set_normalize_newlines(true);
+        let normalized = extractor
+            .extract_from_text(None, source, &mut Parser::new())
+            .unwrap()
+            .unwrap();
+
+        // tree-sitter only counts LF as a line break, so a lone CR leaves
+        // `b` on the first line unless we convert it.
+        assert_eq!(raw_start, Point { row: 0, column: 10 });
+        assert_eq!(raw_text, "fn b() {\r\n}");
+
+        assert_eq!(normalized.matches[1].start, Point { row: 1, column: 0 });
+        assert_eq!(normalized.matches[1].text, "fn b() {\n}");
+        assert_eq!(normalized.matches[1].end_byte, 20);
+    }
+
+    #[test]
+    fn test_big_files_are_mapped() {
+        let dir = TempDir::new("mapped");
+        let path = dir.join("big.rs");
+        let functions = MMAP_THRESHOLD as usize / 10 + 1;
+        fs::write(&path, "fn a() {}\n".repeat(functions)).unwrap();
+
+        let source = read_source(&path).unwrap();
+        let mapped = matches!(source, Source::Mapped(_));
+
+        let lang = Language::Rust;
+        let extractor = Extractor::new(lang, lang.parse_query("(function_item)@f").unwrap());
+        let extracted = extractor
+            .extract_from_file(&path, &mut Parser::new())
+            .unwrap()
+            .unwrap();
+
+        assert!(mapped);
+        assert_eq!(extracted.matches_len(), functions);
+    }
+
+    #[test]
+    fn test_max_count() {
+        let lang = Language::Rust;
+        let query = lang
+            .parse_query("(function_item body: (block)@body)@func (identifier)@ident")
+            .unwrap();
+        let mut extractor = Extractor::new(lang, query);
+        extractor.set_max_count(Some(3));
+
+        let extracted = extractor
+            .extract_from_text(
+                None,
+                b"fn main() { go(); }\nfn other() {}",
+                &mut Parser::new(),
+            )
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        assert_eq!(
+            extracted
+                .matches
+                .iter()
+                .map(|extraction| extraction.text.as_str())
+                .collect::<Vec<_>>(),
+            vec!["fn main() { go(); }", "main", "{ go(); }"]
+        );
+    }
+
+    #[test]
+    fn test_at() {
+        let lang = Language::Rust;
+        let query = lang.parse_query("(_)@node").unwrap();
+        let mut extractor = Extractor::new(lang, query);
+        // on the `o` in `go`
+        extractor.set_at(Some(Point::new(1, 5)));
+
+        let extracted = extractor
+            .extract_from_text(None, b"fn main() {\n    go(1);\n}\n", &mut Parser::new())
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        assert_eq!(
+            extracted
+                .matches
+                .iter()
+                .map(|extraction| (extraction.kind, extraction.text.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("identifier", "go")]
+        );
+    }
+
+    #[test]
+    fn test_at_outside_every_match() {
+        let lang = Language::Rust;
+        let query = lang.parse_query("(call_expression)@call").unwrap();
+        let mut extractor = Extractor::new(lang, query);
+        extractor.set_at(Some(Point::new(0, 0)));
+
+        assert!(extractor
+            .extract_from_text(None, b"fn main() { go(1); }", &mut Parser::new())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_highlight() {
+        let lang = Language::Rust;
+        let query = lang.parse_query("(function_item)@func").unwrap();
+        let mut extractor = Extractor::new(lang, query);
+        extractor.set_highlight(true);
+
+        let extracted = extractor
+            .extract_from_text(
+                None,
+                b"fn a() -> bool { /* <b> */ \"s\" == 1 }",
+                &mut Parser::new(),
+            )
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        assert_eq!(
+            extracted.matches[0].highlighted.as_deref(),
+            Some(
+                "<span class=\"keyword\">fn</span> a() -&gt; bool { \
+                 <span class=\"comment\">/* &lt;b&gt; */</span> \
+                 <span class=\"string\">&quot;s&quot;</span> == \
+                 <span class=\"number\">1</span> }"
+            )
+        );
+    }
+
+    #[test]
+    fn test_only_capture() {
+        let lang = Language::Rust;
+        let query = lang
+            .parse_query("(function_item name: (identifier)@name)@func")
+            .unwrap();
+        let mut extractor = Extractor::new(lang, query);
+        extractor.set_only_capture(Some("name")).unwrap();
+
+        let extracted = extractor
+            .extract_from_text(None, b"fn main() {}\nfn other() {}", &mut Parser::new())
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        assert_eq!(
+            extracted
+                .matches
+                .iter()
+                .map(|extraction| (extraction.name, extraction.text.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("name", "main"), ("name", "other")]
+        );
+    }
+
+    #[test]
+    fn test_only_capture_must_exist() {
+        let lang = Language::Rust;
+        let query = lang.parse_query("(function_item)@func").unwrap();
+        let mut extractor = Extractor::new(lang, query);
+
+        assert!(extractor.set_only_capture(Some("name")).is_err());
+    }
+
+    #[test]
+    fn test_capture_index() {
+        let lang = Language::Rust;
+        let query = lang
+            .parse_query("(arguments (identifier)@arg (identifier)@arg)")
+            .unwrap();
+        let mut extractor = Extractor::new(lang, query);
+        extractor.set_capture_index(Some(1));
+
+        let extracted = extractor
+            .extract_from_text(None, b"fn main() { go(a, b); }", &mut Parser::new())
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        assert_eq!(extracted.matches.len(), 1);
+        assert_eq!(extracted.matches[0].text, "b");
+    }
+
+    #[test]
+    fn test_count_lines() {
+        assert_eq!(count_lines(b""), 0);
+        assert_eq!(count_lines(b"one"), 1);
+        assert_eq!(count_lines(b"one\n"), 1);
+        assert_eq!(count_lines(b"one\ntwo"), 2);
+    }
+
+    #[test]
+    fn test_list_errors() {
+        let lang = Language::Rust;
+        let query = lang.parse_query("").unwrap();
+        let mut extractor = Extractor::new(lang, query);
+        extractor.set_list_errors(true);
+
+        let extracted = extractor
+            .extract_from_text(None, b"fn main() {\n    let x = 1\n}\n", &mut Parser::new())
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        assert_eq!(extracted.matches.len(), 1);
+        assert_eq!(extracted.matches[0].name, "missing");
+        assert_eq!(extracted.matches[0].kind, ";");
+        assert_eq!(extracted.matches[0].start, Point::new(1, 13));
+    }
+
+    #[test]
+    fn test_list_errors_without_errors() {
+        let lang = Language::Rust;
+        let query = lang.parse_query("(identifier)").unwrap();
+        let mut extractor = Extractor::new(lang, query);
+        extractor.set_list_errors(true);
+
+        let extracted = extractor
+            .extract_from_text(None, b"fn main() {}", &mut Parser::new())
+            // From Result<Option<ExtractedFile>>
+            .unwrap();
+
+        assert_eq!(extracted, None);
     }
 }
@@ -1,24 +1,66 @@
 use crate::extractor::Extractor;
+use crate::language::Language;
 use anyhow::{bail, Context, Result};
 use ignore::types::{Types, TypesBuilder};
 use ignore::DirEntry;
 use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+/// We only look this far into a file for a language marker.
+const MARKER_LINE_LIMIT: u64 = 1024;
+
+/// Files the types builder's defaults don't know belong to our languages.
+const EXTRA_TYPES: &[(&str, &str)] = &[
+    ("capnp", "*.capnp"),
+    ("apex", "*.cls"),
+    ("apex", "*.trigger"),
+    ("bicep", "*.bicep"),
+    ("mermaid", "*.mmd"),
+    // Zig object notation, which is a single Zig expression. Mostly seen as
+    // `build.zig.zon`.
+    ("zig", "*.zon"),
+    // type stubs, which are Python with the bodies left out.
+    ("py", "*.pyi"),
+    // Cython. There's no grammar for it, but it's a superset of Python, so
+    // we search it as Python. Cython-only syntax (like `cdef`) parses as
+    // errors.
+    ("py", "*.pyx"),
+    ("py", "*.pxd"),
+    // GHC reads these to break import cycles. They're Haskell modules with
+    // only the declarations filled in.
+    ("haskell", "*.hs-boot"),
+];
 
 pub struct ExtractorChooser<'extractor> {
     matcher: Types,
     extractors: HashMap<&'extractor str, &'extractor Extractor>,
+    language_marker: Option<String>,
+    overrides: HashMap<String, Language>,
 }
 
 impl<'extractor> ExtractorChooser<'extractor> {
     pub fn from_extractors(extractors: &[Extractor]) -> Result<ExtractorChooser> {
         let mut types_builder = TypesBuilder::new();
         types_builder.add_defaults();
+        for (name, glob) in EXTRA_TYPES {
+            types_builder
+                .add(name, glob)
+                .with_context(|| format!("could not add {} to the {} filetype", glob, name))?;
+        }
 
         let mut names_to_extractors = HashMap::with_capacity(extractors.len());
 
         for extractor in extractors {
             let name = extractor.language().name_for_types_builder();
-            types_builder.select(name);
+            // the types builder doesn't know about grammars we loaded at
+            // runtime, so files only get those through --language-override.
+            if !matches!(extractor.language(), Language::Dynamic(_)) {
+                types_builder.select(name);
+            }
 
             // a little reminder: insert returns the old value if the key was
             // already present
@@ -32,12 +74,86 @@ impl<'extractor> ExtractorChooser<'extractor> {
                 .build()
                 .context("could not build a filetype matcher using provided extractors")?,
             extractors: names_to_extractors,
+            language_marker: None,
+            overrides: HashMap::new(),
         })
     }
 
+    /// Let files say what language they are on their first line, after this
+    /// marker (like Racket's `#lang`.) A marker naming a language we have a
+    /// query for wins over the file's extension.
+    pub fn set_language_marker(&mut self, language_marker: Option<String>) {
+        self.language_marker = language_marker
+    }
+
+    /// Treat files with these extensions (or exactly these names) as the
+    /// given languages, instead of whatever we'd usually pick (or not pick)
+    /// for them.
+    pub fn set_overrides(&mut self, overrides: Vec<(String, Language)>) {
+        self.overrides = overrides
+            .into_iter()
+            .map(|(name, language)| (name.trim_start_matches('.').to_string(), language))
+            .collect()
+    }
+
     pub fn extractor_for(&self, entry: &DirEntry) -> Option<&Extractor> {
         let is_dir = entry.file_type().map(|ft| ft.is_dir()).unwrap_or(true);
-        let matched = self.matcher.matched(entry.path(), is_dir);
+
+        if !is_dir {
+            if let Some(extractor) = self.extractor_for_marker(entry.path()) {
+                return Some(extractor);
+            }
+        }
+
+        self.extractor_for_path(entry.path(), is_dir)
+    }
+
+    fn extractor_for_marker(&self, path: &Path) -> Option<&Extractor> {
+        let marker = self.language_marker.as_ref()?;
+
+        let mut first_line = String::new();
+        BufReader::new(File::open(path).ok()?.take(MARKER_LINE_LIMIT))
+            .read_line(&mut first_line)
+            .ok()?;
+
+        let name = first_line
+            .strip_prefix(marker.as_str())?
+            .split_whitespace()
+            .next()?;
+        let language = Language::from_str(name).ok()?;
+
+        self.extractor_for_language(language)
+    }
+
+    /// Choose an extractor from a file's name alone, for files that aren't
+    /// on disk (like the staged versions of files in git.)
+    pub fn extractor_for_name(&self, path: &Path) -> Option<&Extractor> {
+        self.extractor_for_path(path, false)
+    }
+
+    /// Choose an extractor for a gzipped file based on the name it has
+    /// without `.gz` (so `foo.rs.gz` gets the Rust extractor.)
+    pub fn extractor_for_compressed(&self, entry: &DirEntry) -> Option<&Extractor> {
+        if entry.path().extension() != Some(OsStr::new("gz")) {
+            return None;
+        }
+
+        self.extractor_for_path(&entry.path().with_extension(""), false)
+    }
+
+    fn extractor_for_path(&self, path: &Path, is_dir: bool) -> Option<&Extractor> {
+        if !is_dir && !self.overrides.is_empty() {
+            let overridden = [path.file_name(), path.extension()]
+                .iter()
+                .flatten()
+                .find_map(|name| self.overrides.get(name.to_str()?));
+
+            if let Some(language) = overridden {
+                return self.extractor_for_language(*language);
+            }
+        }
+
+        let matched = self.matcher.matched(path, is_dir);
 
         if !matched.is_whitelist() {
             return None;
@@ -49,4 +165,10 @@ impl<'extractor> ExtractorChooser<'extractor> {
             .and_then(|def| self.extractors.get(def.name()))
             .copied()
     }
+
+    pub fn extractor_for_language(&self, language: Language) -> Option<&Extractor> {
+        self.extractors
+            .get(language.name_for_types_builder())
+            .copied()
+    }
 }
@@ -1,47 +1,75 @@
-use anyhow::{anyhow, bail, Error, Result};
+use crate::dynamic_grammar::{self, DynamicGrammar};
+use anyhow::{anyhow, bail, Context, Error, Result};
 use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 
-#[derive(PartialEq, Eq, Hash, Debug)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
 pub enum Language {
+    Apex,
+    Bicep,
+    Capnp,
     Cpp,
     Elixir,
     Elm,
+    Go,
     Haskell,
     JavaScript,
+    Mermaid,
+    Pascal,
     Php,
+    Python,
     Ruby,
     Rust,
     TypeScript,
+    Zig,
+    /// A grammar loaded from a shared library with `--grammar`.
+    Dynamic(&'static DynamicGrammar),
 }
 
 impl Language {
     pub fn all() -> Vec<Language> {
         vec![
+            Language::Apex,
+            Language::Bicep,
+            Language::Capnp,
             Language::Cpp,
             Language::Elixir,
             Language::Elm,
+            Language::Go,
             Language::Haskell,
             Language::JavaScript,
+            Language::Mermaid,
+            Language::Pascal,
             Language::Php,
+            Language::Python,
             Language::Ruby,
             Language::Rust,
             Language::TypeScript,
+            Language::Zig,
         ]
     }
 
     pub fn language(&self) -> tree_sitter::Language {
         unsafe {
             match self {
+                Language::Apex => tree_sitter_apex(),
+                Language::Bicep => tree_sitter_bicep(),
+                Language::Capnp => tree_sitter_capnp(),
                 Language::Cpp => tree_sitter_cpp(),
                 Language::Elixir => tree_sitter_elixir(),
                 Language::Elm => tree_sitter_elm(),
+                Language::Go => tree_sitter_go(),
                 Language::Haskell => tree_sitter_haskell(),
                 Language::JavaScript => tree_sitter_javascript(),
+                Language::Mermaid => tree_sitter_mermaid(),
+                Language::Pascal => tree_sitter_pascal(),
                 Language::Php => tree_sitter_php(),
+                Language::Python => tree_sitter_python(),
                 Language::Ruby => tree_sitter_ruby(),
                 Language::Rust => tree_sitter_rust(),
                 Language::TypeScript => tree_sitter_typescript(),
+                Language::Zig => tree_sitter_zig(),
+                Language::Dynamic(grammar) => grammar.language(),
             }
         }
     }
@@ -50,17 +78,379 @@ impl Language {
         tree_sitter::Query::new(self.language(), raw).map_err(|err| anyhow!("{}", err))
     }
 
+    /// The node types this grammar uses for comments.
+    pub fn comment_node_types(&self) -> &[&str] {
+        match self {
+            Language::Apex | Language::Elm | Language::Rust => &["line_comment", "block_comment"],
+            Language::Bicep
+            | Language::Capnp
+            | Language::Cpp
+            | Language::Elixir
+            | Language::Go
+            | Language::Haskell
+            | Language::JavaScript
+            | Language::Mermaid
+            | Language::Pascal
+            | Language::Php
+            | Language::Python
+            | Language::Ruby
+            | Language::TypeScript
+            | Language::Zig => &["comment"],
+            // most grammars call them this, so it's our best guess.
+            Language::Dynamic(_) => &["comment"],
+        }
+    }
+
+    /// The node types this grammar uses for string literals that can contain
+    /// escape sequences. Raw strings aren't included, since there's no way to
+    /// escape things inside them.
+    pub fn string_node_types(&self) -> &[&str] {
+        match self {
+            Language::Apex | Language::Cpp | Language::Rust => &["string_literal"],
+            Language::Bicep
+            | Language::Capnp
+            | Language::Elixir
+            | Language::Haskell
+            | Language::Python
+            | Language::Ruby
+            | Language::Zig => &["string"],
+            Language::Elm => &["string_constant_expr"],
+            Language::Go => &["interpreted_string_literal"],
+            Language::JavaScript | Language::TypeScript => &["string", "template_string"],
+            // Mermaid spells special characters in strings as entities (like
+            // `#quot;`) instead of escaping them, so we leave them alone.
+            Language::Mermaid => &[],
+            Language::Pascal => &["literalString"],
+            Language::Php => &["string", "encapsed_string"],
+            Language::Dynamic(_) => &["string"],
+        }
+    }
+
+    /// The node types this grammar uses for raw string literals, where
+    /// nothing can be escaped.
+    pub fn raw_string_node_types(&self) -> &[&str] {
+        match self {
+            // multiline strings are raw, but share a node type with ordinary
+            // strings. `raw_string_terminator` tells them apart.
+            Language::Bicep => &["string"],
+            Language::Cpp | Language::Go | Language::Rust => &["raw_string_literal"],
+            Language::Zig => &["multiline_string"],
+            _ => &[],
+        }
+    }
+
+    /// Escape text so it can go inside a string literal delimited by `quote`
+    /// without ending it early (or otherwise changing what it means,) including
+    /// by starting an interpolation.
+    pub fn escape_string(&self, text: &str, quote: char) -> String {
+        // Pascal doesn't have backslash escapes. Quotes are doubled, and
+        // anything else has to go between two strings as a character code.
+        if let Language::Pascal = self {
+            return text
+                .replace(quote, &format!("{}{}", quote, quote))
+                .replace('\n', &format!("{}#10{}", quote, quote));
+        }
+
+        // strings in these languages can't contain literal newlines, except
+        // for JavaScript's backtick template strings.
+        let escape_newlines = match self {
+            // we don't know, so we play it safe.
+            Language::Cpp
+            | Language::Elm
+            | Language::Haskell
+            | Language::Mermaid
+            | Language::Dynamic(_) => true,
+            Language::Apex | Language::Capnp | Language::Go | Language::Zig => true,
+            // only multiline strings can, and we can't tell those apart from
+            // the quote alone.
+            Language::Bicep => true,
+            // Pascal returned early above, but it can't have them either.
+            Language::Pascal => true,
+            // only triple-quoted strings can, and we can't tell those apart
+            // from the quote alone.
+            Language::Python => true,
+            Language::JavaScript | Language::TypeScript => quote != '`',
+            Language::Elixir | Language::Php | Language::Ruby | Language::Rust => false,
+        };
+
+        let mut out = String::with_capacity(text.len());
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            let next = chars.peek().copied();
+
+            let interpolates = match self {
+                Language::JavaScript | Language::TypeScript => {
+                    quote == '`' && c == '$' && next == Some('{')
+                }
+                Language::Ruby => {
+                    matches!(quote, '"' | '`')
+                        && c == '#'
+                        && matches!(next, Some('{') | Some('@') | Some('$'))
+                }
+                // Elixir interpolates in charlists too
+                Language::Elixir => c == '#' && next == Some('{'),
+                Language::Php => quote == '"' && c == '$',
+                Language::Bicep => c == '$' && next == Some('{'),
+                _ => false,
+            };
+
+            match c {
+                '\\' => out.push_str("\\\\"),
+                '\n' if escape_newlines => out.push_str("\\n"),
+                _ if c == quote || interpolates => {
+                    out.push('\\');
+                    out.push(c);
+                }
+                _ => out.push(c),
+            }
+        }
+
+        out
+    }
+
+    /// The delimiter that ends a raw string literal (like `"#` for Rust's
+    /// `r#"..."#`, `)x"` for C++'s `R"x(...)x"`, or the closing backtick of
+    /// a Go raw string), so we can check that we don't insert it. `None`
+    /// means the literal isn't raw after all.
+    pub fn raw_string_terminator(&self, literal: &str) -> Option<String> {
+        match self {
+            Language::Bicep if literal.starts_with("'''") => Some(String::from("'''")),
+            Language::Go => Some(String::from("`")),
+            // each line of a multiline string starts with `\\`, so a newline
+            // would end it.
+            Language::Zig => Some(String::from("\n")),
+            Language::Rust => {
+                let (prefix, _) = literal.split_once('"')?;
+                Some(format!(
+                    "\"{}",
+                    "#".repeat(prefix.chars().filter(|c| *c == '#').count())
+                ))
+            }
+            Language::Cpp => Some(format!(
+                "){}\"",
+                literal.split_once('"')?.1.split_once('(')?.0
+            )),
+            _ => None,
+        }
+    }
+
+    /// The node types that make up a qualified name (like `module::Type::method`),
+    /// paired with the field that holds each one's name. Languages whose
+    /// grammars don't name their containers in a field have none.
+    pub fn qualified_name_containers(&self) -> &[(&str, &str)] {
+        match self {
+            Language::Apex => &[
+                ("class_declaration", "name"),
+                ("interface_declaration", "name"),
+                ("enum_declaration", "name"),
+                ("trigger_declaration", "name"),
+                ("method_declaration", "name"),
+            ],
+            Language::Bicep => &[("user_defined_function", "name")],
+            // none of the definitions keep their names in fields
+            Language::Capnp => &[],
+            Language::Cpp => &[
+                ("namespace_definition", "name"),
+                ("class_specifier", "name"),
+                ("struct_specifier", "name"),
+            ],
+            Language::Elixir => &[],
+            Language::Elm => &[("module_declaration", "name")],
+            Language::Go => &[],
+            Language::Haskell => &[("function", "name")],
+            Language::JavaScript => &[
+                ("class_declaration", "name"),
+                ("function_declaration", "name"),
+                ("method_definition", "name"),
+            ],
+            Language::Mermaid => &[],
+            Language::Pascal => &[("declType", "name"), ("declProc", "name")],
+            Language::Php => &[
+                ("namespace_definition", "name"),
+                ("class_declaration", "name"),
+                ("method_declaration", "name"),
+                ("function_definition", "name"),
+            ],
+            Language::Python => &[
+                ("class_definition", "name"),
+                ("function_definition", "name"),
+            ],
+            Language::Ruby => &[("module", "name"), ("class", "name"), ("method", "name")],
+            Language::Rust => &[
+                ("mod_item", "name"),
+                ("struct_item", "name"),
+                ("enum_item", "name"),
+                ("trait_item", "name"),
+                ("impl_item", "type"),
+                ("function_item", "name"),
+            ],
+            Language::TypeScript => &[
+                ("module", "name"),
+                ("internal_module", "name"),
+                ("interface_declaration", "name"),
+                ("class_declaration", "name"),
+                ("abstract_class_declaration", "name"),
+                ("function_declaration", "name"),
+                ("method_definition", "name"),
+            ],
+            Language::Zig => &[("function_declaration", "name")],
+            Language::Dynamic(_) => &[],
+        }
+    }
+
+    /// What goes between the parts of a qualified name.
+    pub fn qualified_name_separator(&self) -> &str {
+        match self {
+            Language::Cpp | Language::Php | Language::Ruby | Language::Rust => "::",
+            Language::Apex
+            | Language::Bicep
+            | Language::Capnp
+            | Language::Elixir
+            | Language::Elm
+            | Language::Go
+            | Language::Haskell
+            | Language::JavaScript
+            | Language::Mermaid
+            | Language::Pascal
+            | Language::Python
+            | Language::TypeScript
+            | Language::Zig
+            | Language::Dynamic(_) => ".",
+        }
+    }
+
+    /// A query matching every comment (optionally only ones matching a regex)
+    /// and capturing them as `@comment`.
+    pub fn comments_query(&self, pattern: Option<&str>) -> String {
+        let mut out = String::from("([");
+        for node_type in self.comment_node_types() {
+            out.push_str(&format!("({})", node_type));
+        }
+        out.push_str("] @comment");
+
+        if let Some(pattern) = pattern {
+            out.push_str(&format!(
+                " (#match? @comment \"{}\")",
+                pattern.replace('\\', "\\\\").replace('"', "\\\"")
+            ));
+        }
+
+        out.push(')');
+        out
+    }
+
+    /// The grammar's `node-types.json`, which describes every node type the
+    /// grammar can produce.
+    fn node_types_json(&self) -> Result<&'static str> {
+        Ok(match self {
+            Language::Apex => include_str!("../vendor/tree-sitter-sfapex/apex/src/node-types.json"),
+            Language::Bicep => include_str!("../vendor/tree-sitter-bicep/src/node-types.json"),
+            Language::Capnp => include_str!("../vendor/tree-sitter-capnp/src/node-types.json"),
+            Language::Cpp => include_str!("../vendor/tree-sitter-cpp/src/node-types.json"),
+            Language::Elixir => include_str!("../vendor/tree-sitter-elixir/src/node-types.json"),
+            Language::Elm => include_str!("../vendor/tree-sitter-elm/src/node-types.json"),
+            Language::Go => include_str!("../vendor/tree-sitter-go/src/node-types.json"),
+            Language::Haskell => include_str!("../vendor/tree-sitter-haskell/src/node-types.json"),
+            Language::JavaScript => {
+                include_str!("../vendor/tree-sitter-javascript/src/node-types.json")
+            }
+            Language::Mermaid => include_str!("../vendor/tree-sitter-mermaid/src/node-types.json"),
+            Language::Pascal => include_str!("../vendor/tree-sitter-pascal/src/node-types.json"),
+            Language::Php => include_str!("../vendor/tree-sitter-php/src/node-types.json"),
+            Language::Python => include_str!("../vendor/tree-sitter-python/src/node-types.json"),
+            Language::Ruby => include_str!("../vendor/tree-sitter-ruby/src/node-types.json"),
+            Language::Rust => include_str!("../vendor/tree-sitter-rust/src/node-types.json"),
+            Language::TypeScript => {
+                include_str!("../vendor/tree-sitter-typescript/typescript/src/node-types.json")
+            }
+            Language::Zig => include_str!("../vendor/tree-sitter-zig/src/node-types.json"),
+            Language::Dynamic(grammar) => bail!(
+                "we don't have node types for {}, since it was loaded with --grammar",
+                grammar.name()
+            ),
+        })
+    }
+
+    /// The grammar's `package.json`, where the grammar records its version.
+    fn package_json(&self) -> Result<&'static str> {
+        Ok(match self {
+            Language::Apex => include_str!("../vendor/tree-sitter-sfapex/package.json"),
+            Language::Bicep => include_str!("../vendor/tree-sitter-bicep/package.json"),
+            Language::Capnp => include_str!("../vendor/tree-sitter-capnp/package.json"),
+            Language::Cpp => include_str!("../vendor/tree-sitter-cpp/package.json"),
+            Language::Elixir => include_str!("../vendor/tree-sitter-elixir/package.json"),
+            Language::Elm => include_str!("../vendor/tree-sitter-elm/package.json"),
+            Language::Go => include_str!("../vendor/tree-sitter-go/package.json"),
+            Language::Haskell => include_str!("../vendor/tree-sitter-haskell/package.json"),
+            Language::JavaScript => include_str!("../vendor/tree-sitter-javascript/package.json"),
+            Language::Mermaid => include_str!("../vendor/tree-sitter-mermaid/package.json"),
+            Language::Pascal => include_str!("../vendor/tree-sitter-pascal/package.json"),
+            Language::Php => include_str!("../vendor/tree-sitter-php/package.json"),
+            Language::Python => include_str!("../vendor/tree-sitter-python/package.json"),
+            Language::Ruby => include_str!("../vendor/tree-sitter-ruby/package.json"),
+            Language::Rust => include_str!("../vendor/tree-sitter-rust/package.json"),
+            Language::TypeScript => include_str!("../vendor/tree-sitter-typescript/package.json"),
+            Language::Zig => include_str!("../vendor/tree-sitter-zig/package.json"),
+            Language::Dynamic(grammar) => bail!(
+                "we don't know the version of {}, since it was loaded with --grammar",
+                grammar.name()
+            ),
+        })
+    }
+
+    /// The version of the vendored grammar we were built with.
+    pub fn grammar_version(&self) -> Result<String> {
+        let package: serde_json::Value = serde_json::from_str(self.package_json()?)
+            .context("could not parse the grammar's package.json")?;
+
+        package["version"]
+            .as_str()
+            .map(String::from)
+            .ok_or_else(|| anyhow!("the {} grammar's package.json doesn't have a version", self))
+    }
+
+    /// The names of the fields (like `name` in `name: (identifier)`) that
+    /// can be used in queries on the given node type.
+    pub fn field_names(&self, node_type: &str) -> Result<Vec<String>> {
+        let node_types: serde_json::Value = serde_json::from_str(self.node_types_json()?)
+            .context("could not parse the grammar's node types")?;
+
+        let node = node_types
+            .as_array()
+            .and_then(|node_types| {
+                node_types.iter().find(|node| {
+                    node["type"].as_str() == Some(node_type)
+                        && node["named"].as_bool() == Some(true)
+                })
+            })
+            .ok_or_else(|| anyhow!("{} doesn't have a node type named {}", self, node_type))?;
+
+        Ok(match node["fields"].as_object() {
+            Some(fields) => fields.keys().cloned().collect(),
+            None => Vec::new(),
+        })
+    }
+
     pub fn name_for_types_builder(&self) -> &str {
         match self {
+            Language::Apex => "apex",
+            Language::Bicep => "bicep",
+            Language::Capnp => "capnp",
             Language::Cpp => "cpp",
             Language::Elixir => "elixir",
             Language::Elm => "elm",
+            Language::Go => "go",
             Language::Haskell => "haskell",
             Language::JavaScript => "js",
+            Language::Mermaid => "mermaid",
+            Language::Pascal => "pascal",
             Language::Php => "php",
+            Language::Python => "py",
             Language::Ruby => "ruby",
             Language::Rust => "rust",
             Language::TypeScript => "ts",
+            Language::Zig => "zig",
+            Language::Dynamic(grammar) => grammar.name(),
         }
     }
 }
@@ -70,24 +460,36 @@ impl FromStr for Language {
 
     fn from_str(s: &str) -> Result<Self> {
         match s {
+            "apex" => Ok(Language::Apex),
+            "bicep" => Ok(Language::Bicep),
+            "capnp" => Ok(Language::Capnp),
             "cpp" => Ok(Language::Cpp),
             "elixir" => Ok(Language::Elixir),
             "elm" => Ok(Language::Elm),
+            "go" => Ok(Language::Go),
             "haskell" => Ok(Language::Haskell),
             "javascript" => Ok(Language::JavaScript),
+            "mermaid" => Ok(Language::Mermaid),
+            "pascal" => Ok(Language::Pascal),
             "php" => Ok(Language::Php),
+            "python" => Ok(Language::Python),
             "ruby" => Ok(Language::Ruby),
             "rust" => Ok(Language::Rust),
             "typescript" => Ok(Language::TypeScript),
-            _ => bail!(
-                "unknown language {}. Try one of: {}",
-                s,
-                Language::all()
-                    .into_iter()
-                    .map(|l| l.to_string())
-                    .collect::<Vec<String>>()
-                    .join(", ")
-            ),
+            "zig" => Ok(Language::Zig),
+            _ => match dynamic_grammar::find(s) {
+                Some(grammar) => Ok(Language::Dynamic(grammar)),
+                None => bail!(
+                    "unknown language {}. Try one of: {}",
+                    s,
+                    Language::all()
+                        .into_iter()
+                        .map(|l| l.to_string())
+                        .chain(dynamic_grammar::names())
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                ),
+            },
         }
     }
 }
@@ -95,15 +497,24 @@ impl FromStr for Language {
 impl Display for Language {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
         match self {
+            Language::Apex => f.write_str("apex"),
+            Language::Bicep => f.write_str("bicep"),
+            Language::Capnp => f.write_str("capnp"),
             Language::Cpp => f.write_str("cpp"),
             Language::Elixir => f.write_str("elixir"),
             Language::Elm => f.write_str("elm"),
+            Language::Go => f.write_str("go"),
             Language::Haskell => f.write_str("haskell"),
             Language::JavaScript => f.write_str("javascript"),
+            Language::Mermaid => f.write_str("mermaid"),
+            Language::Pascal => f.write_str("pascal"),
             Language::Php => f.write_str("php"),
+            Language::Python => f.write_str("python"),
             Language::Ruby => f.write_str("ruby"),
             Language::Rust => f.write_str("rust"),
             Language::TypeScript => f.write_str("typescript"),
+            Language::Zig => f.write_str("zig"),
+            Language::Dynamic(grammar) => f.write_str(grammar.name()),
         }
     }
 }
@@ -127,6 +538,149 @@ mod tests {
         assert_eq!(true, Language::Elm.parse_query("(_)").is_ok());
     }
 
+    #[test]
+    fn qualified_name_containers_have_their_fields() {
+        Language::all().into_iter().for_each(|lang| {
+            for (node_type, field) in lang.qualified_name_containers() {
+                assert!(
+                    lang.field_names(node_type)
+                        .unwrap()
+                        .contains(&field.to_string()),
+                    "{} {} doesn't have a {} field",
+                    lang,
+                    node_type,
+                    field
+                )
+            }
+        })
+    }
+
+    #[test]
+    fn comments_query_parses_for_all_languages() {
+        Language::all().into_iter().for_each(|lang| {
+            assert!(
+                lang.parse_query(&lang.comments_query(Some("TODO\\b \"quoted\"")))
+                    .is_ok(),
+                "comments query failed to parse for {}",
+                lang
+            )
+        })
+    }
+
+    #[test]
+    fn field_names_for_node_type() {
+        let fields = Language::Rust.field_names("function_item").unwrap();
+
+        assert!(fields.contains(&String::from("name")));
+        assert!(fields.contains(&String::from("body")));
+    }
+
+    #[test]
+    fn field_names_for_unknown_node_type() {
+        assert_eq!(
+            String::from("rust doesn't have a node type named nope"),
+            Language::Rust.field_names("nope").unwrap_err().to_string()
+        )
+    }
+
+    #[test]
+    fn escape_string() {
+        assert_eq!(
+            Language::Rust.escape_string("say \"hi\" \\o/", '"'),
+            "say \\\"hi\\\" \\\\o/"
+        );
+        assert_eq!(
+            Language::JavaScript.escape_string("it's\n\"fine\"", '\''),
+            "it\\'s\\n\"fine\""
+        );
+        assert_eq!(
+            Language::JavaScript.escape_string("one\ntwo", '`'),
+            "one\ntwo"
+        );
+        assert_eq!(
+            Language::Pascal.escape_string("it's\n\\o/", '\''),
+            "it''s'#10'\\o/"
+        );
+    }
+
+    #[test]
+    fn escape_string_interpolation() {
+        assert_eq!(
+            Language::TypeScript.escape_string("${x} costs $5", '`'),
+            "\\${x} costs $5"
+        );
+        assert_eq!(Language::JavaScript.escape_string("${x}", '"'), "${x}");
+        assert_eq!(
+            Language::Ruby.escape_string("#{x} #@y #$z #1", '"'),
+            "\\#{x} \\#@y \\#$z #1"
+        );
+        assert_eq!(Language::Ruby.escape_string("#{x}", '\''), "#{x}");
+        assert_eq!(
+            Language::Elixir.escape_string("#{x} #@y", '\''),
+            "\\#{x} #@y"
+        );
+        assert_eq!(Language::Php.escape_string("$x {$y}", '"'), "\\$x {\\$y}");
+        assert_eq!(Language::Php.escape_string("$x", '\''), "$x");
+        assert_eq!(
+            Language::Bicep.escape_string("${x} costs $5", '\''),
+            "\\${x} costs $5"
+        );
+    }
+
+    #[test]
+    fn raw_string_terminator() {
+        assert_eq!(
+            Language::Rust.raw_string_terminator("r\"a\""),
+            Some(String::from("\""))
+        );
+        assert_eq!(
+            Language::Rust.raw_string_terminator("br##\"a\"##"),
+            Some(String::from("\"##"))
+        );
+        assert_eq!(
+            Language::Cpp.raw_string_terminator("u8R\"x(a)x\""),
+            Some(String::from(")x\""))
+        );
+        assert_eq!(
+            Language::Go.raw_string_terminator("`a`"),
+            Some(String::from("`"))
+        );
+        assert_eq!(
+            Language::Zig.raw_string_terminator("\\\\a\n"),
+            Some(String::from("\n"))
+        );
+        assert_eq!(
+            Language::Bicep.raw_string_terminator("'''a'''"),
+            Some(String::from("'''"))
+        );
+        assert_eq!(Language::Bicep.raw_string_terminator("'a'"), None);
+    }
+
+    #[test]
+    fn string_node_types_exist() {
+        Language::all().into_iter().for_each(|lang| {
+            for node_type in lang.string_node_types() {
+                assert!(
+                    lang.parse_query(&format!("({})", node_type)).is_ok(),
+                    "{} doesn't have a {} node",
+                    lang,
+                    node_type
+                )
+            }
+        })
+    }
+
+    #[test]
+    fn grammar_versions_for_all_languages() {
+        Language::all().into_iter().for_each(|lang| {
+            assert!(
+                lang.grammar_version().is_ok(),
+                "could not get a grammar version for {}",
+                lang
+            )
+        })
+    }
+
     #[test]
     fn parse_query_problem() {
         // tree-grepper 1.0 just printed the error struct when problems like
@@ -143,13 +697,21 @@ mod tests {
 }
 
 extern "C" {
+    fn tree_sitter_apex() -> tree_sitter::Language;
+    fn tree_sitter_bicep() -> tree_sitter::Language;
+    fn tree_sitter_capnp() -> tree_sitter::Language;
     fn tree_sitter_cpp() -> tree_sitter::Language;
     fn tree_sitter_elixir() -> tree_sitter::Language;
     fn tree_sitter_elm() -> tree_sitter::Language;
+    fn tree_sitter_go() -> tree_sitter::Language;
     fn tree_sitter_haskell() -> tree_sitter::Language;
     fn tree_sitter_javascript() -> tree_sitter::Language;
+    fn tree_sitter_mermaid() -> tree_sitter::Language;
+    fn tree_sitter_pascal() -> tree_sitter::Language;
     fn tree_sitter_php() -> tree_sitter::Language;
+    fn tree_sitter_python() -> tree_sitter::Language;
     fn tree_sitter_ruby() -> tree_sitter::Language;
     fn tree_sitter_rust() -> tree_sitter::Language;
     fn tree_sitter_typescript() -> tree_sitter::Language;
+    fn tree_sitter_zig() -> tree_sitter::Language;
 }
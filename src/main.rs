@@ -1,59 +1,146 @@
+mod cache;
 mod cli;
+mod color_json;
+mod diff;
+mod dynamic_grammar;
 mod extractor;
 mod extractor_chooser;
 mod language;
+mod merge;
+mod query_test;
+mod replace;
+mod sample;
+mod socket;
+mod staged;
+#[cfg(test)]
+mod temp_dir;
 
 use anyhow::{bail, Context, Result};
+use cache::Cache;
 use cli::{Invocation, QueryFormat, QueryOpts};
 use crossbeam::channel;
+use extractor::Extractor;
+use extractor_chooser::ExtractorChooser;
 use language::Language;
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use replace::Replacement;
+use serde::Serialize;
+use socket::Socket;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::env;
-use std::io::{self, BufWriter, Write};
+use std::fmt;
+use std::fs;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+#[cfg(test)]
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tree_sitter::Parser;
 
+/// The same code `timeout(1)` uses when a command runs too long.
+const TIMED_OUT_EXIT_CODE: i32 = 124;
+
+/// Like grep, we exit with 1 when nothing matched, so errors get 2 to keep
+/// the two apart in shell conditionals.
+const ERROR_EXIT_CODE: i32 = 2;
+
+/// Going over `--max-allowed` gets its own code, so CI can tell a blown
+/// budget apart from a search that found nothing or couldn't run.
+const TOO_MANY_MATCHES_EXIT_CODE: i32 = 3;
+
+#[cfg(feature = "bump-alloc")]
 #[global_allocator]
 static ALLOCATOR: bump_alloc::BumpAlloc = bump_alloc::BumpAlloc::new();
 
 fn main() {
     let mut buffer = BufWriter::new(io::stdout());
 
-    if let Err(error) = try_main(env::args().collect(), &mut buffer) {
-        if let Some(err) = error.downcast_ref::<io::Error>() {
-            // a broken pipe is totally normal and fine. It's what we get when
-            // we pipe to something like `head` that only takes a certain number
-            // of lines.
-            if err.kind() == io::ErrorKind::BrokenPipe {
-                std::process::exit(0);
-            }
+    let error = match try_main(env::args().collect(), &mut buffer) {
+        Ok(matched) => {
+            buffer.flush().expect("failed to flush buffer!");
+            std::process::exit(if matched { 0 } else { 1 });
         }
+        Err(error) => error,
+    };
 
-        if let Some(clap_error) = error.downcast_ref::<clap::Error>() {
-            // Clap errors (--help or misuse) are already well-formatted,
-            // so we don't have to do any additional work.
-            eprint!("{}", clap_error);
-        } else {
-            eprintln!("{:?}", error);
+    if let Some(code) = flushed_exit_code(&error) {
+        buffer.flush().expect("failed to flush buffer!");
+        eprintln!("{:?}", error);
+        std::process::exit(code);
+    }
+
+    if let Some(err) = error.downcast_ref::<io::Error>() {
+        // a broken pipe is totally normal and fine. It's what we get when
+        // we pipe to something like `head` that only takes a certain number
+        // of lines.
+        if err.kind() == io::ErrorKind::BrokenPipe {
+            std::process::exit(0);
         }
+    }
+
+    if let Some(clap_error) = error.downcast_ref::<clap::Error>() {
+        // Clap errors (--help or misuse) are already well-formatted,
+        // so we don't have to do any additional work.
+        eprint!("{}", clap_error);
+    } else {
+        eprintln!("{:?}", error);
+    }
+
+    std::process::exit(ERROR_EXIT_CODE);
+}
+
+/// The exit code for errors where we still want to print what we found, or
+/// `None` for errors that should throw the output away.
+fn flushed_exit_code(error: &anyhow::Error) -> Option<i32> {
+    if error.downcast_ref::<TimedOut>().is_some() {
+        // we still want whatever we found before the deadline
+        return Some(TIMED_OUT_EXIT_CODE);
+    }
+
+    // for these, the output is what you need to fix things, so print it too
+    if error.downcast_ref::<TooManyMatches>().is_some() {
+        return Some(TOO_MANY_MATCHES_EXIT_CODE);
+    }
 
-        std::process::exit(1);
+    if error.downcast_ref::<query_test::Failed>().is_some() {
+        return Some(1);
     }
 
-    buffer.flush().expect("failed to flush buffer!");
+    None
 }
 
-fn try_main(args: Vec<String>, out: impl Write) -> Result<()> {
+/// Returns whether we found anything. Invocations that don't search always
+/// count as having found something.
+fn try_main(args: Vec<String>, out: impl Write + Send) -> Result<bool> {
     let invocation = Invocation::from_args(args)
         .context("couldn't get a valid configuration from the command-line options")?;
 
     match invocation {
         Invocation::DoQuery(query_opts) => {
-            do_query(query_opts, out).context("couldn't perform the query")
+            do_query(*query_opts, out).context("couldn't perform the query")
         }
         Invocation::ShowLanguages => {
-            show_languages(out).context("couldn't show the list of languages")
+            show_languages(out).context("couldn't show the list of languages")?;
+            Ok(true)
+        }
+        Invocation::ShowGrammarVersions => {
+            show_grammar_versions(out).context("couldn't show the grammar versions")?;
+            Ok(true)
         }
+        Invocation::ShowFields(language, node_type) => {
+            show_fields(language, &node_type, out).context("couldn't show the list of fields")?;
+            Ok(true)
+        }
+    }
+}
+
+fn show_fields(language: Language, node_type: &str, mut out: impl Write) -> Result<()> {
+    for field in language.field_names(node_type)? {
+        writeln!(out, "{}", field).context("couldn't print a field")?;
     }
+
+    Ok(())
 }
 
 fn show_languages(mut out: impl Write) -> Result<()> {
@@ -64,44 +151,621 @@ fn show_languages(mut out: impl Write) -> Result<()> {
     Ok(())
 }
 
-fn do_query(opts: QueryOpts, mut out: impl Write) -> Result<()> {
-    // You might think "why not use ParallelBridge here?" Well, the quick answer
-    // is that I benchmarked it and having things separated here and handling
-    // their own errors actually speeds up this part of the code by like 20%!
-    let items: Vec<ignore::DirEntry> =
-        find_files(&opts).context("had a problem while walking the filesystem")?;
+fn show_grammar_versions(mut out: impl Write) -> Result<()> {
+    for language in Language::all() {
+        writeln!(out, "{} {}", language, language.grammar_version()?)
+            .context("couldn't print a grammar version")?;
+    }
+
+    Ok(())
+}
+
+fn do_query(opts: QueryOpts, out: impl Write + Send) -> Result<bool> {
+    let deadline = Deadline::new(opts.deadline);
+
+    query_until(&opts, &deadline, out)
+}
+
+/// Run the query, stopping early once we're past `deadline`.
+fn query_until(opts: &QueryOpts, deadline: &Deadline, out: impl Write + Send) -> Result<bool> {
+    // we compile the queries while parsing arguments, so if we got here
+    // they're fine.
+    if opts.check {
+        return Ok(true);
+    }
+
+    let out: Box<dyn Write + Send + '_> = if opts.quiet {
+        Box::new(io::sink())
+    } else {
+        Box::new(out)
+    };
+
+    let found = if opts.threads == 0 {
+        run_query(opts, deadline, out)?
+    } else {
+        // we use our own pool instead of configuring the global one, which
+        // can only be set up once per process.
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(opts.threads)
+            .build()
+            .context("could not start worker threads")?
+            .install(|| run_query(opts, deadline, out))?
+    };
+
+    match opts.deadline {
+        Some(after) if deadline.was_passed() => return Err(TimedOut(after).into()),
+        _ => (),
+    }
+
+    match opts.max_allowed {
+        Some(allowed) if found > allowed => Err(TooManyMatches { found, allowed }.into()),
+        // these don't report what they found, so there's nothing to count
+        _ if opts.query_test.is_some() || opts.inventory || opts.replacement.is_some() => Ok(true),
+        _ => Ok(found > 0),
+    }
+}
+
+/// Tracks `--deadline`. Once we're past it we stop starting new work, but
+/// remember that we did so we can say the output is incomplete.
+struct Deadline {
+    at: Option<Instant>,
+    passed: AtomicBool,
+    #[cfg(test)]
+    checks_left: Option<AtomicUsize>,
+}
+
+impl Deadline {
+    fn new(after: Option<Duration>) -> Deadline {
+        Deadline {
+            at: after.map(|after| Instant::now() + after),
+            passed: AtomicBool::new(false),
+            #[cfg(test)]
+            checks_left: None,
+        }
+    }
+
+    /// A deadline that passes after `checks` calls to `passed`, so tests can
+    /// stop partway through without depending on how fast the machine is.
+    #[cfg(test)]
+    fn after_checks(checks: usize) -> Deadline {
+        Deadline {
+            at: None,
+            passed: AtomicBool::new(false),
+            checks_left: Some(AtomicUsize::new(checks)),
+        }
+    }
+
+    fn passed(&self) -> bool {
+        #[cfg(test)]
+        if let Some(checks_left) = &self.checks_left {
+            let counted = checks_left
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                    left.checked_sub(1)
+                })
+                .is_ok();
+            if !counted {
+                self.passed.store(true, Ordering::Relaxed);
+            }
+
+            return !counted;
+        }
+
+        match self.at {
+            Some(at) if Instant::now() >= at => {
+                self.passed.store(true, Ordering::Relaxed);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn was_passed(&self) -> bool {
+        self.passed.load(Ordering::Relaxed)
+    }
+}
+
+/// We ran out of time before searching everything. This gets its own exit
+/// code so CI scripts can tell an incomplete run apart from other problems.
+#[derive(Debug)]
+struct TimedOut(Duration);
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "stopped after the {}s deadline, so the output is incomplete",
+            self.0.as_secs_f64()
+        )
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// We found more matches than `--max-allowed` lets through.
+#[derive(Debug)]
+struct TooManyMatches {
+    found: usize,
+    allowed: usize,
+}
+
+impl fmt::Display for TooManyMatches {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "found {} matches, but only {} are allowed",
+            self.found, self.allowed
+        )
+    }
+}
+
+impl std::error::Error for TooManyMatches {}
 
+/// Returns how many matches we found, before any sampling.
+fn run_query(opts: &QueryOpts, deadline: &Deadline, mut out: impl Write + Send) -> Result<usize> {
     let chooser = opts
         .extractor_chooser()
         .context("couldn't construct a filetype matcher")?;
 
-    let mut extracted_files = items
-        .par_iter()
-        .filter_map(|entry| {
-            chooser
-                .extractor_for(entry)
-                .map(|extractor| (entry, extractor))
-        })
-        .map_init(Parser::new, |parser, (entry, extractor)| {
-            extractor
-                .extract_from_file(entry.path(), parser)
-                .with_context(|| {
-                    format!("could not extract matches from {}", entry.path().display())
+    if let Some(spec) = &opts.query_test {
+        query_test::run(spec, &chooser, out)?;
+        return Ok(0);
+    }
+
+    let socket = match &opts.socket {
+        Some(path) => Some(Socket::connect(path)?),
+        None => None,
+    };
+
+    let mut extracted_files = if !opts.merge.is_empty() {
+        let extracted_files = merge::read_results(&opts.merge)?;
+
+        if let Some(socket) = &socket {
+            for extracted_file in &extracted_files {
+                socket.send(extracted_file)?;
+            }
+        }
+
+        extracted_files
+    } else if let Some(language) = opts.stdin {
+        let extracted_files = extract_from_stdin(language, &chooser, io::stdin())?;
+
+        if let Some(socket) = &socket {
+            for extracted_file in &extracted_files {
+                socket.send(extracted_file)?;
+            }
+        }
+
+        extracted_files
+    } else if opts.staged {
+        let extracted_files = extract_staged(opts, &chooser, deadline)?;
+
+        if let Some(socket) = &socket {
+            for extracted_file in &extracted_files {
+                socket.send(extracted_file)?;
+            }
+        }
+
+        extracted_files
+    } else {
+        // You might think "why not use ParallelBridge here?" Well, the quick
+        // answer is that I benchmarked it and having things separated here and
+        // handling their own errors actually speeds up this part of the code by
+        // like 20%!
+        let items: Vec<ignore::DirEntry> =
+            find_files(opts, deadline).context("had a problem while walking the filesystem")?;
+
+        let targets = targets(opts, &items, &chooser)?;
+
+        if opts.inventory {
+            write_inventory(opts, &targets, deadline, out)?;
+            return Ok(0);
+        }
+
+        if let Some(replacement) = &opts.replacement {
+            do_replace(opts, &targets, replacement, deadline, out)?;
+            return Ok(0);
+        }
+
+        let cache = match &opts.cache {
+            Some((dir, args)) => Some(Cache::load(dir, args).context("could not load the cache")?),
+            None => None,
+        };
+
+        let (time_sender, time_receiver) = channel::unbounded();
+        let extract = |parser: &mut Parser, path, extractor| {
+            extract_target(
+                opts,
+                deadline,
+                cache.as_ref(),
+                socket.as_ref(),
+                &time_sender,
+                path,
+                extractor,
+                parser,
+            )
+        };
+
+        if opts.quiet {
+            // all anyone will see is the exit code, so we can stop as soon
+            // as anything matches.
+            return match targets
+                .par_iter()
+                .map_init(Parser::new, |parser, (path, extractor)| {
+                    extract(parser, path, extractor)
                 })
-        })
-        .filter_map(|result_containing_option| match result_containing_option {
-            Ok(None) => None,
-            Ok(Some(extraction)) => Some(Ok(extraction)),
-            Err(err) => Some(Err(err)),
-        })
-        .collect::<Result<Vec<extractor::ExtractedFile>>>()
-        .context("couldn't extract matches from files")?;
+                .find_map_any(|result| match result {
+                    Ok(None) => None,
+                    Ok(Some(_)) => Some(Ok(1)),
+                    Err(err) => Some(Err(err)),
+                }) {
+                Some(result) => result.context("couldn't extract matches from files"),
+                None => Ok(0),
+            };
+        }
 
-    if opts.sort {
+        let streamed = if can_stream(opts) && socket.is_none() {
+            Some(stream_files(opts, &targets, extract, &mut out)?)
+        } else {
+            None
+        };
+
+        let extracted_files = match streamed {
+            Some(_) => Vec::new(),
+            None => targets
+                .par_iter()
+                .map_init(Parser::new, |parser, (path, extractor)| {
+                    extract(parser, path, extractor)
+                })
+                .filter_map(|result_containing_option| match result_containing_option {
+                    Ok(None) => None,
+                    Ok(Some(extraction)) => Some(Ok(extraction)),
+                    Err(err) => Some(Err(err)),
+                })
+                .collect::<Result<Vec<extractor::ExtractedFile>>>()
+                .context("couldn't extract matches from files")?,
+        };
+
+        if let Some(cache) = cache {
+            cache.save().context("could not save the cache")?;
+        }
+
+        drop(time_sender);
+        if opts.time_files {
+            write_file_times(time_receiver.into_iter().collect(), io::stderr())
+                .context("could not write file times")?;
+        }
+
+        if let Some(found) = streamed {
+            return Ok(found);
+        }
+
+        extracted_files
+    };
+
+    let found = extracted_files
+        .iter()
+        .map(|extracted_file| extracted_file.matches_len())
+        .sum();
+
+    // everything we found has already gone to the socket
+    if socket.is_some() {
+        return Ok(found);
+    }
+
+    if let Some(name) = &opts.unique_capture {
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for extracted_file in &extracted_files {
+            for text in extracted_file.captured_texts(name) {
+                *counts.entry(text).or_default() += 1;
+            }
+        }
+
+        for (text, count) in counts {
+            writeln!(out, "{} {}", count, text).context("could not write line")?;
+        }
+
+        return Ok(found);
+    }
+
+    if let Some((key, value)) = &opts.key_value {
+        let mut table: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for extracted_file in &extracted_files {
+            for (found_key, found_value) in extracted_file.key_values(key, value) {
+                table.entry(found_key).or_default().push(found_value);
+            }
+        }
+
+        let table: BTreeMap<&str, serde_json::Value> = table
+            .into_iter()
+            .map(|(key, mut values)| {
+                values.sort_unstable();
+                values.dedup();
+
+                let value = match values.as_slice() {
+                    [only] => serde_json::Value::from(*only),
+                    _ => serde_json::Value::from(values),
+                };
+
+                (key, value)
+            })
+            .collect();
+
+        if let QueryFormat::PrettyJson = opts.format {
+            if opts.color {
+                color_json::to_writer_pretty(&mut out, &table)
+            } else {
+                serde_json::to_writer_pretty(&mut out, &table)
+            }
+        } else {
+            serde_json::to_writer(&mut out, &table)
+        }
+        .context("could not write JSON output")?;
+
+        return Ok(found);
+    }
+
+    if let Some(size) = opts.sample {
+        // we need the matches in the same order every time for a seed to
+        // give the same sample.
+        extracted_files.sort();
+
+        let seed = opts.seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_nanos() as u64)
+                .unwrap_or_default()
+        });
+
+        let total = extracted_files.iter().map(|file| file.matches_len()).sum();
+        let chosen: HashSet<usize> = sample::reservoir(total, size, &mut sample::Rng::new(seed))
+            .into_iter()
+            .collect();
+
+        let mut offset = 0;
+        for extracted_file in &mut extracted_files {
+            let base = offset;
+            offset += extracted_file.matches_len();
+            extracted_file.retain_matches(|index| chosen.contains(&(base + index)));
+        }
+
+        extracted_files.retain(|extracted_file| extracted_file.matches_len() > 0);
+    } else if opts.sort {
         extracted_files.sort()
     }
 
+    if opts.reverse {
+        extracted_files.reverse();
+        for extracted_file in &mut extracted_files {
+            extracted_file.reverse_matches();
+        }
+    }
+
+    if let Some(dir) = &opts.output_per_file {
+        for extracted_file in &extracted_files {
+            let path = output_path(dir, extracted_file.path(), &opts.format);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("could not create {}", parent.display()))?;
+            }
+
+            let file = fs::File::create(&path)
+                .with_context(|| format!("could not create {}", path.display()))?;
+            let mut writer = BufWriter::new(file);
+
+            write_files(opts, std::slice::from_ref(extracted_file), &mut writer)
+                .with_context(|| format!("could not write {}", path.display()))?;
+            writer
+                .flush()
+                .with_context(|| format!("could not write {}", path.display()))?;
+        }
+
+        return Ok(found);
+    }
+
+    write_files(opts, &extracted_files, out)?;
+
+    Ok(found)
+}
+
+fn write_files(
+    opts: &QueryOpts,
+    extracted_files: &[extractor::ExtractedFile],
+    mut out: impl Write,
+) -> Result<()> {
+    // null-file-list output is already a list of files with matches
+    if opts.files_with_matches && !matches!(opts.format, QueryFormat::NullFileList) {
+        let mut seen = HashSet::new();
+        let paths: Vec<&str> = extracted_files
+            .iter()
+            .map(|extracted_file| extracted_file.filename())
+            .filter(|path| seen.insert(*path))
+            .collect();
+
+        match opts.format {
+            QueryFormat::Json => {
+                serde_json::to_writer(out, &paths).context("could not write JSON output")?
+            }
+
+            QueryFormat::PrettyJson => if opts.color {
+                color_json::to_writer_pretty(out, &paths)
+            } else {
+                serde_json::to_writer_pretty(out, &paths)
+            }
+            .context("could not write JSON output")?,
+
+            QueryFormat::JsonLines => {
+                for path in paths {
+                    writeln!(
+                        out,
+                        "{}",
+                        serde_json::to_string(path).context("could not write JSON output")?
+                    )
+                    .context("could not write line")?;
+                }
+            }
+
+            _ => {
+                for path in paths {
+                    writeln!(out, "{}", path).context("could not write path")?;
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    if opts.file_kinds {
+        match opts.format {
+            QueryFormat::Json | QueryFormat::PrettyJson => {
+                let table: BTreeMap<&str, BTreeSet<&str>> = extracted_files
+                    .iter()
+                    .map(|extracted_file| (extracted_file.filename(), extracted_file.kinds()))
+                    .collect();
+
+                if let QueryFormat::PrettyJson = opts.format {
+                    if opts.color {
+                        color_json::to_writer_pretty(out, &table)
+                    } else {
+                        serde_json::to_writer_pretty(out, &table)
+                    }
+                } else {
+                    serde_json::to_writer(out, &table)
+                }
+                .context("could not write JSON output")?
+            }
+
+            QueryFormat::JsonLines => {
+                for extracted_file in extracted_files {
+                    writeln!(
+                        out,
+                        "{}",
+                        serde_json::json!({
+                            "file": extracted_file.filename(),
+                            "kinds": extracted_file.kinds(),
+                        })
+                    )
+                    .context("could not write line")?;
+                }
+            }
+
+            _ => {
+                for extracted_file in extracted_files {
+                    writeln!(
+                        out,
+                        "{}:{}",
+                        extracted_file.filename(),
+                        extracted_file
+                            .kinds()
+                            .into_iter()
+                            .collect::<Vec<_>>()
+                            .join(" ")
+                    )
+                    .context("could not write kinds")?;
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    if opts.count {
+        let mut total = 0;
+
+        for extracted_file in extracted_files {
+            total += extracted_file.matches_len();
+            writeln!(
+                out,
+                "{}:{}",
+                extracted_file.filename(),
+                extracted_file.matches_len()
+            )
+            .context("could not write count")?;
+        }
+
+        writeln!(out, "total:{}", total).context("could not write total")?;
+
+        return Ok(());
+    }
+
+    if let Some(depth) = opts.group_by_dir {
+        let mut counts: BTreeMap<PathBuf, usize> = BTreeMap::new();
+        for extracted_file in extracted_files {
+            let path = extracted_file
+                .path()
+                .unwrap_or_else(|| Path::new("NO FILE"));
+
+            *counts
+                .entry(group_dir(&opts.paths, path, depth))
+                .or_default() += extracted_file.matches_len();
+        }
+
+        for (dir, count) in &counts {
+            writeln!(out, "{}:{}", dir.display(), count).context("could not write count")?;
+        }
+
+        writeln!(out, "total:{}", counts.values().sum::<usize>())
+            .context("could not write total")?;
+
+        return Ok(());
+    }
+
+    if opts.coverage {
+        let (mut total_covered, mut total_len) = (0, 0);
+
+        for extracted_file in extracted_files {
+            let (covered, len) = extracted_file.covered_bytes();
+            total_covered += covered;
+            total_len += len;
+
+            writeln!(
+                out,
+                "{}:{:.2}%",
+                extracted_file.filename(),
+                percent(covered, len)
+            )
+            .context("could not write coverage")?;
+        }
+
+        writeln!(out, "total:{:.2}%", percent(total_covered, total_len))
+            .context("could not write total")?;
+
+        return Ok(());
+    }
+
     match opts.format {
+        QueryFormat::Lines if opts.line_number && opts.color => {
+            for extracted_file in extracted_files {
+                write!(out, "{}", extracted_file.colored_line_numbered())
+                    .context("could not write lines")?;
+            }
+        }
+
+        QueryFormat::Lines if opts.line_number => {
+            for extracted_file in extracted_files {
+                write!(out, "{}", extracted_file.line_numbered())
+                    .context("could not write lines")?;
+            }
+        }
+
+        QueryFormat::Lines if opts.collapse_lines => {
+            for extracted_file in extracted_files {
+                write!(out, "{}", extracted_file.collapsed_lines())
+                    .context("could not write lines")?;
+            }
+        }
+
+        QueryFormat::Lines if opts.context_lines => {
+            for extracted_file in extracted_files {
+                write!(out, "{}", extracted_file.in_context()).context("could not write lines")?;
+            }
+        }
+
+        QueryFormat::Lines if opts.color => {
+            for extracted_file in extracted_files {
+                write!(out, "{}", extracted_file.colored()).context("could not write lines")?;
+            }
+        }
+
         QueryFormat::Lines => {
             for extracted_file in extracted_files {
                 write!(out, "{}", extracted_file).context("could not write lines")?;
@@ -109,7 +773,41 @@ fn do_query(opts: QueryOpts, mut out: impl Write) -> Result<()> {
         }
 
         QueryFormat::Json => {
-            serde_json::to_writer(out, &extracted_files).context("could not write JSON output")?;
+            serde_json::to_writer(out, extracted_files).context("could not write JSON output")?;
+        }
+
+        QueryFormat::Msgpack => {
+            rmp_serde::encode::write_named(&mut out, extracted_files)
+                .context("could not write msgpack output")?;
+        }
+
+        QueryFormat::JsonLines if opts.jsonl_with_summary => {
+            let mut matches = 0;
+            let mut files = HashSet::new();
+
+            for extracted_file in extracted_files {
+                matches += extracted_file.matches_len();
+                files.insert(extracted_file.path());
+
+                writeln!(
+                    out,
+                    "{}",
+                    serde_json::to_string(&Record::File(extracted_file))
+                        .context("could not write JSON output")?
+                )
+                .context("could not write line")?;
+            }
+
+            writeln!(
+                out,
+                "{}",
+                serde_json::to_string(&Record::Summary {
+                    files: files.len(),
+                    matches,
+                })
+                .context("could not write JSON summary")?
+            )
+            .context("could not write line")?;
         }
 
         QueryFormat::JsonLines => {
@@ -125,126 +823,3027 @@ fn do_query(opts: QueryOpts, mut out: impl Write) -> Result<()> {
         }
 
         QueryFormat::PrettyJson => {
-            serde_json::to_writer_pretty(out, &extracted_files)
-                .context("could not write JSON output")?;
+            if opts.color {
+                color_json::to_writer_pretty(out, extracted_files)
+            } else {
+                serde_json::to_writer_pretty(out, extracted_files)
+            }
+            .context("could not write JSON output")?;
+        }
+
+        QueryFormat::Gnu => {
+            for extracted_file in extracted_files {
+                write!(out, "{}", extracted_file.gnu()).context("could not write lines")?;
+            }
         }
+
+        QueryFormat::NullFileList => {
+            let mut seen = HashSet::new();
+
+            for path in extracted_files
+                .iter()
+                .filter_map(|extracted_file| extracted_file.path())
+            {
+                if seen.insert(path) {
+                    write_path_bytes(&mut out, path).context("could not write path")?;
+                    out.write_all(b"\0").context("could not write path")?;
+                }
+            }
+        }
+
+        QueryFormat::Sarif => {
+            let results: Vec<serde_json::Value> = extracted_files
+                .iter()
+                .flat_map(|extracted_file| extracted_file.sarif_results())
+                .collect();
+
+            let rule_ids: BTreeSet<&str> = results
+                .iter()
+                .filter_map(|result| result["ruleId"].as_str())
+                .collect();
+
+            let document = serde_json::json!({
+                "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+                "version": "2.1.0",
+                "runs": [{
+                    "tool": {
+                        "driver": {
+                            "name": "tree-grepper",
+                            "version": env!("CARGO_PKG_VERSION"),
+                            "informationUri": "https://github.com/BrianHicks/tree-grepper",
+                            "rules": rule_ids
+                                .iter()
+                                .map(|id| serde_json::json!({ "id": id }))
+                                .collect::<Vec<_>>(),
+                        },
+                    },
+                    "results": results,
+                }],
+            });
+
+            serde_json::to_writer(out, &document).context("could not write SARIF output")?;
+        }
+
+        QueryFormat::Csv => {
+            writeln!(out, "{}", extractor::CSV_HEADER).context("could not write header")?;
+
+            for extracted_file in extracted_files {
+                write!(out, "{}", extracted_file.csv()).context("could not write rows")?;
+            }
+        }
+
+        QueryFormat::Html => {
+            write!(out, "{}", extractor::HtmlPage(extracted_files))
+                .context("could not write HTML output")?;
+        }
+
+        QueryFormat::Patch => bail!("patches only come from --replace, so we should never have gotten here. This indicates an internal error and you should report it!"),
+
+        QueryFormat::Spans => {
+            for extracted_file in extracted_files {
+                write!(out, "{}", extracted_file.spans()).context("could not write spans")?;
+            }
+        }
+
+        QueryFormat::Ctags => {
+            // editors binary search tags files, so they have to be sorted
+            let mut tags: Vec<String> = extracted_files
+                .iter()
+                .flat_map(|extracted_file| extracted_file.ctags())
+                .collect();
+            tags.sort();
+
+            for tag in tags {
+                writeln!(out, "{}", tag).context("could not write tag")?;
+            }
+        }
+
+        QueryFormat::AnnotatedTree => {
+            for extracted_file in extracted_files {
+                write!(out, "{}", extracted_file.annotated_tree())
+                    .context("could not write tree")?;
+            }
+        }
+
+        QueryFormat::Folded => {
+            let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+            for extracted_file in extracted_files {
+                for stack in extracted_file.folded_stacks() {
+                    *counts.entry(stack).or_default() += 1;
+                }
+            }
+
+            for (stack, count) in counts {
+                writeln!(out, "{} {}", stack, count).context("could not write line")?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Pair up every file we're going to search with the extractor to search it
+/// with. Most of these come from the walk, but files given with `--as` skip
+/// filetype detection and use the extractor for the language they were given.
+fn targets<'a, 'query>(
+    opts: &'a QueryOpts,
+    items: &'a [ignore::DirEntry],
+    chooser: &'query ExtractorChooser,
+) -> Result<Vec<(&'a Path, &'query Extractor)>> {
+    let mut targets: Vec<(&Path, &Extractor)> = items
+        .iter()
+        .filter(|entry| {
+            !opts
+                .forced_languages
+                .iter()
+                .any(|(_, path)| path == entry.path())
+        })
+        .filter_map(|entry| {
+            chooser
+                .extractor_for(entry)
+                .or_else(|| {
+                    if opts.search_compressed {
+                        chooser.extractor_for_compressed(entry)
+                    } else {
+                        None
+                    }
+                })
+                .map(|extractor| (entry.path(), extractor))
+        })
+        .collect();
+
+    for (language, path) in &opts.forced_languages {
+        match chooser.extractor_for_language(*language) {
+            Some(extractor) => targets.push((path, extractor)),
+            None => bail!(
+                "{} was given with --as {}, but there's no query for {}",
+                path.display(),
+                language,
+                language
+            ),
+        }
+    }
+
+    Ok(targets)
+}
+
+/// Where `--output-per-file` should put the output for a source file. This
+/// mirrors the source layout under `dir`, leaving out anything (like `..` or
+/// a leading `/`) that would take us outside of it.
+fn output_path(dir: &Path, source: Option<&Path>, format: &QueryFormat) -> PathBuf {
+    let mut path = dir.to_path_buf();
+
+    for component in source.unwrap_or_else(|| Path::new("NO FILE")).components() {
+        if let Component::Normal(part) = component {
+            path.push(part)
+        }
+    }
+
+    let mut name = path.into_os_string();
+    name.push(match format {
+        QueryFormat::Json | QueryFormat::JsonLines | QueryFormat::PrettyJson => ".json",
+        QueryFormat::Sarif => ".sarif",
+        QueryFormat::Csv => ".csv",
+        QueryFormat::Html => ".html",
+        QueryFormat::Msgpack => ".msgpack",
+        _ => ".txt",
+    });
+
+    PathBuf::from(name)
+}
+
+fn do_replace(
+    opts: &QueryOpts,
+    targets: &[(&Path, &Extractor)],
+    replacement: &Replacement,
+    deadline: &Deadline,
+    mut out: impl Write,
+) -> Result<()> {
+    if opts.atomic {
+        return do_atomic_replace(opts, targets, replacement, out);
+    }
+
+    let patch = matches!(opts.format, QueryFormat::Patch);
+    if opts.dry_run || patch {
+        let mut diffs =
+            replace_targets(targets, replacement, |path, extractor, parser, counter| {
+                if deadline.passed() {
+                    return Ok(None);
+                }
+
+                if patch {
+                    replacement.patch_file(path, extractor, parser, counter)
+                } else {
+                    replacement.diff_file(path, extractor, parser, counter)
+                }
+                .map(|diff| diff.map(|diff| (path, diff)))
+                .with_context(|| format!("could not replace matches in {}", path.display()))
+            })
+            .context("couldn't replace matches in files")?;
+
+        if opts.sort {
+            diffs.sort()
+        }
+
+        for (_, diff) in diffs {
+            write!(out, "{}", diff).context("could not write diff")?;
+        }
+
+        return Ok(());
+    }
+
+    let mut changed = replace_targets(targets, replacement, |path, extractor, parser, counter| {
+        if deadline.passed() {
+            return Ok(None);
+        }
+
+        replacement
+            .rewrite_file(path, extractor, parser, counter)
+            .map(|changed| if changed { Some(path) } else { None })
+            .with_context(|| format!("could not replace matches in {}", path.display()))
+    })
+    .context("couldn't replace matches in files")?;
+
+    if opts.sort {
+        changed.sort()
+    }
+
+    for path in changed {
+        writeln!(out, "{}", path.display()).context("could not write changed path")?;
+    }
+
+    Ok(())
+}
+
+/// Call `replace` on every target in parallel, keeping whatever it returns.
+/// If the replacement's counter carries on from file to file, we have to go
+/// one file at a time in path order instead, passing the counter along.
+fn replace_targets<'a, T, F>(
+    targets: &[(&'a Path, &Extractor)],
+    replacement: &Replacement,
+    replace: F,
+) -> Result<Vec<T>>
+where
+    T: Send,
+    F: Fn(&'a Path, &Extractor, &mut Parser, &mut usize) -> Result<Option<T>> + Sync,
+{
+    if replacement.counts_across_files() {
+        let mut targets = targets.to_vec();
+        targets.sort_by_key(|(path, _)| *path);
+
+        let mut parser = Parser::new();
+        let mut counter = 0;
+        let mut out = Vec::new();
+        for (path, extractor) in targets {
+            if let Some(result) = replace(path, extractor, &mut parser, &mut counter)? {
+                out.push(result)
+            }
+        }
+
+        return Ok(out);
+    }
+
+    targets
+        .par_iter()
+        .map_init(Parser::new, |parser, (path, extractor)| {
+            replace(path, extractor, parser, &mut 0)
+        })
+        .filter_map(|result_containing_option| match result_containing_option {
+            Ok(None) => None,
+            Ok(Some(result)) => Some(Ok(result)),
+            Err(err) => Some(Err(err)),
+        })
+        .collect()
+}
+
+/// Like `do_replace`, but either every file gets rewritten or none do. We
+/// work out every rewrite in memory first, and only start writing once we
+/// know none of them introduce parse errors.
+fn do_atomic_replace(
+    opts: &QueryOpts,
+    targets: &[(&Path, &Extractor)],
+    replacement: &Replacement,
+    mut out: impl Write,
+) -> Result<()> {
+    let mut rewrites = replace_targets(targets, replacement, |path, extractor, parser, counter| {
+        replacement
+            .checked_rewrite_file(path, extractor, parser, counter)
+            .map(|rewritten| rewritten.map(|rewritten| (path, rewritten)))
+            .with_context(|| format!("could not replace matches in {}", path.display()))
+    })
+    .context("couldn't replace matches in files, so we didn't change any")?;
+
+    if opts.sort {
+        rewrites.sort()
+    }
+
+    for (path, rewritten) in rewrites {
+        fs::write(path, rewritten)
+            .with_context(|| format!("could not write {}", path.display()))?;
+        writeln!(out, "{}", path.display()).context("could not write changed path")?;
+    }
+
+    Ok(())
+}
+
+/// Extract matches from one of the files we found while walking, keeping
+/// track of whatever the options ask us to along the way.
+#[allow(clippy::too_many_arguments)]
+fn extract_target<'path, 'query>(
+    opts: &QueryOpts,
+    deadline: &Deadline,
+    cache: Option<&Cache>,
+    socket: Option<&Socket>,
+    time_sender: &channel::Sender<(&'path Path, Duration)>,
+    path: &'path Path,
+    extractor: &'query Extractor,
+    parser: &mut Parser,
+) -> Result<Option<extractor::ExtractedFile<'query>>> {
+    if deadline.passed() {
+        return Ok(None);
+    }
+
+    let started = Instant::now();
+    let extracted = match cache {
+        Some(cache) => extract_with_cache(cache, extractor, path, parser),
+        None => extractor.extract_from_file(path, parser),
+    }
+    .with_context(|| format!("could not extract matches from {}", path.display()));
+
+    if opts.time_files {
+        // the receiver lives until after we're done here, so this can't
+        // fail.
+        let _ = time_sender.send((path, started.elapsed()));
+    }
+
+    if let (Some(socket), Ok(Some(extracted_file))) = (socket, &extracted) {
+        socket.send(extracted_file)?;
+    }
+
+    extracted
+}
+
+/// The directory to count the file at `path` under with `--group-by-dir`:
+/// the path we searched that it's in, plus up to `depth` more directories.
+fn group_dir(roots: &[PathBuf], path: &Path, depth: usize) -> PathBuf {
+    let (root, rest) = roots
+        .iter()
+        .find_map(|root| Some((root.as_path(), path.strip_prefix(root).ok()?)))
+        .unwrap_or((Path::new(""), path));
+
+    let dirs = rest
+        .parent()
+        .map(|parent| parent.components().take(depth).collect::<PathBuf>())
+        .unwrap_or_default();
+
+    // `root.join` would give us `./src` when searching `.`, and `root/` for
+    // files right in the root.
+    let dir = if root == Path::new(".") {
+        dirs
+    } else if dirs.as_os_str().is_empty() {
+        root.to_path_buf()
+    } else {
+        root.join(dirs)
+    };
+
+    if dir.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        dir
+    }
+}
+
+/// Can we write each file's matches as soon as we have them? We can't if
+/// we need to see everything before writing anything: to sort, sample, or
+/// total things up, say.
+fn can_stream(opts: &QueryOpts) -> bool {
+    matches!(
+        opts.format,
+        QueryFormat::Lines | QueryFormat::Json | QueryFormat::JsonLines
+    ) && !opts.sort
+        && !opts.reverse
+        && !opts.jsonl_with_summary
+        && !opts.files_with_matches
+        && !opts.file_kinds
+        && !opts.count
+        && opts.group_by_dir.is_none()
+        && !opts.coverage
+        && opts.sample.is_none()
+        && opts.unique_capture.is_none()
+        && opts.key_value.is_none()
+        && opts.output_per_file.is_none()
+}
+
+/// Extract matches from every target in parallel, writing them from another
+/// thread as soon as we have them. This is much nicer than waiting for every
+/// file to be done for big searches (or when piping into `head`.) Files come
+/// out in the same order as if we'd waited. Returns how many matches we
+/// found.
+fn stream_files<'path, 'query, F>(
+    opts: &QueryOpts,
+    targets: &[(&'path Path, &'query Extractor)],
+    extract: F,
+    out: impl Write + Send,
+) -> Result<usize>
+where
+    F: Fn(
+            &mut Parser,
+            &'path Path,
+            &'query Extractor,
+        ) -> Result<Option<extractor::ExtractedFile<'query>>>
+        + Sync,
+{
+    let (sender, receiver) = channel::unbounded();
+
+    crossbeam::scope(|scope| {
+        let writer = scope.spawn(move |_| write_streamed(opts, receiver, out));
+
+        let extracted = targets.par_iter().enumerate().try_for_each_init(
+            || (Parser::new(), sender.clone()),
+            |(parser, sender), (index, (path, extractor))| {
+                let extracted = extract(parser, path, extractor)?;
+
+                sender
+                    .send((index, extracted))
+                    .map_err(|_| anyhow::anyhow!("stopped writing output"))
+            },
+        );
+        drop(sender);
+
+        // if writing failed (say the pipe we were writing to closed) the
+        // workers fail too, but the writer's error is the interesting one.
+        let found = match writer.join() {
+            Ok(result) => result?,
+            Err(_) => bail!("the thread writing output panicked"),
+        };
+        extracted.context("couldn't extract matches from files")?;
+
+        Ok(found)
+    })
+    .unwrap_or_else(|_| bail!("a thread panicked while extracting matches"))
+}
+
+/// Write files as `stream_files` extracts them, in target order.
+fn write_streamed(
+    opts: &QueryOpts,
+    receiver: channel::Receiver<(usize, Option<extractor::ExtractedFile>)>,
+    mut out: impl Write,
+) -> Result<usize> {
+    let mut found = 0;
+    let mut wrote_any = false;
+    // files that finished before some file ahead of them in the order
+    let mut waiting = BTreeMap::new();
+    let mut next = 0;
+
+    if let QueryFormat::Json = opts.format {
+        write!(out, "[").context("could not write JSON output")?;
+    }
+
+    for (index, extracted) in receiver {
+        waiting.insert(index, extracted);
+
+        while let Some(extracted) = waiting.remove(&next) {
+            next += 1;
+
+            let extracted_file = match extracted {
+                Some(extracted_file) => extracted_file,
+                None => continue,
+            };
+            found += extracted_file.matches_len();
+
+            if let QueryFormat::Json = opts.format {
+                if wrote_any {
+                    write!(out, ",").context("could not write JSON output")?;
+                }
+                serde_json::to_writer(&mut out, &extracted_file)
+                    .context("could not write JSON output")?;
+            } else {
+                write_files(opts, std::slice::from_ref(&extracted_file), &mut out)?;
+            }
+
+            wrote_any = true;
+        }
+    }
+
+    if let QueryFormat::Json = opts.format {
+        write!(out, "]").context("could not write JSON output")?;
+    }
+
+    Ok(found)
+}
+
+/// Like `Extractor::extract_from_file`, but skipping files that didn't match
+/// last time and haven't changed since, and remembering which files don't
+/// match now.
+fn extract_with_cache<'query>(
+    cache: &Cache,
+    extractor: &'query Extractor,
+    path: &Path,
+    parser: &mut Parser,
+) -> Result<Option<extractor::ExtractedFile<'query>>> {
+    let source = extractor::read_source(path)?;
+    if cache.is_unmatched(path, &source) {
+        return Ok(None);
+    }
+
+    let extracted = extractor.extract_from_text(Some(path), &source, parser)?;
+    cache.record(path, &source, extracted.is_some());
+
+    Ok(extracted)
+}
+
+/// Search everything on `input` as `language` for `--stdin`.
+fn extract_from_stdin<'query>(
+    language: Language,
+    chooser: &'query ExtractorChooser,
+    mut input: impl Read,
+) -> Result<Vec<extractor::ExtractedFile<'query>>> {
+    let extractor = match chooser.extractor_for_language(language) {
+        Some(extractor) => extractor,
+        None => bail!(
+            "--stdin {} was given, but there's no query for {}",
+            language,
+            language
+        ),
+    };
+
+    let mut source = Vec::new();
+    input
+        .read_to_end(&mut source)
+        .context("could not read stdin")?;
+
+    Ok(extractor
+        .extract_from_text(Some(Path::new("<stdin>")), &source, &mut Parser::new())
+        .context("could not extract matches from stdin")?
+        .into_iter()
+        .collect())
+}
+
+/// Print the files that parse cleanly in each language for `--no-query`.
+fn write_inventory(
+    opts: &QueryOpts,
+    targets: &[(&Path, &Extractor)],
+    deadline: &Deadline,
+    mut out: impl Write,
+) -> Result<()> {
+    let parsed = targets
+        .par_iter()
+        .map_init(Parser::new, |parser, (path, extractor)| {
+            if deadline.passed() {
+                return Ok(None);
+            }
+
+            let parses = extractor
+                .parses_cleanly(path, parser)
+                .with_context(|| format!("could not parse {}", path.display()))?;
+
+            Ok(if parses {
+                Some((extractor.language().to_string(), path.to_string_lossy()))
+            } else {
+                None
+            })
+        })
+        .filter_map(|result_containing_option| match result_containing_option {
+            Ok(None) => None,
+            Ok(Some(parsed)) => Some(Ok(parsed)),
+            Err(err) => Some(Err(err)),
+        })
+        .collect::<Result<Vec<_>>>()
+        .context("couldn't parse files")?;
+
+    let mut inventory: BTreeMap<String, Vec<_>> = BTreeMap::new();
+    for (language, path) in parsed {
+        inventory.entry(language).or_default().push(path);
+    }
+    for paths in inventory.values_mut() {
+        paths.sort();
+    }
+
+    match opts.format {
+        QueryFormat::Json | QueryFormat::JsonLines => {
+            serde_json::to_writer(&mut out, &inventory).context("could not write JSON output")?
+        }
+
+        QueryFormat::PrettyJson => if opts.color {
+            color_json::to_writer_pretty(&mut out, &inventory)
+        } else {
+            serde_json::to_writer_pretty(&mut out, &inventory)
+        }
+        .context("could not write JSON output")?,
+
+        _ => {
+            for (language, paths) in inventory {
+                writeln!(out, "{} {}", language, paths.len()).context("could not write count")?;
+
+                for path in paths {
+                    writeln!(out, "  {}", path).context("could not write path")?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Search the staged version of every file under `opts.paths` for
+/// `--staged`, reporting paths relative to the root of the repository.
+fn extract_staged<'query>(
+    opts: &QueryOpts,
+    chooser: &'query ExtractorChooser,
+    deadline: &Deadline,
+) -> Result<Vec<extractor::ExtractedFile<'query>>> {
+    let mut files = Vec::new();
+    for path in &opts.paths {
+        files.extend(
+            staged::staged_files(path)
+                .with_context(|| format!("could not read staged files in {}", path.display()))?,
+        );
+    }
+
+    files
+        .par_iter()
+        .filter_map(|(path, source)| {
+            chooser
+                .extractor_for_name(path)
+                .map(|extractor| (path, source, extractor))
+        })
+        .map_init(Parser::new, |parser, (path, source, extractor)| {
+            if deadline.passed() {
+                return Ok(None);
+            }
+
+            extractor
+                .extract_from_text(Some(path), source, parser)
+                .with_context(|| format!("could not extract matches from {}", path.display()))
+        })
+        .filter_map(|result_containing_option| match result_containing_option {
+            Ok(None) => None,
+            Ok(Some(extraction)) => Some(Ok(extraction)),
+            Err(err) => Some(Err(err)),
+        })
+        .collect::<Result<Vec<extractor::ExtractedFile>>>()
+        .context("couldn't extract matches from staged files")
+}
+
+/// Write a path exactly as the OS has it, since tools reading NUL-delimited
+/// paths want to be able to open them.
+#[cfg(unix)]
+fn write_path_bytes(out: &mut impl Write, path: &Path) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    out.write_all(path.as_os_str().as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_path_bytes(out: &mut impl Write, path: &Path) -> io::Result<()> {
+    out.write_all(path.to_string_lossy().as_bytes())
+}
+
+/// What percentage `part` is of `whole`, for `--coverage`. An empty file has
+/// nothing to cover, so we say it's 0% covered.
+fn percent(part: usize, whole: usize) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / whole as f64
+    }
+}
+
+/// Print how long each file took for `--time-files`, slowest first.
+fn write_file_times(mut times: Vec<(&Path, Duration)>, mut out: impl Write) -> Result<()> {
+    times.sort_by(|(a_path, a_time), (b_path, b_time)| {
+        b_time.cmp(a_time).then_with(|| a_path.cmp(b_path))
+    });
+
+    for (path, time) in times {
+        writeln!(
+            out,
+            "{:.3}ms {}",
+            time.as_secs_f64() * 1000.0,
+            path.display()
+        )
+        .context("could not write line")?;
+    }
+
+    Ok(())
+}
+
+/// A line of `--jsonl-with-summary` output. The `type` tag lets consumers tell
+/// records apart, and leaves room for new kinds of records later.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Record<'file, 'query> {
+    File(&'file extractor::ExtractedFile<'query>),
+    Summary { files: usize, matches: usize },
+}
+
+/// Files and directories that mark the root of a project.
+const PROJECT_ROOT_MARKERS: &[&str] = &[
+    ".git",
+    ".hg",
+    ".svn",
+    "Cargo.toml",
+    "package.json",
+    "elm.json",
+    "mix.exs",
+    "composer.json",
+    "Gemfile",
+    "stack.yaml",
+    "CMakeLists.txt",
+];
+
+/// Find the closest directory at or above `start` that looks like a project
+/// root, as a path relative to `start` (like `..` or `../..`.)
+fn find_project_root(start: &Path) -> Option<PathBuf> {
+    let start = start.canonicalize().ok()?;
+
+    start
+        .ancestors()
+        .position(|dir| {
+            PROJECT_ROOT_MARKERS
+                .iter()
+                .any(|marker| dir.join(marker).exists())
+        })
+        .map(|depth| match depth {
+            0 => PathBuf::from("."),
+            _ => (0..depth).map(|_| "..").collect(),
+        })
+}
+
+fn find_files(opts: &QueryOpts, deadline: &Deadline) -> Result<Vec<ignore::DirEntry>> {
+    let project_root = if opts.project_root {
+        Some(find_project_root(Path::new(".")).unwrap_or_else(|| PathBuf::from(".")))
+    } else {
+        None
+    };
+
+    let paths = match &project_root {
+        Some(root) => std::slice::from_ref(root),
+        None => &opts.paths,
+    };
+
+    let mut builder = match paths.split_first() {
+        Some((first, rest)) => {
+            let mut builder = ignore::WalkBuilder::new(first);
+            for path in rest {
+                builder.add(path);
+            }
+
+            builder
+        }
+        // it's fine not to walk anything if we were given files with --as
+        None if !opts.forced_languages.is_empty() => return Ok(Vec::new()),
+        None => bail!("I need at least one file or directory to walk!"),
+    };
+
+    // if the window reaches back before the epoch, everything is recent
+    // enough (for --modified-since) or nothing is old enough (for
+    // --modified-until.)
+    let now = SystemTime::now();
+    let since = opts
+        .modified_since
+        .and_then(|window| now.checked_sub(window));
+    let until = opts
+        .modified_until
+        .map(|window| now.checked_sub(window).unwrap_or(UNIX_EPOCH));
+
+    let (root_sender, receiver) = channel::unbounded();
+
+    builder
+        .git_ignore(opts.git_ignore)
+        .git_exclude(opts.git_ignore)
+        .git_global(opts.git_ignore)
+        .follow_links(opts.follow_links)
+        .hidden(!opts.hidden)
+        .max_depth(opts.max_depth)
+        .threads(opts.threads)
+        .build_parallel()
+        .run(|| {
+            let sender = root_sender.clone();
+            Box::new(move |entry_result| match entry_result {
+                _ if deadline.passed() => ignore::WalkState::Quit,
+                Ok(entry) => match sender.send(entry) {
+                    Ok(()) => ignore::WalkState::Continue,
+                    Err(_) => ignore::WalkState::Quit,
+                },
+                // a symlink loop only means we've been here before, so we
+                // can keep going without it.
+                Err(err) if is_loop(&err) => ignore::WalkState::Continue,
+                Err(_) => ignore::WalkState::Quit,
+            })
+        });
+
+    drop(root_sender);
+
+    // if we're given overlapping paths (like `src` and `src/lib`), we'll walk
+    // some files more than once, and following symlinks can lead us to the
+    // same file by different paths. We only want to search them once, though!
+    // Neither can happen otherwise, so then we skip the extra syscalls.
+    let dedup = paths.len() > 1 || opts.follow_links;
+    let mut seen = HashSet::new();
+
+    Ok(receiver
+        .iter()
+        .filter(|entry| modified_between(entry, since, until))
+        .filter(|entry| {
+            if !dedup || !matches!(entry.file_type(), Some(kind) if kind.is_file()) {
+                return true;
+            }
+
+            seen.insert(
+                entry
+                    .path()
+                    .canonicalize()
+                    .unwrap_or_else(|_| entry.path().to_path_buf()),
+            )
+        })
+        .collect())
+}
+
+fn is_loop(err: &ignore::Error) -> bool {
+    match err {
+        ignore::Error::Loop { .. } => true,
+        ignore::Error::WithPath { err, .. }
+        | ignore::Error::WithDepth { err, .. }
+        | ignore::Error::WithLineNumber { err, .. } => is_loop(err),
+        _ => false,
+    }
+}
+
+/// Was this entry modified at or after `since` and at or before `until`?
+/// Directories always pass, and so do files we can't get a modification time
+/// for, so that we don't silently skip things we could have searched.
+fn modified_between(
+    entry: &ignore::DirEntry,
+    since: Option<SystemTime>,
+    until: Option<SystemTime>,
+) -> bool {
+    if since.is_none() && until.is_none() {
+        return true;
+    }
+
+    match entry.file_type() {
+        Some(file_type) if !file_type.is_dir() => (),
+        _ => return true,
+    }
+
+    match entry.metadata().ok().and_then(|meta| meta.modified().ok()) {
+        Some(modified) => {
+            !matches!(since, Some(since) if modified < since)
+                && !matches!(until, Some(until) if modified > until)
+        }
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    fn call(args: &[&str]) -> String {
+        // we write to a buffer here, but `--color auto` looks at whether the
+        // real stdout is a terminal. Tests that want color ask for it.
+        let mut args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        if !args
+            .iter()
+            .any(|arg| arg == "--color" || arg.starts_with("--color="))
+        {
+            args.push("--color".to_string());
+            args.push("never".to_string());
+        }
+
+        let mut bytes = Vec::new();
+        try_main(args, Box::new(&mut bytes)).unwrap();
+
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn lines_output() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause)",
+            "-f",
+            "lines",
+            "--sort",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+        ]))
+    }
+
+    #[test]
+    fn json_output() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause)",
+            "-f",
+            "json",
+            "--sort",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+        ]))
+    }
+
+    #[test]
+    fn msgpack_output_round_trips() {
+        let output = |format: &str| {
+            let mut bytes = Vec::new();
+            try_main(
+                vec![
+                    "tree-grepper".to_string(),
+                    "-q".to_string(),
+                    "elm".to_string(),
+                    "(import_clause)".to_string(),
+                    "-f".to_string(),
+                    format.to_string(),
+                    "--sort".to_string(),
+                    "--no-gitignore".to_string(),
+                    "vendor/tree-sitter-elm/examples".to_string(),
+                ],
+                Box::new(&mut bytes),
+            )
+            .unwrap();
+
+            bytes
+        };
+
+        let msgpack = output("msgpack");
+        let json = output("json");
+
+        let from_msgpack: Vec<extractor::ExtractedFile> = rmp_serde::from_slice(&msgpack).unwrap();
+        let from_json: Vec<extractor::ExtractedFile> = serde_json::from_slice(&json).unwrap();
+
+        assert!(!from_msgpack.is_empty());
+        assert_eq!(from_msgpack, from_json);
+    }
+
+    #[test]
+    fn streamed_json_output_is_one_array() {
+        let args = [
+            "tree-grepper",
+            "-q",
+            "javascript",
+            "(identifier)",
+            "-f",
+            "json",
+            "--no-gitignore",
+            "vendor/tree-sitter-javascript/examples",
+        ];
+
+        let streamed: serde_json::Value = serde_json::from_str(&call(&args)).unwrap();
+        let collected: serde_json::Value =
+            serde_json::from_str(&call(&[&args[..], &["--sort"]].concat())).unwrap();
+
+        assert!(streamed.as_array().unwrap().len() > 1);
+        assert_eq!(
+            streamed.as_array().unwrap().len(),
+            collected.as_array().unwrap().len()
+        );
+    }
+
+    #[test]
+    fn json_lines_output() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "javascript",
+            "(identifier)",
+            "-f",
+            "json-lines",
+            "--sort",
+            "vendor/tree-sitter-javascript/examples"
+        ]))
+    }
+
+    #[test]
+    fn json_lines_with_summary_output() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause)",
+            "-f",
+            "json-lines",
+            "--jsonl-with-summary",
+            "--sort",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+        ]))
+    }
+
+    #[test]
+    fn summary_counts_matches_and_distinct_files() {
+        let dir = TempDir::new("summary");
+        std::fs::write(
+            dir.join("many.rs"),
+            "fn one() {}\nfn two() {}\nfn three() {}\nfn four() {}\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("few.rs"), "fn five() {}\n").unwrap();
+
+        let out = call(&[
+            "tree-grepper",
+            "-q",
+            "rust",
+            "(function_item name: (identifier)@name)",
+            "-f",
+            "json-lines",
+            "--jsonl-with-summary",
+            dir.to_str().unwrap(),
+        ]);
+
+        let summary: serde_json::Value = serde_json::from_str(out.lines().last().unwrap()).unwrap();
+        assert_eq!(summary["files"], 2);
+        assert_eq!(summary["matches"], 5);
+    }
+
+    #[test]
+    fn file_times_are_slowest_first() {
+        let mut out = Vec::new();
+        write_file_times(
+            vec![
+                (Path::new("fast.rs"), Duration::from_micros(1500)),
+                (Path::new("slow.rs"), Duration::from_millis(20)),
+                (Path::new("medium.rs"), Duration::from_millis(3)),
+            ],
+            &mut out,
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "20.000ms slow.rs\n3.000ms medium.rs\n1.500ms fast.rs\n"
+        );
+    }
+
+    #[test]
+    fn time_files_leaves_output_alone() {
+        let args = [
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause)",
+            "--sort",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+        ];
+        let mut timed_args = args.to_vec();
+        timed_args.push("--time-files");
+
+        assert_eq!(call(&timed_args), call(&args));
+    }
+
+    #[test]
+    fn stdin_is_searched_as_the_given_language() {
+        let opts = match Invocation::from_args(
+            [
+                "tree-grepper",
+                "-q",
+                "rust",
+                "(function_item name: (identifier)@name)",
+                "--stdin",
+                "rust",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+        )
+        .unwrap()
+        {
+            Invocation::DoQuery(opts) => opts,
+            _ => panic!("expected a query"),
+        };
+        let chooser = opts.extractor_chooser().unwrap();
+
+        let extracted = extract_from_stdin(
+            opts.stdin.unwrap(),
+            &chooser,
+            &b"fn one() {}\nfn two() {}\n"[..],
+        )
+        .unwrap();
+
+        assert_eq!(extracted.len(), 1);
+        assert_eq!(extracted[0].path(), Some(Path::new("<stdin>")));
+        assert_eq!(
+            extracted[0].captured_texts("name").collect::<Vec<_>>(),
+            vec!["one", "two"]
+        );
+    }
+
+    #[test]
+    fn stdin_conflicts_with_paths() {
+        assert!(try_main(
+            [
+                "tree-grepper",
+                "-q",
+                "rust",
+                "(function_item)",
+                "--stdin",
+                "rust",
+                "src",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+            Vec::new(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn language_marker_overrides_extension() {
+        let dir = TempDir::new("marker");
+        std::fs::write(dir.join("marked.js"), "#lang rust\nfn hello() {}\n").unwrap();
+        std::fs::write(dir.join("unmarked.rs"), "fn goodbye() {}\n").unwrap();
+
+        let out = call(&[
+            "tree-grepper",
+            "-q",
+            "rust",
+            "(function_item name: (identifier)@name)",
+            "--language-marker",
+            "#lang ",
+            "--sort",
+            dir.to_str().unwrap(),
+        ]);
+
+        let names: Vec<&str> = out
+            .lines()
+            .map(|line| line.rsplit(':').next().unwrap())
+            .collect();
+        assert_eq!(names, vec!["hello", "goodbye"]);
+    }
+
+    #[test]
+    fn go_build_tags_dont_look_like_language_markers() {
+        // `//go:build linux` starts with `//`, but `go:build` isn't a
+        // language, so the file should still be chosen by its extension.
+        let out = call(&[
+            "tree-grepper",
+            "-q",
+            "go",
+            "(function_declaration name: (identifier)@name)",
+            "--language-marker",
+            "//",
+            "--sort",
+            "--no-gitignore",
+            "tests/fixtures/go",
+        ]);
+
+        assert_eq!(
+            out,
+            "tests/fixtures/go/server.go:22:6:name:New\n\
+             tests/fixtures/go/sockets_linux.go:8:6:name:reusePort\n"
+        );
+    }
+
+    #[test]
+    fn multiple_languages_lines_output() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "rust",
+            "(function_item name: (identifier)@name)",
+            "-q",
+            "elm",
+            "(module_declaration (upper_case_qid)@name)",
+            "--sort",
+            "--no-gitignore",
+            "vendor/tree-sitter-rust/bindings/rust",
+            "vendor/tree-sitter-elm/examples",
+        ]))
+    }
+
+    #[test]
+    fn multiple_languages_json_output() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "rust",
+            "(function_item name: (identifier)@name)",
+            "-q",
+            "elm",
+            "(module_declaration (upper_case_qid)@name)",
+            "--format=json",
+            "--sort",
+            "--no-gitignore",
+            "vendor/tree-sitter-rust/bindings/rust",
+            "vendor/tree-sitter-elm/examples",
+        ]))
+    }
+
+    #[test]
+    fn sarif_output() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause (upper_case_qid)@module)",
+            "--format=sarif",
+            "--sort",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+        ]))
+    }
+
+    #[test]
+    fn null_file_list_output() {
+        assert_eq!(
+            call(&[
+                "tree-grepper",
+                "-q",
+                "elm",
+                "(import_clause)",
+                "-q",
+                "rust",
+                "(function_item)",
+                "--format=null-file-list",
+                "--sort",
+                "--no-gitignore",
+                "vendor/tree-sitter-elm/examples",
+                "vendor/tree-sitter-rust/bindings/rust",
+            ]),
+            "vendor/tree-sitter-elm/examples/basic.elm\0vendor/tree-sitter-rust/bindings/rust/build.rs\0vendor/tree-sitter-rust/bindings/rust/lib.rs\0"
+        )
+    }
+
+    #[test]
+    fn files_with_matches_output() {
+        let args = [
+            "tree-grepper",
+            "-q",
+            "rust",
+            "(function_item name: (identifier)@name)",
+            "-q",
+            "elm",
+            "(import_clause)",
+            "-l",
+            "--sort",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+            "vendor/tree-sitter-rust/bindings/rust",
+        ];
+
+        assert_eq!(
+            call(&args),
+            "vendor/tree-sitter-elm/examples/basic.elm\nvendor/tree-sitter-rust/bindings/rust/build.rs\nvendor/tree-sitter-rust/bindings/rust/lib.rs\n"
+        );
+
+        let mut json_args = args.to_vec();
+        json_args.push("--format=json");
+        assert_eq!(
+            call(&json_args),
+            r#"["vendor/tree-sitter-elm/examples/basic.elm","vendor/tree-sitter-rust/bindings/rust/build.rs","vendor/tree-sitter-rust/bindings/rust/lib.rs"]"#
+        );
+    }
+
+    #[test]
+    fn file_kinds_output() {
+        let dir = TempDir::new("kinds");
+        let path = dir.join("kinds.rs");
+        std::fs::write(&path, "fn main() { go(1); }\n").unwrap();
+
+        let out = call(&[
+            "tree-grepper",
+            "-q",
+            "rust",
+            "(_)",
+            "--file-kinds",
+            "--format=json",
+            path.to_str().unwrap(),
+        ]);
+
+        let table: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(
+            table[path.to_str().unwrap()],
+            serde_json::json!([
+                "arguments",
+                "block",
+                "call_expression",
+                "function_item",
+                "identifier",
+                "integer_literal",
+                "parameters",
+                "source_file"
+            ])
+        );
+    }
+
+    #[test]
+    fn count_output() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "rust",
+            "(function_item name: (identifier)@name)",
+            "-q",
+            "elm",
+            "(import_clause)",
+            "-c",
+            "--sort",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+            "vendor/tree-sitter-rust/bindings/rust",
+        ]))
+    }
+
+    #[test]
+    fn group_by_dir_output() {
+        let dir = TempDir::new("group");
+        for nested in &["a/b", "a/c", "d"] {
+            std::fs::create_dir_all(dir.join(nested)).unwrap();
+        }
+        std::fs::write(dir.join("top.rs"), "fn top() {}\n").unwrap();
+        std::fs::write(dir.join("a/b/one.rs"), "fn one() {}\nfn two() {}\n").unwrap();
+        std::fs::write(dir.join("a/c/three.rs"), "fn three() {}\n").unwrap();
+        std::fs::write(dir.join("d/four.rs"), "fn four() {}\n").unwrap();
+
+        let query = "(function_item name: (identifier)@name)";
+        let shallow = call(&[
+            "tree-grepper",
+            "-q",
+            "rust",
+            query,
+            "--no-gitignore",
+            dir.to_str().unwrap(),
+            "--group-by-dir",
+        ]);
+        let deep = call(&[
+            "tree-grepper",
+            "-q",
+            "rust",
+            query,
+            "--group-by-dir=2",
+            "--no-gitignore",
+            dir.to_str().unwrap(),
+        ]);
+
+        let d = dir.display();
+        assert_eq!(shallow, format!("{}:1\n{}/a:3\n{}/d:1\ntotal:5\n", d, d, d));
+        assert_eq!(
+            deep,
+            format!("{}:1\n{}/a/b:2\n{}/a/c:1\n{}/d:1\ntotal:5\n", d, d, d, d)
+        );
+    }
+
+    #[test]
+    fn coverage_output() {
+        let dir = TempDir::new("coverage");
+        let path = dir.join("main.rs");
+        // 12 of these 24 bytes are in the function
+        fs::write(&path, "fn main() {}\nstruct Fo;\n").unwrap();
+
+        let out = call(&[
+            "tree-grepper",
+            "-q",
+            "rust",
+            "(function_item name: (identifier)@name)@func",
+            "--coverage",
+            path.to_str().unwrap(),
+        ]);
+
+        assert_eq!(
+            out,
+            format!("{}:50.00%\ntotal:50.00%\n", path.to_str().unwrap())
+        );
+    }
+
+    #[test]
+    fn html_output() {
+        let out = call(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause)",
+            "--format=html",
+            "--sort",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples/basic.elm",
+        ]);
+
+        assert!(out.starts_with("<!DOCTYPE html>"));
+        assert!(out.contains(
+            "<li><a href=\"#file-0\">vendor/tree-sitter-elm/examples/basic.elm</a> (3)</li>"
+        ));
+        assert!(out.contains("<section id=\"file-0\">"));
+        assert!(out.contains(
+            "<h3 id=\"file-0-0\"><a href=\"#file-0-0\">vendor/tree-sitter-elm/examples/basic.elm:3:1</a>"
+        ));
+        assert!(out.contains("<pre><code>import Browser</code></pre>"));
+        assert!(out.trim_end().ends_with("</html>"));
+    }
+
+    #[test]
+    fn no_query_takes_inventory() {
+        let dir = TempDir::new("inventory");
+        std::fs::write(dir.join("a.rs"), "fn a() {}\n").unwrap();
+        std::fs::write(dir.join("b.rs"), "fn b() {}\n").unwrap();
+        std::fs::write(dir.join("broken.rs"), "fn broken( {}\n").unwrap();
+        std::fs::write(dir.join("Main.elm"), "module Main exposing (..)\n").unwrap();
+        std::fs::write(dir.join("notes.txt"), "not code\n").unwrap();
+
+        let out = call(&[
+            "tree-grepper",
+            "--no-query",
+            "--no-gitignore",
+            dir.to_str().unwrap(),
+        ]);
+
+        let dir = dir.to_str().unwrap();
+        assert_eq!(
+            out,
+            format!(
+                "elm 1\n  {dir}/Main.elm\nrust 2\n  {dir}/a.rs\n  {dir}/b.rs\n",
+                dir = dir
+            )
+        );
+    }
+
+    #[test]
+    fn merge_combines_json_results() {
+        let dir = TempDir::new("merge");
+        std::fs::write(dir.join("a.rs"), "fn a() {}\n").unwrap();
+        std::fs::write(dir.join("b.rs"), "fn b() {}\n").unwrap();
+        let query = "(function_item name: (identifier)@name)";
+
+        // the shards overlap on a.rs, and use different JSON formats
+        let first = call(&[
+            "tree-grepper",
+            "-q",
+            "rust",
+            query,
+            "-f",
+            "json",
+            "--no-gitignore",
+            dir.join("a.rs").to_str().unwrap(),
+        ]);
+        let second = call(&[
+            "tree-grepper",
+            "-q",
+            "rust",
+            query,
+            "-f",
+            "json-lines",
+            "--jsonl-with-summary",
+            "--no-gitignore",
+            dir.to_str().unwrap(),
+        ]);
+        std::fs::write(dir.join("first.json"), first).unwrap();
+        std::fs::write(dir.join("second.jsonl"), second).unwrap();
+
+        let merged = call(&[
+            "tree-grepper",
+            "--merge",
+            dir.join("first.json").to_str().unwrap(),
+            "--merge",
+            dir.join("second.jsonl").to_str().unwrap(),
+        ]);
+        let searched = call(&[
+            "tree-grepper",
+            "-q",
+            "rust",
+            query,
+            "--sort",
+            "--no-gitignore",
+            dir.to_str().unwrap(),
+        ]);
+
+        assert_eq!(merged.lines().count(), 2);
+        assert_eq!(merged, searched);
+    }
+
+    #[test]
+    fn merge_keeps_interleaved_matches_in_line_order() {
+        let dir = TempDir::new("merge-order");
+        let path = dir.join("shapes.rs");
+        std::fs::write(&path, "fn zeta() {}\nstruct Alpha;\nfn beta() {}\n").unwrap();
+
+        // each shard has matches the other's fall between
+        let shard = |query, name| {
+            let out = call(&[
+                "tree-grepper",
+                "-q",
+                "rust",
+                query,
+                "-f",
+                "json",
+                "--no-gitignore",
+                path.to_str().unwrap(),
+            ]);
+            let shard = dir.join(name);
+            std::fs::write(&shard, out).unwrap();
+            shard
+        };
+        let functions = shard("(function_item name: (identifier)@name)", "functions.json");
+        let structs = shard("(struct_item name: (type_identifier)@name)", "structs.json");
+
+        let merged = call(&[
+            "tree-grepper",
+            "--merge",
+            functions.to_str().unwrap(),
+            "--merge",
+            structs.to_str().unwrap(),
+        ]);
+
+        assert_eq!(
+            merged
+                .lines()
+                .map(|line| line.rsplit(':').next().unwrap())
+                .collect::<Vec<&str>>(),
+            vec!["zeta", "Alpha", "beta"]
+        );
+    }
+
+    #[test]
+    fn embedded_matches_only_come_from_captured_nodes() {
+        let dir = TempDir::new("embedded");
+        let path = dir.join("script.py");
+        std::fs::write(
+            &path,
+            "greet = \"alert(name)\"\nshout = \"alert(name.upper())\"\nnote = \"just words\"\n",
+        )
+        .unwrap();
+
+        let search = |extra: &[&str]| {
+            let mut args = vec![
+                "tree-grepper",
+                "-q",
+                "python",
+                "((string)@_js (#match? @_js \"alert\"))",
+                "-q",
+                "javascript",
+                "(identifier)@id",
+                "--embedded",
+                "javascript:string",
+                "--no-gitignore",
+            ];
+            args.extend_from_slice(extra);
+            args.push(path.to_str().unwrap());
+
+            call(&args)
+                .lines()
+                .map(|line| line.split_once(':').unwrap().1.to_string())
+                .collect::<Vec<String>>()
+        };
+
+        // `note` isn't captured by the Python query, so it isn't re-parsed
+        assert_eq!(
+            search(&[]),
+            vec![
+                "1:10:id:alert",
+                "1:16:id:name",
+                "2:10:id:alert",
+                "2:16:id:name"
+            ]
+        );
+
+        // filters see embedded matches at their place in the whole file
+        assert_eq!(
+            search(&["--byte-range", "0:22"]),
+            vec!["1:10:id:alert", "1:16:id:name"]
+        );
+    }
+
+    #[test]
+    fn cache_skips_unchanged_files_without_matches() {
+        let dir = TempDir::new("cache");
+        let cache_dir = dir.join("cache");
+        let src = dir.join("src");
+        std::fs::create_dir_all(&src).unwrap();
+        std::fs::write(src.join("matches.rs"), "fn one() {}\n").unwrap();
+        std::fs::write(src.join("nothing.rs"), "struct Nothing;\n").unwrap();
+
+        let args = [
+            "tree-grepper",
+            "-q",
+            "rust",
+            "(function_item name: (identifier)@name)",
+            "--cache",
+            cache_dir.to_str().unwrap(),
+            "--sort",
+            src.to_str().unwrap(),
+        ];
+
+        let first = call(&args);
+        let cached: Vec<std::path::PathBuf> = std::fs::read_dir(&cache_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(cached.len(), 1);
+        let saved: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&cached[0]).unwrap()).unwrap();
+
+        // the cache says nothing.rs doesn't match, so we skip it...
+        let second = call(&args);
+
+        // ...until it changes.
+        std::fs::write(src.join("nothing.rs"), "fn two() {}\n").unwrap();
+        let third = call(&args);
+
+        assert_eq!(
+            saved["unmatched"]
+                .as_object()
+                .unwrap()
+                .keys()
+                .collect::<Vec<_>>(),
+            vec![src.join("nothing.rs").to_str().unwrap()]
+        );
+        assert_eq!(first, second);
+        assert_eq!(third.lines().count(), 2);
+        assert!(third.contains("nothing.rs:1:4:name:two"));
+    }
+
+    #[test]
+    fn query_test_reports_passes_and_failures() {
+        let dir = TempDir::new("query-test");
+        std::fs::create_dir_all(dir.join("fixtures")).unwrap();
+        std::fs::write(
+            dir.join("fixtures").join("two.rs"),
+            "fn one() {}\nfn two() {}\n",
+        )
+        .unwrap();
+
+        let spec = dir.join("spec.json");
+        let run = |expected: &str| {
+            std::fs::write(&spec, expected).unwrap();
+
+            let mut bytes = Vec::new();
+            let result = try_main(
+                vec![
+                    "tree-grepper".to_string(),
+                    "-q".to_string(),
+                    "rust".to_string(),
+                    "(function_item name: (identifier)@name)@func".to_string(),
+                    "--query-test".to_string(),
+                    spec.to_str().unwrap().to_string(),
+                ],
+                Box::new(&mut bytes),
+            );
+
+            (result, String::from_utf8(bytes).unwrap())
+        };
+
+        let (passing, passing_out) = run(r#"{"fixtures/two.rs": {"name": 2, "func": 2}}"#);
+        let (failing, failing_out) = run(r#"{"fixtures/two.rs": {"name": 3, "other": 0}}"#);
+
+        assert!(passing.is_ok());
+        assert_eq!(passing_out, "ok fixtures/two.rs\n1 passed, 0 failed\n");
+
+        assert!(failing
+            .unwrap_err()
+            .downcast_ref::<query_test::Failed>()
+            .is_some());
+        assert_eq!(
+            failing_out,
+            "FAILED fixtures/two.rs: @name expected 3, got 2\n0 passed, 1 failed\n"
+        );
+    }
+
+    #[test]
+    fn csv_output() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause)",
+            "--format=csv",
+            "--sort",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+        ]))
+    }
+
+    #[test]
+    fn grammar_versions() {
+        let out = call(&["tree-grepper", "--grammar-versions"]);
+
+        assert_eq!(out.lines().count(), Language::all().len());
+        assert!(out.lines().any(|line| line.starts_with("rust 0.")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn socket_output() {
+        use std::io::Read;
+        use std::os::unix::net::UnixListener;
+
+        let dir = TempDir::new("socket");
+        let path = dir.join("out.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let reader = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = String::new();
+            stream.read_to_string(&mut received).unwrap();
+            received
+        });
+
+        let out = call(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause)",
+            "--socket",
+            path.to_str().unwrap(),
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+        ]);
+
+        let received = reader.join().unwrap();
+
+        assert_eq!(out, "");
+
+        let record: serde_json::Value =
+            serde_json::from_str(received.lines().next().unwrap()).unwrap();
+        assert_eq!(record["file"], "vendor/tree-sitter-elm/examples/basic.elm");
+        assert_eq!(record["matches"].as_array().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn pretty_json_output() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause)",
+            "--format=pretty-json",
+            "--sort",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+        ]))
+    }
+
+    #[test]
+    fn colored_pretty_json_output() {
+        let out = call(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause)",
+            "--format=pretty-json",
+            "--color=always",
+            "--sort",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+        ]);
+
+        assert!(out.contains("\x1b[1;34m\"file\"\x1b[0m"));
+        assert!(out.contains("\x1b[32m\"import_clause\"\x1b[0m"));
+        assert!(out.contains("\x1b[36m1\x1b[0m"));
+    }
+
+    #[test]
+    fn pretty_json_is_uncolored_by_default() {
+        assert!(!call(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause)",
+            "--format=pretty-json",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+        ])
+        .contains('\x1b'));
+    }
+
+    #[test]
+    fn collapsed_lines_output() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(exposed_value)@value (exposed_type)@type",
+            "--collapse-lines",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples/basic.elm",
+        ]))
+    }
+
+    #[test]
+    fn gnu_output() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause)",
+            "--format=gnu",
+            "--sort",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+        ]))
+    }
+
+    #[test]
+    fn line_number_output() {
+        let args = [
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause)",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples/basic.elm",
+        ];
+        let plain = call(&args);
+        let numbered = call(&[&args[..], &["-n"]].concat());
+
+        assert!(plain.starts_with("vendor/tree-sitter-elm/examples/basic.elm:"));
+        assert_eq!(
+            numbered,
+            plain
+                .lines()
+                .map(|line| format!("{}:{}\n", line.split(':').nth(1).unwrap(), line))
+                .collect::<String>()
+        );
+        assert!(numbered.starts_with("3:vendor/tree-sitter-elm/examples/basic.elm:3:"));
+    }
+
+    #[test]
+    fn line_number_needs_lines_format() {
+        assert!(try_main(
+            vec![
+                "tree-grepper",
+                "-q",
+                "elm",
+                "(import_clause)",
+                "-n",
+                "-f",
+                "json"
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+            Box::new(Vec::new()),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn colored_line_number_output() {
+        let numbered = call(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause)",
+            "-n",
+            "--color",
+            "always",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples/basic.elm",
+        ]);
+
+        assert!(numbered.starts_with(
+            "\x1b[32m3\x1b[0m:\x1b[35mvendor/tree-sitter-elm/examples/basic.elm\x1b[0m:\x1b[32m3\x1b[0m:"
+        ));
+    }
+
+    #[test]
+    fn line_number_conflicts_with_other_lines_layouts() {
+        for layout in [
+            &["--collapse-lines"][..],
+            &["-C", "1"],
+            &["-A", "1"],
+            &["-B", "1"],
+        ] {
+            let args = [
+                &["tree-grepper", "-q", "elm", "(import_clause)", "-n"][..],
+                layout,
+            ]
+            .concat();
+
+            assert!(
+                try_main(
+                    args.into_iter().map(String::from).collect(),
+                    Box::new(Vec::new())
+                )
+                .is_err(),
+                "{:?} should conflict with -n",
+                layout
+            );
+        }
+    }
+
+    #[test]
+    fn folded_output() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(upper_case_identifier)",
+            "--format=folded",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples/basic.elm",
+        ]))
+    }
+
+    #[test]
+    fn project_root_from_nested_directory() {
+        assert_eq!(
+            find_project_root(Path::new("vendor/tree-sitter-elm/examples")),
+            Some(PathBuf::from(".."))
+        )
+    }
+
+    #[test]
+    fn project_root_at_start() {
+        assert_eq!(
+            find_project_root(Path::new("vendor/tree-sitter-elm")),
+            Some(PathBuf::from("."))
+        )
+    }
+
+    #[test]
+    fn overlapping_paths_are_searched_once() {
+        let overlapping = call(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause)",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+            "vendor/tree-sitter-elm/examples/basic.elm",
+            "vendor/tree-sitter-elm/examples/../examples",
+        ]);
+
+        let single = call(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause)",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+        ]);
+
+        // which of the overlapping paths we report a file under depends on
+        // which walker got there first, so we just compare how many matches
+        // there are.
+        assert_eq!(overlapping.lines().count(), single.lines().count());
+        assert_eq!(
+            overlapping
+                .lines()
+                .filter(|line| line.contains("basic.elm:3:1:"))
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn threads_do_not_change_output() {
+        let query = |threads: &str| {
+            call(&[
+                "tree-grepper",
+                "-q",
+                "elm",
+                "(import_clause)",
+                "--threads",
+                threads,
+                "--sort",
+                "--no-gitignore",
+                "vendor/tree-sitter-elm/examples",
+            ])
+        };
+
+        assert_eq!(query("1"), query("0"));
+    }
+
+    #[test]
+    fn language_override_beats_extensions() {
+        let dir = TempDir::new("override");
+        std::fs::write(dir.join("lib.txt"), "fn from_txt() {}\n").unwrap();
+        std::fs::write(dir.join("Buildfile"), "fn from_buildfile() {}\n").unwrap();
+        // this would usually be Elm, so we'd never look for Rust in it
+        std::fs::write(dir.join("Main.elm"), "fn from_elm() {}\n").unwrap();
+
+        let out = call(&[
+            "tree-grepper",
+            "-q",
+            "rust",
+            "(function_item name: (identifier)@name)",
+            "--language-override",
+            ".txt:rust",
+            "--language-override",
+            "Buildfile:rust",
+            "--language-override",
+            "elm:rust",
+            "--no-gitignore",
+            "--sort",
+            dir.to_str().unwrap(),
+        ]);
+
+        let names: Vec<&str> = out
+            .lines()
+            .filter_map(|line| line.rsplit(':').next())
+            .collect();
+        assert_eq!(names, vec!["from_buildfile", "from_elm", "from_txt"]);
+    }
+
+    #[test]
+    fn max_depth_stops_descending() {
+        let dir = TempDir::new("depth");
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        std::fs::write(dir.join("top.rs"), "fn top() {}\n").unwrap();
+        std::fs::write(dir.join("nested").join("deep.rs"), "fn deep() {}\n").unwrap();
+
+        let query = |depth: &str| {
+            call(&[
+                "tree-grepper",
+                "-q",
+                "rust",
+                "(function_item name: (identifier)@name)",
+                "--no-gitignore",
+                "--max-depth",
+                depth,
+                dir.to_str().unwrap(),
+            ])
+        };
+
+        let shallow = query("1");
+        let deeper = query("2");
+        let none = query("0");
+
+        assert_eq!(shallow.lines().count(), 1);
+        assert!(shallow.ends_with("top.rs:1:4:name:top\n"));
+        assert_eq!(deeper.lines().count(), 2);
+        assert_eq!(none, "");
+    }
+
+    #[test]
+    fn hidden_searches_dotfiles() {
+        let dir = TempDir::new("hidden");
+        std::fs::create_dir_all(dir.join(".config")).unwrap();
+        std::fs::write(dir.join(".config").join("lib.rs"), "fn hidden() {}\n").unwrap();
+        std::fs::write(dir.join("lib.rs"), "fn visible() {}\n").unwrap();
+
+        let query = |hidden: bool| {
+            let mut args = vec![
+                "tree-grepper",
+                "-q",
+                "rust",
+                "(function_item name: (identifier)@name)",
+                "--no-gitignore",
+                "--sort",
+                dir.to_str().unwrap(),
+            ];
+            if hidden {
+                args.push("--hidden");
+            }
+
+            call(&args)
+        };
+
+        let with_hidden = query(true);
+        let without_hidden = query(false);
+
+        assert_eq!(with_hidden.lines().count(), 2);
+        assert!(with_hidden.contains(".config/lib.rs:1:4:name:hidden\n"));
+        assert_eq!(without_hidden.lines().count(), 1);
+        assert!(without_hidden.ends_with("lib.rs:1:4:name:visible\n"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follow_searches_through_symlinks_once() {
+        let dir = TempDir::new("follow");
+        let root = dir.join("root");
+        let target = dir.join("target");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::create_dir_all(&target).unwrap();
+        std::fs::write(target.join("lib.rs"), "fn linked() {}\n").unwrap();
+
+        std::os::unix::fs::symlink(&target, root.join("link")).unwrap();
+        std::os::unix::fs::symlink(&target, root.join("other-link")).unwrap();
+        // this would go on forever if we didn't notice the loop
+        std::os::unix::fs::symlink(&root, root.join("loop")).unwrap();
+
+        let query = |follow: bool| {
+            let mut args = vec![
+                "tree-grepper",
+                "-q",
+                "rust",
+                "(function_item name: (identifier)@name)",
+                "--no-gitignore",
+                root.to_str().unwrap(),
+            ];
+            if follow {
+                args.push("--follow");
+            }
+
+            call(&args)
+        };
+
+        let followed = query(true);
+        let unfollowed = query(false);
+
+        assert_eq!(followed.lines().count(), 1);
+        assert!(followed.ends_with("lib.rs:1:4:name:linked\n"));
+        assert_eq!(unfollowed, "");
+    }
+
+    #[test]
+    fn modified_between_filters_by_mtime() {
+        let dir = TempDir::new("mtime");
+        let day = std::time::Duration::from_secs(60 * 60 * 24);
+        for (name, age) in [("old", 30), ("recent", 3), ("fresh", 0)] {
+            let path = dir.join(format!("{}.rs", name));
+            std::fs::write(&path, format!("fn {}() {{}}\n", name)).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(SystemTime::now() - day * age)
+                .unwrap();
+        }
+
+        let names = |window: &[&str]| {
+            let mut args = vec![
+                "tree-grepper",
+                "-q",
+                "rust",
+                "(function_item name: (identifier)@name)",
+                "--sort",
+                "--no-gitignore",
+            ];
+            args.extend_from_slice(window);
+            args.push(dir.to_str().unwrap());
+
+            call(&args)
+                .lines()
+                .map(|line| line.rsplit(':').next().unwrap().to_string())
+                .collect::<Vec<String>>()
+        };
+
+        assert_eq!(names(&[]), ["fresh", "old", "recent"]);
+        assert_eq!(names(&["--modified-since", "7d"]), ["fresh", "recent"]);
+        assert_eq!(names(&["--until", "1d"]), ["old", "recent"]);
+        assert_eq!(
+            names(&["--modified-since", "7d", "--modified-until", "1d"]),
+            ["recent"]
+        );
+    }
+
+    #[test]
+    fn parse_byte_ranges() {
+        assert_eq!(cli::parse_byte_range("0:10").unwrap(), 0..10);
+        assert_eq!(cli::parse_byte_range("5:5").unwrap(), 5..5);
+        assert!(cli::parse_byte_range("10").is_err());
+        assert!(cli::parse_byte_range("10:5").is_err());
+        assert!(cli::parse_byte_range("a:5").is_err());
+    }
+
+    #[test]
+    fn parse_durations() {
+        use std::time::Duration;
+
+        assert_eq!(cli::parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(
+            cli::parse_duration("15m").unwrap(),
+            Duration::from_secs(900)
+        );
+        assert_eq!(
+            cli::parse_duration("2h").unwrap(),
+            Duration::from_secs(7200)
+        );
+        assert_eq!(
+            cli::parse_duration("7d").unwrap(),
+            Duration::from_secs(7 * 86400)
+        );
+        assert_eq!(
+            cli::parse_duration("1w").unwrap(),
+            Duration::from_secs(7 * 86400)
+        );
+        assert!(cli::parse_duration("7").is_err());
+        assert!(cli::parse_duration("d").is_err());
+        assert!(cli::parse_duration("7y").is_err());
+    }
+
+    #[test]
+    fn deadline_keeps_partial_output() {
+        let args = [
+            "tree-grepper",
+            "-q",
+            "javascript",
+            "(identifier)",
+            "-q",
+            "rust",
+            "(identifier)",
+            "-q",
+            "typescript",
+            "(identifier)",
+            "--deadline",
+            "60",
+            "--threads",
+            "1",
+            "--no-gitignore",
+            "--color",
+            "never",
+            "tests/auto",
+        ];
+        let opts =
+            match Invocation::from_args(args.iter().map(|s| s.to_string()).collect()).unwrap() {
+                Invocation::DoQuery(opts) => opts,
+                _ => panic!("expected a query"),
+            };
+
+        let full = call(&args);
+
+        // we don't know how many checks walking takes, so give the deadline
+        // more room each time until it lets some (but not all) files through.
+        let partial = (0..20).find_map(|checks| {
+            let mut bytes = Vec::new();
+            let error = query_until(&opts, &Deadline::after_checks(checks), &mut bytes).err()?;
+            assert_eq!(flushed_exit_code(&error), Some(TIMED_OUT_EXIT_CODE));
+
+            Some(String::from_utf8(bytes).unwrap()).filter(|out| !out.is_empty())
+        });
+
+        let partial = partial.expect("the deadline never let anything through");
+        assert!(partial.len() < full.len());
+        assert!(full.starts_with(&partial));
+    }
+
+    #[test]
+    fn max_allowed_passes_at_or_under_the_limit() {
+        call(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause)",
+            "--max-allowed",
+            "3",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+        ]);
+    }
+
+    #[test]
+    fn max_allowed_fails_over_the_limit() {
+        let mut bytes = Vec::new();
+        let error = try_main(
+            vec![
+                "tree-grepper".to_string(),
+                "-q".to_string(),
+                "elm".to_string(),
+                "(import_clause)".to_string(),
+                "--max-allowed".to_string(),
+                "2".to_string(),
+                "--no-gitignore".to_string(),
+                "vendor/tree-sitter-elm/examples".to_string(),
+            ],
+            Box::new(&mut bytes),
+        )
+        .unwrap_err();
+
+        let too_many = error.downcast_ref::<TooManyMatches>().unwrap();
+        assert_eq!((too_many.found, too_many.allowed), (3, 2));
+        assert_eq!(flushed_exit_code(&error), Some(TOO_MANY_MATCHES_EXIT_CODE));
+
+        // we still print the matches so you can go fix them
+        assert_eq!(String::from_utf8(bytes).unwrap().lines().count(), 3);
+    }
+
+    #[test]
+    fn generous_deadline_finishes() {
+        assert_eq!(
+            call(&[
+                "tree-grepper",
+                "-q",
+                "elm",
+                "(import_clause)",
+                "--deadline",
+                "3600",
+                "--no-gitignore",
+                "vendor/tree-sitter-elm/examples/basic.elm",
+            ]),
+            call(&[
+                "tree-grepper",
+                "-q",
+                "elm",
+                "(import_clause)",
+                "--no-gitignore",
+                "vendor/tree-sitter-elm/examples/basic.elm",
+            ])
+        );
+    }
+
+    #[test]
+    fn output_per_file() {
+        let dir = TempDir::new("per-file");
+
+        let out = call(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause)",
+            "--output-per-file",
+            dir.to_str().unwrap(),
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+        ]);
+
+        let written =
+            std::fs::read_to_string(dir.join("vendor/tree-sitter-elm/examples/basic.elm.txt"))
+                .unwrap();
+
+        assert_eq!(out, "");
+        assert!(written.contains("import Browser"));
+        assert_eq!(
+            written,
+            call(&[
+                "tree-grepper",
+                "-q",
+                "elm",
+                "(import_clause)",
+                "vendor/tree-sitter-elm/examples/basic.elm",
+            ])
+        );
+    }
+
+    #[test]
+    fn forced_language() {
+        let dir = TempDir::new("as");
+
+        let path = dir.join("weird_name.txt");
+        std::fs::write(&path, "fn main() {}\n").unwrap();
+
+        let out = call(&[
+            "tree-grepper",
+            "-q",
+            "rust",
+            "(function_item name: (identifier)@name)",
+            "--as",
+            "rust",
+            path.to_str().unwrap(),
+        ]);
+
+        assert_eq!(out, format!("{}:1:4:name:main\n", path.display()));
+    }
+
+    #[test]
+    fn query_file_works_like_inline_query() {
+        let dir = TempDir::new("query-file");
+
+        let query = dir.join("imports.scm");
+        std::fs::write(&query, "(import_clause (upper_case_qid)@module)\n").unwrap();
+
+        let from_file = call(&[
+            "tree-grepper",
+            "--query-file",
+            "elm",
+            query.to_str().unwrap(),
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples/basic.elm",
+        ]);
+
+        let both = try_main(
+            [
+                "tree-grepper",
+                "-q",
+                "elm",
+                "(import_clause)",
+                "--query-file",
+                "elm",
+                query.to_str().unwrap(),
+                "vendor/tree-sitter-elm/examples/basic.elm",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+            Vec::new(),
+        );
+
+        assert_eq!(
+            from_file,
+            call(&[
+                "tree-grepper",
+                "-q",
+                "elm",
+                "(import_clause (upper_case_qid)@module)\n",
+                "--no-gitignore",
+                "vendor/tree-sitter-elm/examples/basic.elm",
+            ])
+        );
+        assert!(both.is_err());
+    }
+
+    #[test]
+    fn query_file_must_be_readable() {
+        assert!(try_main(
+            [
+                "tree-grepper",
+                "--query-file",
+                "elm",
+                "does/not/exist.scm",
+                "vendor/tree-sitter-elm/examples/basic.elm",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+            Vec::new(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn namespaced_query_files() {
+        let dir = TempDir::new("query-files");
+
+        let values = dir.join("values.scm");
+        std::fs::write(
+            &values,
+            "; the \"@name\" here is left alone\n((exposed_value)@name (#eq? @name \"main\"))\n",
+        )
+        .unwrap();
+
+        let types = dir.join("types.scm");
+        std::fs::write(&types, "(exposed_type)@name\n").unwrap();
+
+        let search = |flag| {
+            call(&[
+                "tree-grepper",
+                "--query-file",
+                "elm",
+                values.to_str().unwrap(),
+                "--query-file",
+                "elm",
+                types.to_str().unwrap(),
+                flag,
+                "--no-gitignore",
+                "vendor/tree-sitter-elm/examples/basic.elm",
+            ])
+        };
+        let out = search("--namespace-captures");
+        let aliased = search("--merge-queries");
+
+        assert_eq!(out, aliased);
+
+        insta::assert_snapshot!(out)
+    }
+
+    #[test]
+    fn atomic_replace_changes_nothing_if_any_file_breaks() {
+        let dir = TempDir::new("atomic");
+
+        let good = dir.join("good.rs");
+        std::fs::write(&good, "fn main() { let x = 1; }\n").unwrap();
+
+        // `1` becomes `1.`, which is fine, but `2.0` becomes `2.0.`, which
+        // doesn't parse.
+        let bad = dir.join("bad.rs");
+        std::fs::write(&bad, "fn main() { let y = 2.0; }\n").unwrap();
+
+        let mut bytes = Vec::new();
+        let result = try_main(
+            vec![
+                "tree-grepper".to_string(),
+                "-q".to_string(),
+                "rust".to_string(),
+                "[(integer_literal) (float_literal)]@n".to_string(),
+                "--replace".to_string(),
+                "n".to_string(),
+                "--with".to_string(),
+                "{{n}}.".to_string(),
+                "--atomic".to_string(),
+                dir.to_str().unwrap().to_string(),
+            ],
+            Box::new(&mut bytes),
+        );
+
+        let good_after = std::fs::read_to_string(&good).unwrap();
+        let bad_after = std::fs::read_to_string(&bad).unwrap();
+
+        assert!(result.is_err());
+        assert_eq!(good_after, "fn main() { let x = 1; }\n");
+        assert_eq!(bad_after, "fn main() { let y = 2.0; }\n");
+    }
+
+    #[test]
+    fn replace_counter_goes_through_files_in_order() {
+        let dir = TempDir::new("counter");
+
+        let a = dir.join("a.rs");
+        std::fs::write(&a, "fn one() {}\nfn two() {}\n").unwrap();
+        let b = dir.join("b.rs");
+        std::fs::write(&b, "fn three() {}\n").unwrap();
+
+        call(&[
+            "tree-grepper",
+            "-q",
+            "rust",
+            "(function_item name: (identifier)@name)",
+            "--replace",
+            "name",
+            "--with",
+            "item_${counter}",
+            dir.to_str().unwrap(),
+        ]);
+
+        let a_after = std::fs::read_to_string(&a).unwrap();
+        let b_after = std::fs::read_to_string(&b).unwrap();
+
+        assert_eq!(a_after, "fn item_0() {}\nfn item_1() {}\n");
+        assert_eq!(b_after, "fn item_2() {}\n");
+    }
+
+    #[test]
+    fn replace_dry_run_prints_a_diff() {
+        let dir = TempDir::new("dry-run");
+
+        let path = dir.join("main.rs");
+        std::fs::write(&path, "fn one() {}\nfn two() {}\n").unwrap();
+
+        let out = call(&[
+            "tree-grepper",
+            "-q",
+            "rust",
+            "(function_item name: (identifier)@name)",
+            "--replace",
+            "name",
+            "--with",
+            "new_{{name}}",
+            "--dry-run",
+            path.to_str().unwrap(),
+        ]);
+
+        let after = std::fs::read_to_string(&path).unwrap();
+
+        assert_eq!(after, "fn one() {}\nfn two() {}\n");
+        assert_eq!(
+            out,
+            format!(
+                "--- a/{}\n+++ b/{}\n@@ -1,2 +1,2 @@\n-fn one() {{}}\n-fn two() {{}}\n+fn new_one() {{}}\n+fn new_two() {{}}\n",
+                path.display(),
+                path.display()
+            )
+        );
+    }
+
+    #[test]
+    fn replace_patch_applies_with_git() {
+        let out = call(&[
+            "tree-grepper",
+            "-q",
+            "rust",
+            "(function_item name: (identifier)@name)",
+            "--replace",
+            "name",
+            "--with",
+            "new_{{name}}",
+            "--format",
+            "patch",
+            "--no-gitignore",
+            "tests/patch/two_functions.rs",
+        ]);
+
+        assert!(out.starts_with(
+            "diff --git a/tests/patch/two_functions.rs b/tests/patch/two_functions.rs\nindex "
+        ));
+        assert_eq!(
+            std::fs::read_to_string("tests/patch/two_functions.rs").unwrap(),
+            "fn one() {}\n\nfn two() {}\n"
+        );
+
+        let mut apply = std::process::Command::new("git")
+            .args(["apply", "--check", "-"])
+            .stdin(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        apply
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(out.as_bytes())
+            .unwrap();
+        assert!(apply.wait().unwrap().success());
+    }
+
+    #[test]
+    fn check_accepts_queries_that_compile() {
+        assert_eq!(
+            call(&[
+                "tree-grepper",
+                "-q",
+                "rust",
+                "(function_item name: (identifier)@name)",
+                "--check",
+                "/nonexistent",
+            ]),
+            ""
+        )
     }
 
-    Ok(())
-}
+    #[test]
+    fn check_reports_query_errors() {
+        let mut bytes = Vec::new();
+        let err = try_main(
+            vec![
+                "tree-grepper".to_string(),
+                "-q".to_string(),
+                "rust".to_string(),
+                "(function_item nme: (identifier))".to_string(),
+                "--check".to_string(),
+            ],
+            Box::new(&mut bytes),
+        )
+        .unwrap_err();
 
-fn find_files(opts: &QueryOpts) -> Result<Vec<ignore::DirEntry>> {
-    let mut builder = match opts.paths.split_first() {
-        Some((first, rest)) => {
-            let mut builder = ignore::WalkBuilder::new(first);
-            for path in rest {
-                builder.add(path);
-            }
+        assert_eq!(
+            format!("{:#}", err),
+            "couldn't get a valid configuration from the command-line options: could not parse query: Query error at 1:16. Invalid field name nme"
+        );
+        assert_eq!(String::from_utf8(bytes).unwrap(), "");
+    }
 
-            builder
-        }
-        None => bail!("I need at least one file or directory to walk!"),
-    };
+    #[test]
+    fn exit_status_says_whether_anything_matched() {
+        let run = |query: &str, quiet: bool| {
+            let mut args = vec![
+                "tree-grepper".to_string(),
+                "-q".to_string(),
+                "elm".to_string(),
+                query.to_string(),
+                "--no-gitignore".to_string(),
+                "vendor/tree-sitter-elm/examples".to_string(),
+            ];
+            if quiet {
+                args.push("--quiet".to_string());
+            }
 
-    let (root_sender, receiver) = channel::unbounded();
+            let mut bytes = Vec::new();
+            let matched = try_main(args, Box::new(&mut bytes)).unwrap();
 
-    builder
-        .git_ignore(opts.git_ignore)
-        .git_exclude(opts.git_ignore)
-        .git_global(opts.git_ignore)
-        .build_parallel()
-        .run(|| {
-            let sender = root_sender.clone();
-            Box::new(move |entry_result| match entry_result {
-                Ok(entry) => match sender.send(entry) {
-                    Ok(()) => ignore::WalkState::Continue,
-                    Err(_) => ignore::WalkState::Quit,
-                },
-                Err(_) => ignore::WalkState::Quit,
-            })
-        });
+            (matched, String::from_utf8(bytes).unwrap())
+        };
 
-    drop(root_sender);
+        let (matched, out) = run("(import_clause)@import", false);
+        assert!(matched);
+        assert_ne!(out, "");
 
-    Ok(receiver.iter().collect())
-}
+        assert_eq!(
+            run("((import_clause)@import (#eq? @import \"nope\"))", false),
+            (false, String::new())
+        );
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        assert_eq!(run("(import_clause)@import", true), (true, String::new()));
+        assert_eq!(
+            run("((import_clause)@import (#eq? @import \"nope\"))", true),
+            (false, String::new())
+        );
+    }
 
-    fn call(args: &[&str]) -> String {
+    #[test]
+    fn patch_format_needs_replace() {
         let mut bytes = Vec::new();
-        try_main(
-            args.iter().map(|s| s.to_string()).collect(),
+        let err = try_main(
+            vec![
+                "tree-grepper".to_string(),
+                "-q".to_string(),
+                "rust".to_string(),
+                "(identifier)".to_string(),
+                "--format".to_string(),
+                "patch".to_string(),
+            ],
             Box::new(&mut bytes),
         )
-        .unwrap();
+        .unwrap_err();
 
-        String::from_utf8(bytes).unwrap()
+        assert_eq!(
+            format!("{:#}", err),
+            "couldn't get a valid configuration from the command-line options: --format=patch only works with --replace"
+        );
     }
 
     #[test]
-    fn lines_output() {
+    fn staged_searches_the_index() {
+        let dir = TempDir::new("staged");
+
+        let git = |args: &[&str]| {
+            assert!(std::process::Command::new("git")
+                .arg("-C")
+                .arg(&*dir)
+                .args(args)
+                .output()
+                .unwrap()
+                .status
+                .success())
+        };
+
+        git(&["init", "--quiet"]);
+        std::fs::write(dir.join("main.rs"), "fn staged() {}\n").unwrap();
+        git(&["add", "main.rs"]);
+        std::fs::write(dir.join("main.rs"), "fn working() {}\n").unwrap();
+        // not staged at all, so we shouldn't see it
+        std::fs::write(dir.join("other.rs"), "fn untracked() {}\n").unwrap();
+
+        let out = call(&[
+            "tree-grepper",
+            "-q",
+            "rust",
+            "(function_item name: (identifier)@name)",
+            "--staged",
+            dir.to_str().unwrap(),
+        ]);
+
+        assert_eq!(out, "main.rs:1:4:name:staged\n");
+    }
+
+    #[test]
+    fn unique_capture_output() {
         insta::assert_snapshot!(call(&[
             "tree-grepper",
             "-q",
             "elm",
-            "(import_clause)",
-            "-f",
-            "lines",
-            "--sort",
+            "(import_clause (upper_case_qid)@module)",
+            "--unique-capture",
+            "module",
             "--no-gitignore",
             "vendor/tree-sitter-elm/examples",
         ]))
     }
 
     #[test]
-    fn json_output() {
+    fn key_value_output() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "rust",
+            "(function_item name: (identifier)@name return_type: (_)@type)",
+            "--kv",
+            "name:type",
+            "--format=pretty-json",
+            "--no-gitignore",
+            "vendor/tree-sitter-typescript/bindings/rust/lib.rs",
+            "vendor/tree-sitter-rust/bindings/rust/lib.rs",
+        ]))
+    }
+
+    #[test]
+    fn sample_output() {
         insta::assert_snapshot!(call(&[
             "tree-grepper",
             "-q",
             "elm",
-            "(import_clause)",
-            "-f",
-            "json",
-            "--sort",
+            "(upper_case_identifier)",
+            "--sample",
+            "3",
+            "--seed",
+            "1",
             "--no-gitignore",
             "vendor/tree-sitter-elm/examples",
         ]))
     }
 
     #[test]
-    fn json_lines_output() {
+    fn reverse_output() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause)",
+            "--reverse",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples/basic.elm",
+        ]))
+    }
+
+    #[test]
+    fn search_compressed() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "rust",
+            "(function_item name: (identifier)@name)",
+            "--search-compressed",
+            "--no-gitignore",
+            "tests/fixtures",
+        ]))
+    }
+
+    #[test]
+    fn compressed_files_are_skipped_by_default() {
+        assert_eq!(
+            call(&[
+                "tree-grepper",
+                "-q",
+                "rust",
+                "(function_item name: (identifier)@name)",
+                "--no-gitignore",
+                "tests/fixtures",
+            ]),
+            ""
+        )
+    }
+
+    #[test]
+    fn hs_boot_files_are_haskell() {
+        assert_eq!(
+            call(&[
+                "tree-grepper",
+                "-q",
+                "haskell",
+                "(signature name: (variable)@name)",
+                "--no-gitignore",
+                "tests/fixtures",
+            ]),
+            "tests/fixtures/Types.hs-boot:5:1:name:defaultConfig\n"
+        )
+    }
+
+    #[test]
+    fn cython_files_are_python() {
+        // `cdef` isn't Python, so those lines parse as errors, but plain
+        // `def` functions around them are still found.
+        assert_eq!(
+            call(&[
+                "tree-grepper",
+                "-q",
+                "python",
+                "(function_definition name: (identifier)@name)",
+                "--sort",
+                "--no-gitignore",
+                "tests/fixtures/cython",
+            ]),
+            "tests/fixtures/cython/primes.pyx:1:5:name:primes\ntests/fixtures/cython/primes.pyx:19:5:name:count\n"
+        )
+    }
+
+    #[test]
+    fn auto_runs_the_query_wherever_it_compiles() {
+        // Rust calls these `function_item`, so greet.rs gets skipped
+        // instead of failing the whole search.
+        assert_eq!(
+            call(&[
+                "tree-grepper",
+                "-q",
+                "auto",
+                "(function_declaration name: (identifier)@name)",
+                "--sort",
+                "tests/auto",
+            ]),
+            "tests/auto/greet.js:1:10:name:greet\ntests/auto/greet.ts:1:10:name:greet\n"
+        )
+    }
+
+    #[test]
+    fn auto_needs_the_query_to_compile_somewhere() {
+        let mut bytes = Vec::new();
+        let err = try_main(
+            vec![
+                "tree-grepper".to_string(),
+                "-q".to_string(),
+                "auto".to_string(),
+                "(no_such_node)".to_string(),
+                "tests/auto".to_string(),
+            ],
+            Box::new(&mut bytes),
+        )
+        .unwrap_err();
+
+        assert!(format!("{:#}", err).ends_with(
+            "the query didn't compile in any language we know about, so `auto` has nothing to search"
+        ));
+    }
+
+    #[test]
+    fn spans_output() {
         insta::assert_snapshot!(call(&[
             "tree-grepper",
             "-q",
             "javascript",
             "(identifier)",
-            "-f",
-            "json-lines",
+            "--format=spans",
             "--sort",
-            "vendor/tree-sitter-javascript/examples"
+            "--no-gitignore",
+            "vendor/tree-sitter-typescript/bindings/node/tsx.js",
         ]))
     }
 
     #[test]
-    fn pretty_json_output() {
+    fn ctags_output() {
         insta::assert_snapshot!(call(&[
             "tree-grepper",
             "-q",
-            "elm",
-            "(import_clause)",
-            "--format=pretty-json",
-            "--sort",
+            "rust",
+            "(function_item name: (identifier)@name)",
+            "--format=ctags",
             "--no-gitignore",
-            "vendor/tree-sitter-elm/examples",
+            "vendor/tree-sitter-rust/bindings/rust/build.rs",
+        ]))
+    }
+
+    #[test]
+    fn annotated_tree_output() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "javascript",
+            "(call_expression)",
+            "--format=annotated-tree",
+            "--no-gitignore",
+            "vendor/tree-sitter-typescript/bindings/node/tsx.js",
         ]))
     }
 
     // All languages should have a test that just spits out their entire node
     // tree. We use this to know about changes in the vendored parsers!
 
+    #[test]
+    fn all_apex() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "apex",
+            "(_)",
+            "--format=pretty-json",
+            "--sort",
+            "--no-gitignore",
+            "tests/fixtures/apex",
+        ]))
+    }
+
+    #[test]
+    fn apex_method_declarations() {
+        assert_eq!(
+            call(&[
+                "tree-grepper",
+                "-q",
+                "apex",
+                "(method_declaration name: (identifier)@name)",
+                "--no-gitignore",
+                "tests/fixtures/apex",
+            ]),
+            "tests/fixtures/apex/AccountService.cls:7:24:name:rate\n\
+             tests/fixtures/apex/AccountService.cls:14:27:name:ratingFor\n\
+             tests/fixtures/apex/AccountService.cls:23:33:name:largest\n"
+        );
+    }
+
+    #[test]
+    fn all_bicep() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "bicep",
+            "(_)",
+            "--format=pretty-json",
+            "--sort",
+            "--no-gitignore",
+            "tests/fixtures/bicep",
+        ]))
+    }
+
+    #[test]
+    fn bicep_resource_declarations() {
+        assert_eq!(
+            call(&[
+                "tree-grepper",
+                "-q",
+                "bicep",
+                "(resource_declaration (identifier)@name)",
+                "--sort",
+                "--no-gitignore",
+                "tests/fixtures/bicep",
+            ]),
+            "tests/fixtures/bicep/main.bicep:11:10:name:plan\n\
+             tests/fixtures/bicep/storage.bicep:15:10:name:account\n\
+             tests/fixtures/bicep/storage.bicep:24:10:name:uploads\n"
+        );
+    }
+
+    #[test]
+    fn all_capnp() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "capnp",
+            "(_)",
+            "--format=pretty-json",
+            "--sort",
+            "--no-gitignore",
+            "tests/fixtures/capnp",
+        ]))
+    }
+
+    #[test]
+    fn capnp_struct_definitions() {
+        assert_eq!(
+            call(&[
+                "tree-grepper",
+                "-q",
+                "capnp",
+                "(struct (type_identifier)@name)",
+                "--no-gitignore",
+                "tests/fixtures/capnp",
+            ]),
+            "tests/fixtures/capnp/addressbook.capnp:8:8:name:Person\n\
+             tests/fixtures/capnp/addressbook.capnp:14:10:name:PhoneNumber\n\
+             tests/fixtures/capnp/addressbook.capnp:33:8:name:AddressBook\n"
+        );
+    }
+
     #[test]
     fn all_cpp() {
         insta::assert_snapshot!(call(&[
@@ -273,6 +3872,20 @@ mod tests {
         ]))
     }
 
+    #[test]
+    fn all_go() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "go",
+            "(_)",
+            "--format=pretty-json",
+            "--sort",
+            "--no-gitignore",
+            "tests/fixtures/go",
+        ]))
+    }
+
     #[test]
     fn all_haskell() {
         insta::assert_snapshot!(call(&[
@@ -306,6 +3919,88 @@ mod tests {
         ]))
     }
 
+    #[test]
+    fn all_mermaid() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "mermaid",
+            "(_)",
+            "--format=pretty-json",
+            "--sort",
+            "--no-gitignore",
+            "tests/fixtures/mermaid",
+        ]))
+    }
+
+    #[test]
+    fn mermaid_nodes_and_edges() {
+        let out = call(&[
+            "tree-grepper",
+            "-q",
+            "mermaid",
+            "(flow_edge_statement . (flow_node)@from (flow_edge)@edge)",
+            "--no-gitignore",
+            "tests/fixtures/mermaid",
+        ]);
+
+        let captured: Vec<&str> = out
+            .lines()
+            .map(|line| line.splitn(4, ':').nth(3).unwrap())
+            .collect();
+        assert_eq!(
+            captured,
+            vec![
+                "from:cart[Cart]",
+                "edge:-->",
+                "from:checkout",
+                "edge:-->|yes|",
+                "from:checkout",
+                "edge:-->|no|",
+                "from:login",
+                "edge:-->",
+                "from:payment",
+                "edge:-->",
+            ]
+        );
+    }
+
+    #[test]
+    fn all_pascal() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "pascal",
+            "(_)",
+            "--format=pretty-json",
+            "--sort",
+            "--no-gitignore",
+            "tests/fixtures/pascal",
+        ]))
+    }
+
+    #[test]
+    fn pascal_procedures_and_functions() {
+        let out = call(&[
+            "tree-grepper",
+            "-q",
+            "pascal",
+            "(defProc (declProc name: (_)@name))",
+            "--sort",
+            "--no-gitignore",
+            "tests/fixtures/pascal",
+        ]);
+
+        let names: Vec<&str> = out
+            .lines()
+            .map(|line| line.rsplit(':').next().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["SayHello", "TSquare.Create", "TSquare.Area", "TotalArea"]
+        );
+    }
+
     #[test]
     fn all_php() {
         insta::assert_snapshot!(call(&[
@@ -320,6 +4015,20 @@ mod tests {
         ]))
     }
 
+    #[test]
+    fn all_python() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "python",
+            "(_)",
+            "--format=pretty-json",
+            "--sort",
+            "--no-gitignore",
+            "tests/fixtures/python",
+        ]))
+    }
+
     #[test]
     fn all_ruby() {
         insta::assert_snapshot!(call(&[
@@ -368,6 +4077,41 @@ mod tests {
         ]))
     }
 
+    #[test]
+    fn all_zig() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "zig",
+            "(_)",
+            "--format=pretty-json",
+            "--sort",
+            "--no-gitignore",
+            "tests/fixtures/zig",
+        ]))
+    }
+
+    #[test]
+    fn zon_files_are_searched_as_zig() {
+        let out = call(&[
+            "tree-grepper",
+            "-q",
+            "zig",
+            "(assignment_expression (field_expression (identifier)@field) (string))",
+            "--no-gitignore",
+            "tests/fixtures/zig/build.zig.zon",
+        ]);
+
+        let fields: Vec<&str> = out
+            .lines()
+            .map(|line| line.rsplit(':').next().unwrap())
+            .collect();
+        assert_eq!(
+            fields,
+            vec!["version", "minimum_zig_version", "url", "hash"]
+        );
+    }
+
     #[test]
     fn all_elixir() {
         insta::assert_snapshot!(call(&[
@@ -0,0 +1,62 @@
+use crate::extractor::ExtractedFile;
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Read results written with `--format json`, `pretty-json`, or `json-lines`
+/// (including `--jsonl-with-summary`) and combine them into one set, with one
+/// entry per file and no duplicate matches, sorted by path.
+pub fn read_results(paths: &[PathBuf]) -> Result<Vec<ExtractedFile<'static>>> {
+    let mut by_path: BTreeMap<Option<PathBuf>, ExtractedFile<'static>> = BTreeMap::new();
+
+    for path in paths {
+        for extracted_file in read_file(path)? {
+            let key = extracted_file.path().map(Path::to_path_buf);
+
+            match by_path.get_mut(&key) {
+                Some(existing) => existing.merge(extracted_file),
+                None => {
+                    by_path.insert(key, extracted_file);
+                }
+            }
+        }
+    }
+
+    Ok(by_path.into_values().collect())
+}
+
+fn read_file(path: &Path) -> Result<Vec<ExtractedFile<'static>>> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("could not read {}", path.display()))?;
+
+    let mut out = Vec::new();
+
+    // JSON output is a single array, while JSON lines output is one object
+    // per line. Reading a stream of values handles both.
+    for value in serde_json::Deserializer::from_str(&contents).into_iter::<Value>() {
+        let value = value.with_context(|| format!("could not parse {}", path.display()))?;
+
+        match value {
+            Value::Array(files) => {
+                for file in files {
+                    out.push(serde_json::from_value(file).with_context(|| {
+                        format!("could not read results from {}", path.display())
+                    })?);
+                }
+            }
+
+            // --jsonl-with-summary ends with a line of totals, which we'll
+            // recalculate anyway if we need them.
+            Value::Object(ref fields) if fields.get("type") == Some(&Value::from("summary")) => (),
+
+            _ => out.push(
+                serde_json::from_value(value)
+                    .with_context(|| format!("could not read results from {}", path.display()))?,
+            ),
+        }
+    }
+
+    Ok(out)
+}
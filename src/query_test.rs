@@ -0,0 +1,96 @@
+use crate::extractor_chooser::ExtractorChooser;
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tree_sitter::Parser;
+
+/// Run the checks in a `--query-test` spec, writing a line for each fixture.
+///
+/// A spec is a JSON object mapping fixture paths (relative to the spec) to
+/// the number of times we expect each capture to match in them, like
+/// `{"fixtures/basic.rs": {"name": 2, "func": 2}}`. Captures the spec doesn't
+/// mention aren't checked, so `0` is how to say a capture shouldn't match.
+pub fn run(spec_path: &Path, chooser: &ExtractorChooser, mut out: impl Write) -> Result<()> {
+    let spec: BTreeMap<PathBuf, BTreeMap<String, usize>> = serde_json::from_str(
+        &fs::read_to_string(spec_path)
+            .with_context(|| format!("could not read {}", spec_path.display()))?,
+    )
+    .with_context(|| format!("could not parse {}", spec_path.display()))?;
+
+    let base = spec_path.parent().unwrap_or_else(|| Path::new("."));
+    let mut parser = Parser::new();
+    let mut failed = 0;
+
+    for (fixture, expected) in &spec {
+        let path = base.join(fixture);
+
+        let problems = match chooser.extractor_for_name(&path) {
+            Some(extractor) => {
+                let extracted = extractor
+                    .extract_from_file(&path, &mut parser)
+                    .with_context(|| {
+                        format!("could not extract matches from {}", path.display())
+                    })?;
+
+                let mut actual: BTreeMap<&str, usize> = BTreeMap::new();
+                if let Some(extracted_file) = &extracted {
+                    for name in extracted_file.capture_names() {
+                        *actual.entry(name).or_default() += 1;
+                    }
+                }
+
+                expected
+                    .iter()
+                    .filter_map(|(name, count)| {
+                        let found = actual.get(name.as_str()).copied().unwrap_or(0);
+                        if found == *count {
+                            None
+                        } else {
+                            Some(format!("@{} expected {}, got {}", name, count, found))
+                        }
+                    })
+                    .collect()
+            }
+            None => vec!["there's no query for this file's language".to_string()],
+        };
+
+        if problems.is_empty() {
+            writeln!(out, "ok {}", fixture.display()).context("could not write result")?;
+        } else {
+            failed += 1;
+            writeln!(out, "FAILED {}: {}", fixture.display(), problems.join(", "))
+                .context("could not write result")?;
+        }
+    }
+
+    writeln!(out, "{} passed, {} failed", spec.len() - failed, failed)
+        .context("could not write summary")?;
+
+    if failed > 0 {
+        return Err(Failed {
+            failed,
+            total: spec.len(),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Some fixtures didn't match the way the spec says they should.
+#[derive(Debug)]
+pub struct Failed {
+    pub failed: usize,
+    pub total: usize,
+}
+
+impl fmt::Display for Failed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} of {} query tests failed", self.failed, self.total)
+    }
+}
+
+impl std::error::Error for Failed {}
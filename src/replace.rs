@@ -0,0 +1,935 @@
+use crate::diff;
+use crate::extractor::{line_indent, Extractor};
+use anyhow::{bail, Context, Result};
+use std::cmp::Reverse;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+use tree_sitter::{Node, Parser, QueryCapture, QueryCursor, Tree};
+
+/// Rewrites the nodes a query captures with a template. Templates can refer
+/// to the text of any capture in the same match as `{{name}}`.
+///
+/// When a query has several patterns, a template can also refer to captures
+/// from the other patterns, as long as one of their captures is on the exact
+/// same range of source as the node being replaced. We call these sibling
+/// matches. Captures in the replaced node's own match always win, and after
+/// that we take the first sibling match (in source order) with the capture.
+///
+/// Templates can also use `${counter}` for a number that goes up by one for
+/// every replaced node, starting at 0. Nodes are numbered in source order, and
+/// files in path order unless the counter starts over in every file.
+#[derive(Debug)]
+pub struct Replacement {
+    capture: String,
+    template: String,
+    auto_indent: bool,
+    delete_trailing: bool,
+    include_trailing_comment: bool,
+    escape_strings: bool,
+    counter_per_file: bool,
+}
+
+/// Where `${counter}` goes in a template.
+const COUNTER: &str = "${counter}";
+
+#[derive(Debug, PartialEq, Eq)]
+struct Edit {
+    start: usize,
+    end: usize,
+    text: String,
+}
+
+impl Replacement {
+    pub fn new(capture: &str, template: String) -> Replacement {
+        Replacement {
+            capture: capture.trim_start_matches('@').to_string(),
+            template,
+            auto_indent: false,
+            delete_trailing: false,
+            include_trailing_comment: false,
+            escape_strings: false,
+            counter_per_file: false,
+        }
+    }
+
+    /// A replacement that removes the captured nodes.
+    pub fn delete(capture: &str) -> Replacement {
+        Replacement::new(capture, String::new())
+    }
+
+    /// When deleting, also remove a `;` right after the node and the rest of
+    /// the line if it's blank. If that leaves the line empty, the whole line
+    /// goes.
+    pub fn set_delete_trailing(&mut self, delete_trailing: bool) {
+        self.delete_trailing = delete_trailing
+    }
+
+    /// Replace (or delete) a comment after the node on the same line along
+    /// with the node, instead of leaving it where it is.
+    pub fn set_include_trailing_comment(&mut self, include_trailing_comment: bool) {
+        self.include_trailing_comment = include_trailing_comment
+    }
+
+    /// When the replaced node is a string literal (or inside one), escape the
+    /// text of the captures we insert so they can't end the string early.
+    /// The rest of the template is left as it is.
+    pub fn set_escape_strings(&mut self, escape_strings: bool) {
+        self.escape_strings = escape_strings
+    }
+
+    /// Start `${counter}` over at 0 in every file.
+    pub fn set_counter_per_file(&mut self, counter_per_file: bool) {
+        self.counter_per_file = counter_per_file
+    }
+
+    /// Whether `${counter}` carries on from one file to the next, in which
+    /// case files have to be rewritten one at a time, in path order, for the
+    /// numbers to come out the same every time.
+    pub fn counts_across_files(&self) -> bool {
+        !self.counter_per_file && self.template.contains(COUNTER)
+    }
+
+    /// Indent every line of the replacement after the first to match the
+    /// line the replaced node starts on.
+    pub fn set_auto_indent(&mut self, auto_indent: bool) {
+        self.auto_indent = auto_indent
+    }
+
+    /// Rewrite the file in place, returning whether anything changed.
+    pub fn rewrite_file(
+        &self,
+        path: &Path,
+        extractor: &Extractor,
+        parser: &mut Parser,
+        counter: &mut usize,
+    ) -> Result<bool> {
+        if path.extension() == Some(OsStr::new("gz")) {
+            bail!("can't replace matches in compressed files")
+        }
+
+        let source = fs::read(path).context("could not read file")?;
+
+        match self.rewrite(&source, extractor, parser, counter)? {
+            Some(rewritten) => {
+                fs::write(path, rewritten).context("could not write file")?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Get a unified diff of what rewriting the file would change, without
+    /// changing it, or `None` if there was nothing to replace.
+    pub fn diff_file(
+        &self,
+        path: &Path,
+        extractor: &Extractor,
+        parser: &mut Parser,
+        counter: &mut usize,
+    ) -> Result<Option<String>> {
+        if path.extension() == Some(OsStr::new("gz")) {
+            bail!("can't replace matches in compressed files")
+        }
+
+        let source = fs::read(path).context("could not read file")?;
+
+        Ok(self
+            .rewrite(&source, extractor, parser, counter)?
+            .map(|rewritten| {
+                diff::unified(
+                    &path.to_string_lossy(),
+                    &String::from_utf8_lossy(&source),
+                    &String::from_utf8_lossy(&rewritten),
+                )
+            }))
+    }
+
+    /// Get a patch of what rewriting the file would change that `git apply`
+    /// can apply, without changing it, or `None` if there was nothing to
+    /// replace.
+    pub fn patch_file(
+        &self,
+        path: &Path,
+        extractor: &Extractor,
+        parser: &mut Parser,
+        counter: &mut usize,
+    ) -> Result<Option<String>> {
+        if path.extension() == Some(OsStr::new("gz")) {
+            bail!("can't replace matches in compressed files")
+        }
+
+        let source = fs::read(path).context("could not read file")?;
+        let mode = git_mode(path).context("could not read file permissions")?;
+        // walking `.` gives us paths like `./src/main.rs`, but git wants
+        // them without the `./`.
+        let path = path.strip_prefix(".").unwrap_or(path);
+
+        Ok(self
+            .rewrite(&source, extractor, parser, counter)?
+            .map(|rewritten| diff::git_patch(&path.to_string_lossy(), &source, &rewritten, mode)))
+    }
+
+    /// Get the rewritten contents of the file without writing them, or `None`
+    /// if there was nothing to replace. This fails if the rewritten source has
+    /// parse errors that the original didn't.
+    pub fn checked_rewrite_file(
+        &self,
+        path: &Path,
+        extractor: &Extractor,
+        parser: &mut Parser,
+        counter: &mut usize,
+    ) -> Result<Option<Vec<u8>>> {
+        if path.extension() == Some(OsStr::new("gz")) {
+            bail!("can't replace matches in compressed files")
+        }
+
+        let source = fs::read(path).context("could not read file")?;
+
+        let rewritten = match self.rewrite(&source, extractor, parser, counter)? {
+            Some(rewritten) => rewritten,
+            None => return Ok(None),
+        };
+
+        let had_errors = extractor.parse(&source, parser)?.root_node().has_error();
+        if !had_errors && extractor.parse(&rewritten, parser)?.root_node().has_error() {
+            bail!("the replacement would introduce a parse error")
+        }
+
+        Ok(Some(rewritten))
+    }
+
+    /// Get the rewritten source, or `None` if there was nothing to replace.
+    /// `counter` is the first number for `${counter}`, and we move it past
+    /// the numbers we use.
+    pub fn rewrite(
+        &self,
+        source: &[u8],
+        extractor: &Extractor,
+        parser: &mut Parser,
+        counter: &mut usize,
+    ) -> Result<Option<Vec<u8>>> {
+        let edits = self.edits(source, extractor, parser, *counter)?;
+        if edits.is_empty() {
+            return Ok(None);
+        }
+
+        *counter += edits.len();
+
+        let mut out = source.to_vec();
+
+        // we go back-to-front so that applying an edit never moves the bytes
+        // that later edits refer to.
+        for edit in edits.iter().rev() {
+            out.splice(edit.start..edit.end, edit.text.bytes());
+        }
+
+        Ok(Some(out))
+    }
+
+    fn edits(
+        &self,
+        source: &[u8],
+        extractor: &Extractor,
+        parser: &mut Parser,
+        first_counter: usize,
+    ) -> Result<Vec<Edit>> {
+        let tree = extractor.parse(source, parser)?;
+        let query = extractor.query();
+
+        let target = match query
+            .capture_names()
+            .iter()
+            .position(|name| name == &self.capture)
+        {
+            Some(index) => index as u32,
+            None => bail!(
+                "the query for {} doesn't have a capture named @{} to replace",
+                extractor.language(),
+                self.capture
+            ),
+        };
+
+        let mut cursor = QueryCursor::new();
+        let matches: Vec<Vec<QueryCapture>> = cursor
+            .matches(query, tree.root_node(), source)
+            .map(|query_match| query_match.captures.to_vec())
+            .collect();
+
+        let mut edits = Vec::new();
+
+        for (match_index, captures) in matches.iter().enumerate() {
+            for capture in captures {
+                if capture.index != target {
+                    continue;
+                }
+
+                let node = capture.node;
+                // we don't know what number each node gets until we know
+                // which ones we're replacing, so we fill that in below.
+                let text = self.text_for(&matches, match_index, node, extractor, source, 0)?;
+
+                let deleting_trailing = self.delete_trailing && text.is_empty();
+
+                let mut end = node.end_byte();
+                if self.include_trailing_comment {
+                    // we only step over a `;` if we're going to delete it
+                    // anyway.
+                    if let Some(comment_end) =
+                        trailing_comment_end(&tree, extractor, source, end, deleting_trailing)
+                    {
+                        end = comment_end;
+                    }
+                }
+
+                let (start, end) = if deleting_trailing {
+                    trailing_span(source, node.start_byte(), end)
+                } else {
+                    (node.start_byte(), end)
+                };
+
+                edits.push((Edit { start, end, text }, match_index, node))
+            }
+        }
+
+        // Queries can capture the same node several times (or capture nodes
+        // inside other captured nodes), but we can only replace any given
+        // span of bytes once. When that happens, the outermost node wins.
+        edits.sort_by_key(|(edit, _, _)| (edit.start, Reverse(edit.end)));
+
+        let mut out: Vec<Edit> = Vec::with_capacity(edits.len());
+        let mut counter = first_counter;
+        for (mut edit, match_index, node) in edits {
+            match out.last() {
+                Some(last) if edit.start < last.end || edit == *last => continue,
+                _ => {
+                    if self.template.contains(COUNTER) {
+                        edit.text =
+                            self.text_for(&matches, match_index, node, extractor, source, counter)?;
+                        counter += 1;
+                    }
+
+                    out.push(edit)
+                }
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// The text to replace `node` (captured in `matches[match_index]`) with.
+    fn text_for(
+        &self,
+        matches: &[Vec<QueryCapture>],
+        match_index: usize,
+        node: Node,
+        extractor: &Extractor,
+        source: &[u8],
+        counter: usize,
+    ) -> Result<String> {
+        let siblings = matches
+            .iter()
+            .enumerate()
+            .filter(|(other_index, other)| {
+                *other_index != match_index
+                    && other
+                        .iter()
+                        .any(|other| other.node.byte_range() == node.byte_range())
+            })
+            .map(|(_, other)| other.as_slice());
+
+        let string = if self.escape_strings {
+            enclosing_string(node, extractor, source)
+        } else {
+            None
+        };
+
+        let text = self.expand(
+            &std::iter::once(matches[match_index].as_slice())
+                .chain(siblings)
+                .collect::<Vec<_>>(),
+            extractor,
+            source,
+            string.as_ref(),
+            counter,
+        )?;
+
+        if self.auto_indent {
+            Ok(reindent(&text, &line_indent(source, node.start_byte())))
+        } else {
+            Ok(text)
+        }
+    }
+
+    /// Fill in the template. `matches` is the replaced node's own captures
+    /// followed by those of its sibling matches, in the order we should look
+    /// for each name. `string` is the string literal we're inserting into,
+    /// if any. `counter` is the number for `${counter}`.
+    fn expand(
+        &self,
+        matches: &[&[QueryCapture]],
+        extractor: &Extractor,
+        source: &[u8],
+        string: Option<&EnclosingString>,
+        counter: usize,
+    ) -> Result<String> {
+        let names = extractor.query().capture_names();
+        let mut out = String::with_capacity(self.template.len());
+        let mut rest = self.template.as_str();
+
+        while let Some(open) = rest.find("{{") {
+            let close = match rest[open..].find("}}") {
+                Some(close) => open + close,
+                None => break,
+            };
+
+            out.push_str(&rest[..open].replace(COUNTER, &counter.to_string()));
+
+            let name = rest[open + 2..close].trim();
+            let index = match names.iter().position(|candidate| candidate == name) {
+                Some(index) => index as u32,
+                None => bail!(
+                    "the replacement refers to {{{{{}}}}}, but the query for {} doesn't have a capture named @{}",
+                    name,
+                    extractor.language(),
+                    name
+                ),
+            };
+
+            // a capture might not be present in every match (for example if
+            // it's in an alternation.) In that case we substitute nothing.
+            if let Some(capture) = matches
+                .iter()
+                .flat_map(|captures| captures.iter())
+                .find(|capture| capture.index == index)
+            {
+                let text = capture
+                    .node
+                    .utf8_text(source)
+                    .context("could not extract text from capture")?;
+
+                match string {
+                    Some(EnclosingString::Quoted(quote)) => {
+                        out.push_str(&extractor.language().escape_string(text, *quote))
+                    }
+                    Some(EnclosingString::Raw(end)) => {
+                        if text.contains(end.as_str()) {
+                            bail!(
+                                "can't insert `{}` into a raw string, since it contains the string's closing `{}` and raw strings can't escape anything",
+                                text,
+                                end
+                            )
+                        }
+
+                        out.push_str(text)
+                    }
+                    None => out.push_str(text),
+                }
+            }
+
+            rest = &rest[close + 2..];
+        }
+
+        out.push_str(&rest.replace(COUNTER, &counter.to_string()));
+        Ok(out)
+    }
+}
+
+/// A string literal that we're inserting text into.
+#[derive(Debug)]
+enum EnclosingString {
+    /// A string delimited by this quote, where we can escape things.
+    Quoted(char),
+    /// A raw string ending with this delimiter. Nothing in it can be escaped,
+    /// so all we can do is refuse to insert the delimiter.
+    Raw(String),
+}
+
+/// If `node` is a string literal or inside one, find out how that string is
+/// delimited.
+fn enclosing_string(node: Node, extractor: &Extractor, source: &[u8]) -> Option<EnclosingString> {
+    let language = extractor.language();
+    let string_types = language.string_node_types();
+    let raw_string_types = language.raw_string_node_types();
+
+    let mut current = Some(node);
+    while let Some(candidate) = current {
+        if raw_string_types.contains(&candidate.kind()) {
+            if let Some(end) = language.raw_string_terminator(candidate.utf8_text(source).ok()?) {
+                return Some(EnclosingString::Raw(end));
+            }
+        }
+
+        if string_types.contains(&candidate.kind()) {
+            // some strings have a prefix before the quote, like `b"..."` in
+            // Rust or `u8"..."` in C++.
+            return candidate
+                .utf8_text(source)
+                .ok()?
+                .chars()
+                .find(|c| matches!(c, '"' | '\'' | '`'))
+                .map(EnclosingString::Quoted);
+        }
+
+        current = candidate.parent();
+    }
+
+    None
+}
+
+/// Find the end of a comment that starts on the same line as `end`, with only
+/// whitespace (and optionally a `;`) in between.
+fn trailing_comment_end(
+    tree: &Tree,
+    extractor: &Extractor,
+    source: &[u8],
+    end: usize,
+    skip_semicolon: bool,
+) -> Option<usize> {
+    let mut start = end;
+
+    if skip_semicolon && source.get(start) == Some(&b';') {
+        start += 1;
+    }
+
+    while matches!(source.get(start), Some(b' ') | Some(b'\t')) {
+        start += 1;
+    }
+
+    let comment_types = extractor.language().comment_node_types();
+    let mut node = tree
+        .root_node()
+        .descendant_for_byte_range(start, start + 1)?;
+
+    // the smallest node here might be part of the comment (like the `//`),
+    // so we look up the tree for the comment itself.
+    while node.start_byte() == start {
+        if comment_types.contains(&node.kind()) {
+            return Some(node.end_byte());
+        }
+
+        node = node.parent()?;
+    }
+
+    None
+}
+
+/// Widen a deleted span to cover a `;` right after it and the rest of the
+/// line, if that's only whitespace. If the span was the only thing on its
+/// line, we take the indentation before it too, so the line disappears.
+fn trailing_span(source: &[u8], start: usize, end: usize) -> (usize, usize) {
+    let mut new_end = end;
+
+    if source.get(new_end) == Some(&b';') {
+        new_end += 1;
+    }
+
+    while matches!(source.get(new_end), Some(b' ') | Some(b'\t') | Some(b'\r')) {
+        new_end += 1;
+    }
+
+    match source.get(new_end) {
+        Some(b'\n') => new_end += 1,
+        // something else is on this line, so we leave the whitespace alone
+        Some(_) => return (start, end + usize::from(source.get(end) == Some(&b';'))),
+        None => (),
+    }
+
+    let indent = line_indent(source, start);
+    let line_start = start - indent.len();
+    if line_start == 0 || source[line_start - 1] == b'\n' {
+        (line_start, new_end)
+    } else {
+        (start, new_end)
+    }
+}
+
+fn reindent(text: &str, indent: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            out.push('\n');
+
+            if !line.is_empty() {
+                out.push_str(indent);
+            }
+        }
+
+        out.push_str(line);
+    }
+
+    out
+}
+
+/// The mode git would record for the file: whether it's executable or not.
+#[cfg(unix)]
+fn git_mode(path: &Path) -> Result<&'static str> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if fs::metadata(path)?.permissions().mode() & 0o111 != 0 {
+        Ok("100755")
+    } else {
+        Ok("100644")
+    }
+}
+
+#[cfg(not(unix))]
+fn git_mode(_path: &Path) -> Result<&'static str> {
+    Ok("100644")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::language::Language;
+
+    fn rewrite(lang: Language, query: &str, replacement: &Replacement, source: &str) -> String {
+        let extractor = Extractor::new(lang, lang.parse_query(query).unwrap());
+
+        let rewritten = replacement
+            .rewrite(source.as_bytes(), &extractor, &mut Parser::new(), &mut 0)
+            // From Result<Option<Vec<u8>>>
+            .unwrap()
+            // From Option<Vec<u8>>
+            .unwrap();
+
+        String::from_utf8(rewritten).unwrap()
+    }
+
+    #[test]
+    fn test_replaces_with_captures() {
+        let replacement = Replacement::new("@name", String::from("new_{{ name }}"));
+
+        assert_eq!(
+            rewrite(
+                Language::Rust,
+                "(function_item name: (identifier)@name)",
+                &replacement,
+                "fn one() {}\nfn two() {}\n"
+            ),
+            "fn new_one() {}\nfn new_two() {}\n"
+        );
+    }
+
+    #[test]
+    fn test_counter() {
+        let replacement = Replacement::new("@name", String::from("item_${counter}"));
+
+        assert_eq!(
+            rewrite(
+                Language::Rust,
+                "(function_item name: (identifier)@name)",
+                &replacement,
+                "fn one() {}\nfn two() {}\nfn three() {}\n"
+            ),
+            "fn item_0() {}\nfn item_1() {}\nfn item_2() {}\n"
+        );
+    }
+
+    #[test]
+    fn test_counter_carries_on() {
+        let replacement = Replacement::new("@name", String::from("{{name}}_${counter}"));
+        let lang = Language::Rust;
+        let extractor = Extractor::new(
+            lang,
+            lang.parse_query("(function_item name: (identifier)@name)")
+                .unwrap(),
+        );
+
+        let mut counter = 5;
+        let rewritten = replacement
+            .rewrite(
+                b"fn one() {}\nfn two() {}\n",
+                &extractor,
+                &mut Parser::new(),
+                &mut counter,
+            )
+            // From Result<Option<Vec<u8>>>
+            .unwrap()
+            // From Option<Vec<u8>>
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(rewritten).unwrap(),
+            "fn one_5() {}\nfn two_6() {}\n"
+        );
+        assert_eq!(counter, 7);
+    }
+
+    #[test]
+    fn test_no_matches_means_no_rewrite() {
+        let replacement = Replacement::new("name", String::from("x"));
+        let lang = Language::Rust;
+        let extractor = Extractor::new(
+            lang,
+            lang.parse_query("(function_item name: (identifier)@name)")
+                .unwrap(),
+        );
+
+        assert_eq!(
+            replacement
+                .rewrite(b"struct Foo;", &extractor, &mut Parser::new(), &mut 0)
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_unknown_template_capture_is_an_error() {
+        let replacement = Replacement::new("name", String::from("{{nope}}"));
+        let lang = Language::Rust;
+        let extractor = Extractor::new(
+            lang,
+            lang.parse_query("(function_item name: (identifier)@name)")
+                .unwrap(),
+        );
+
+        assert!(replacement
+            .rewrite(b"fn foo() {}", &extractor, &mut Parser::new(), &mut 0)
+            .is_err());
+    }
+
+    #[test]
+    fn test_template_can_use_sibling_pattern_captures() {
+        let replacement = Replacement::new("name", String::from("{{name}}_{{type}}"));
+
+        assert_eq!(
+            rewrite(
+                Language::Rust,
+                "(function_item name: (identifier)@name)
+                 (function_item name: (identifier)@_same return_type: (_)@type)",
+                &replacement,
+                "fn one() -> u8 {}\nfn two() {}\n"
+            ),
+            "fn one_u8() -> u8 {}\nfn two_() {}\n"
+        );
+    }
+
+    #[test]
+    fn test_escapes_values_inserted_into_strings() {
+        let mut replacement = Replacement::new("target", String::from("\"{{source}}\""));
+        replacement.set_escape_strings(true);
+
+        let query = "(arguments (string_literal)@target (string_literal)@source)";
+        let rewritten = rewrite(
+            Language::Rust,
+            query,
+            &replacement,
+            "fn main() { f(\"x\", \"a\"); }\n",
+        );
+
+        assert_eq!(rewritten, "fn main() { f(\"\\\"a\\\"\", \"a\"); }\n");
+
+        let tree = Extractor::new(Language::Rust, Language::Rust.parse_query(query).unwrap())
+            .parse(rewritten.as_bytes(), &mut Parser::new())
+            .unwrap();
+        assert!(!tree.root_node().has_error());
+    }
+
+    #[test]
+    fn test_inserts_into_raw_strings_verbatim() {
+        let mut replacement = Replacement::new("target", String::from("r#\"{{source}}\"#"));
+        replacement.set_escape_strings(true);
+
+        let query = "(arguments (raw_string_literal)@target (string_literal)@source)";
+        assert_eq!(
+            rewrite(
+                Language::Rust,
+                query,
+                &replacement,
+                "fn main() { f(r#\"x\"#, \"a\\\\b\"); }\n",
+            ),
+            "fn main() { f(r#\"\"a\\\\b\"\"#, \"a\\\\b\"); }\n"
+        );
+    }
+
+    #[test]
+    fn test_refuses_to_end_raw_strings_early() {
+        let mut replacement = Replacement::new("target", String::from("r#\"{{source}}\"#"));
+        replacement.set_escape_strings(true);
+
+        let lang = Language::Rust;
+        let query = "(arguments (raw_string_literal)@target (raw_string_literal)@source)";
+        let extractor = Extractor::new(lang, lang.parse_query(query).unwrap());
+        let source = "fn main() { f(r#\"x\"#, r##\"\"#\"##); }\n";
+
+        assert!(replacement
+            .rewrite(source.as_bytes(), &extractor, &mut Parser::new(), &mut 0)
+            .is_err());
+    }
+
+    #[test]
+    fn test_tells_bicep_multiline_strings_from_quoted_ones() {
+        // both are `string` nodes, but only quoted strings escape anything.
+        let query = "(arguments (string)@target (string)@source)";
+
+        let mut quoted = Replacement::new("target", String::from("'{{source}}'"));
+        quoted.set_escape_strings(true);
+        assert_eq!(
+            rewrite(Language::Bicep, query, &quoted, "var a = f('x', 'a${b}')\n"),
+            "var a = f('\\'a\\${b}\\'', 'a${b}')\n"
+        );
+
+        let mut multiline = Replacement::new("target", String::from("'''{{source}}'''"));
+        multiline.set_escape_strings(true);
+        assert_eq!(
+            rewrite(
+                Language::Bicep,
+                query,
+                &multiline,
+                "var a = f('''x''', 'a${b}')\n"
+            ),
+            "var a = f(''''a${b}'''', 'a${b}')\n"
+        );
+    }
+
+    #[test]
+    fn test_does_not_escape_outside_strings() {
+        let mut replacement = Replacement::new("name", String::from("{{name}}"));
+        replacement.set_escape_strings(true);
+
+        assert_eq!(
+            rewrite(
+                Language::Rust,
+                "(function_item name: (identifier)@name)",
+                &replacement,
+                "fn one() {}\n"
+            ),
+            "fn one() {}\n"
+        );
+    }
+
+    #[test]
+    fn test_auto_indent_with_spaces() {
+        let mut replacement =
+            Replacement::new("call", String::from("if ready() {\n    {{call}}\n}"));
+        replacement.set_auto_indent(true);
+
+        assert_eq!(
+            rewrite(
+                Language::Rust,
+                "(block (call_expression)@call)",
+                &replacement,
+                "fn main() {\n    go();\n}\n"
+            ),
+            "fn main() {\n    if ready() {\n        go()\n    };\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_auto_indent_follows_tabs() {
+        let mut replacement = Replacement::new("call", String::from("{{call}}\n\nagain()"));
+        replacement.set_auto_indent(true);
+
+        assert_eq!(
+            rewrite(
+                Language::Rust,
+                "(block (call_expression)@call)",
+                &replacement,
+                "fn main() {\n\tgo();\n}\n"
+            ),
+            "fn main() {\n\tgo()\n\n\tagain();\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_delete_with_trailing() {
+        let mut replacement = Replacement::delete("call");
+        replacement.set_delete_trailing(true);
+
+        let query = "((macro_invocation macro: (identifier)@_name (#eq? @_name \"println\"))@call)";
+        let rewritten = rewrite(
+            Language::Rust,
+            query,
+            &replacement,
+            "fn main() {\n    println!(\"a\");\n    go();\n    println!(\"b\");\n}\n",
+        );
+
+        assert_eq!(rewritten, "fn main() {\n    go();\n}\n");
+
+        let tree = Extractor::new(Language::Rust, Language::Rust.parse_query(query).unwrap())
+            .parse(rewritten.as_bytes(), &mut Parser::new())
+            .unwrap();
+        assert!(!tree.root_node().has_error());
+    }
+
+    #[test]
+    fn test_delete_keeps_trailing_comment_by_default() {
+        let mut replacement = Replacement::delete("stmt");
+        replacement.set_delete_trailing(true);
+
+        assert_eq!(
+            rewrite(
+                Language::Rust,
+                "(block (call_expression function: (identifier)@_f)@stmt (#eq? @_f go))",
+                &replacement,
+                "fn main() {\n    go(); // bye\n    stay();\n}\n"
+            ),
+            "fn main() {\n     // bye\n    stay();\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_delete_including_trailing_comment() {
+        let mut replacement = Replacement::delete("stmt");
+        replacement.set_delete_trailing(true);
+        replacement.set_include_trailing_comment(true);
+
+        assert_eq!(
+            rewrite(
+                Language::Rust,
+                "(block (call_expression function: (identifier)@_f)@stmt (#eq? @_f go))",
+                &replacement,
+                "fn main() {\n    go(); // bye\n    stay();\n}\n"
+            ),
+            "fn main() {\n    stay();\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_include_trailing_comment_keeps_semicolons_when_replacing() {
+        let mut replacement = Replacement::new("call", String::from("{{call}}"));
+        replacement.set_include_trailing_comment(true);
+
+        assert_eq!(
+            rewrite(
+                Language::Rust,
+                "(block (call_expression)@call)",
+                &replacement,
+                "fn main() {\n    go(); // bye\n}\n"
+            ),
+            "fn main() {\n    go(); // bye\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_delete_without_trailing() {
+        let replacement = Replacement::delete("call");
+
+        assert_eq!(
+            rewrite(
+                Language::Rust,
+                "(block (call_expression)@call)",
+                &replacement,
+                "fn main() {\n    go();\n}\n"
+            ),
+            "fn main() {\n    ;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_without_auto_indent() {
+        let replacement = Replacement::new("call", String::from("{{call}}\nagain()"));
+
+        assert_eq!(
+            rewrite(
+                Language::Rust,
+                "(block (call_expression)@call)",
+                &replacement,
+                "fn main() {\n    go();\n}\n"
+            ),
+            "fn main() {\n    go()\nagain();\n}\n"
+        );
+    }
+}
@@ -0,0 +1,70 @@
+/// A small, fast pseudo-random number generator (SplitMix64.) We only use
+/// randomness to pick which matches to show, so we don't need anything
+/// cryptographically strong; we do need the same seed to give the same
+/// numbers everywhere, so `--seed` is reproducible.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..bound`. This has a tiny bias for bounds that aren't
+    /// powers of two, which is fine for picking samples.
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
+
+/// Choose `size` of the indexes `0..total` uniformly at random, using
+/// reservoir sampling so we only ever hold `size` of them at once. The
+/// result is sorted.
+pub fn reservoir(total: usize, size: usize, rng: &mut Rng) -> Vec<usize> {
+    let mut chosen: Vec<usize> = (0..total.min(size)).collect();
+
+    for index in size..total {
+        let slot = rng.below(index + 1);
+        if slot < size {
+            chosen[slot] = index;
+        }
+    }
+
+    chosen.sort_unstable();
+    chosen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reservoir_takes_everything_when_small() {
+        assert_eq!(reservoir(3, 5, &mut Rng::new(0)), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_reservoir_picks_distinct_indexes_in_range() {
+        let chosen = reservoir(1000, 10, &mut Rng::new(42));
+
+        assert_eq!(chosen.len(), 10);
+        assert!(chosen.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(chosen.iter().all(|index| *index < 1000));
+    }
+
+    #[test]
+    fn test_reservoir_is_reproducible() {
+        assert_eq!(
+            reservoir(1000, 10, &mut Rng::new(7)),
+            reservoir(1000, 10, &mut Rng::new(7))
+        );
+    }
+}
@@ -0,0 +1,48 @@
+use crate::extractor::ExtractedFile;
+use anyhow::{anyhow, Context, Result};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+#[cfg(unix)]
+type Stream = std::os::unix::net::UnixStream;
+
+// we can't connect on other platforms, but this keeps everything else the
+// same there.
+#[cfg(not(unix))]
+type Stream = std::io::Sink;
+
+/// Sends matches to a Unix domain socket as JSON lines, one file per line, as
+/// soon as we find them. Workers share the connection, so each line is written
+/// while holding the lock to keep lines from interleaving.
+pub struct Socket {
+    stream: Mutex<Stream>,
+}
+
+impl Socket {
+    #[cfg(unix)]
+    pub fn connect(path: &Path) -> Result<Socket> {
+        Ok(Socket {
+            stream: Mutex::new(
+                Stream::connect(path)
+                    .with_context(|| format!("could not connect to {}", path.display()))?,
+            ),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn connect(_: &Path) -> Result<Socket> {
+        anyhow::bail!("--socket only works on Unix")
+    }
+
+    pub fn send(&self, extracted_file: &ExtractedFile) -> Result<()> {
+        let mut line = serde_json::to_vec(extracted_file).context("could not serialize matches")?;
+        line.push(b'\n');
+
+        self.stream
+            .lock()
+            .map_err(|_| anyhow!("another thread panicked while writing to the socket"))?
+            .write_all(&line)
+            .context("could not write to the socket")
+    }
+}
@@ -0,0 +1,146 @@
+use anyhow::{bail, Context, Result};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Symlinks are stored in the index as blobs holding the link target, which
+/// isn't what anyone wants to search.
+const SYMLINK_MODE: &str = "120000";
+
+/// Submodules are commits, not blobs, so there's nothing to read.
+const SUBMODULE_MODE: &str = "160000";
+
+/// Get the staged contents of every file under `path`, as they'd be
+/// committed right now. The paths we return are relative to the root of the
+/// repository.
+pub fn staged_files(path: &Path) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+    let (dir, pathspec) = if path.is_dir() {
+        (path, Path::new("."))
+    } else {
+        match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => (parent, Path::new(name)),
+            _ => (Path::new("."), path),
+        }
+    };
+    // `Path::new("foo").parent()` is `Some("")`, which git won't run in.
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+
+    let listed = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["ls-files", "--stage", "-z", "--full-name", "--"])
+        .arg(pathspec)
+        .output()
+        .context("could not run git")?;
+
+    if !listed.status.success() {
+        bail!(
+            "could not list staged files in {}: {}",
+            dir.display(),
+            String::from_utf8_lossy(&listed.stderr).trim()
+        )
+    }
+
+    let mut entries = Vec::new();
+    for entry in listed.stdout.split(|byte| *byte == 0) {
+        if entry.is_empty() {
+            continue;
+        }
+
+        // entries look like `MODE OBJECT STAGE\tPATH`
+        let entry = String::from_utf8_lossy(entry);
+        let (info, path) = match entry.split_once('\t') {
+            Some(parts) => parts,
+            None => bail!("could not understand `{}` from git ls-files", entry),
+        };
+
+        match info.split(' ').collect::<Vec<&str>>().as_slice() {
+            [SYMLINK_MODE, _, _] | [SUBMODULE_MODE, _, _] => (),
+            // files with merge conflicts have several entries in later
+            // stages instead, and none of them are what'll be committed.
+            [_, object, "0"] => entries.push((PathBuf::from(path), object.to_string())),
+            [_, _, _] => (),
+            _ => bail!("could not understand `{}` from git ls-files", entry),
+        }
+    }
+
+    read_blobs(dir, entries)
+}
+
+/// Read the contents of a bunch of blobs with a single `git cat-file`.
+fn read_blobs(dir: &Path, entries: Vec<(PathBuf, String)>) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+    if entries.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["cat-file", "--batch"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("could not run git")?;
+
+    let objects: Vec<String> = entries.iter().map(|(_, object)| object.clone()).collect();
+    let mut stdin = child.stdin.take().context("could not write to git")?;
+    // git writes the blobs as we ask for them, so we have to ask from another
+    // thread or we'd both get stuck waiting on full pipes.
+    let writer = thread::spawn(move || -> std::io::Result<()> {
+        for object in objects {
+            writeln!(stdin, "{}", object)?;
+        }
+
+        Ok(())
+    });
+
+    let mut stdout = BufReader::new(child.stdout.take().context("could not read from git")?);
+    let mut out = Vec::with_capacity(entries.len());
+
+    for (path, object) in entries {
+        let mut header = String::new();
+        stdout
+            .read_line(&mut header)
+            .context("could not read from git")?;
+
+        // headers look like `OBJECT blob SIZE`
+        let size = match header
+            .trim_end()
+            .split(' ')
+            .collect::<Vec<&str>>()
+            .as_slice()
+        {
+            [_, "blob", size] => size
+                .parse::<usize>()
+                .with_context(|| format!("could not parse the size of {}", object))?,
+            _ => bail!(
+                "could not read {} from git: {}",
+                path.display(),
+                header.trim()
+            ),
+        };
+
+        // the contents are followed by a newline
+        let mut contents = vec![0; size + 1];
+        stdout
+            .read_exact(&mut contents)
+            .context("could not read from git")?;
+        contents.truncate(size);
+
+        out.push((path, contents));
+    }
+
+    match writer.join() {
+        Ok(result) => result.context("could not write to git")?,
+        Err(_) => bail!("the thread writing to git panicked"),
+    }
+
+    child.wait().context("could not wait for git to finish")?;
+
+    Ok(out)
+}
@@ -0,0 +1,40 @@
+use std::env;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// A scratch directory for tests. It's removed when dropped, so tests clean
+/// up after themselves even when an assertion fails partway through.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// `name` only needs to be unique among tests, since tests in different
+    /// processes get different directories anyway.
+    pub fn new(name: &str) -> TempDir {
+        let path = env::temp_dir().join(format!("tree-grepper-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&path).unwrap();
+
+        TempDir(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        // panicking while already unwinding would abort the whole test run
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1 @@
+cdef int largest(int[:] numbers)
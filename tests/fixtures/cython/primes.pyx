@@ -0,0 +1,20 @@
+def primes(int nb_primes):
+    cdef int n, i, len_p
+    cdef int[1000] p
+
+    len_p = 0
+    n = 2
+    while len_p < nb_primes:
+        for i in p[:len_p]:
+            if n % i == 0:
+                break
+        else:
+            p[len_p] = n
+            len_p += 1
+        n += 1
+
+    return [prime for prime in p[:len_p]]
+
+
+def count(limit):
+    return len(primes(limit))
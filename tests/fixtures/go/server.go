@@ -0,0 +1,24 @@
+// Package server answers greetings over HTTP.
+package server
+
+import (
+	"fmt"
+	"net/http"
+)
+
+type Greeter struct {
+	Greeting string
+}
+
+func (g *Greeter) ServeHTTP(w http.ResponseWriter, r *http.Request) {
+	name := r.URL.Query().Get("name")
+	if name == "" {
+		name = "world"
+	}
+
+	fmt.Fprintf(w, "%s, %s!\n", g.Greeting, name)
+}
+
+func New() *Greeter {
+	return &Greeter{Greeting: `Hello`}
+}
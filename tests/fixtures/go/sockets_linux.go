@@ -0,0 +1,10 @@
+//go:build linux
+// +build linux
+
+package server
+
+import "syscall"
+
+func reusePort(fd uintptr) error {
+	return syscall.SetsockoptInt(int(fd), syscall.SOL_SOCKET, 0xf, 1)
+}
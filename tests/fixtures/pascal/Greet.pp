@@ -0,0 +1,15 @@
+{ Free Pascal likes .pp for units }
+unit Greet;
+
+interface
+
+procedure SayHello(const Name: string);
+
+implementation
+
+procedure SayHello(const Name: string);
+begin
+  WriteLn('Hello, ', Name, '!');
+end;
+
+end.
@@ -0,0 +1,42 @@
+unit Shapes;
+
+interface
+
+type
+  TShape = class
+  public
+    function Area: Double; virtual; abstract;
+  end;
+
+  TSquare = class(TShape)
+  private
+    FSide: Double;
+  public
+    constructor Create(Side: Double);
+    function Area: Double; override;
+  end;
+
+function TotalArea(const Shapes: array of TShape): Double;
+
+implementation
+
+constructor TSquare.Create(Side: Double);
+begin
+  FSide := Side;
+end;
+
+function TSquare.Area: Double;
+begin
+  Result := FSide * FSide;
+end;
+
+function TotalArea(const Shapes: array of TShape): Double;
+var
+  Shape: TShape;
+begin
+  Result := 0;
+  for Shape in Shapes do
+    Result := Result + Shape.Area;
+end;
+
+end.
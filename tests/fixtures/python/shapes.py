@@ -0,0 +1,29 @@
+"""Shapes and the areas they cover."""
+
+import math
+from dataclasses import dataclass
+
+
+@dataclass
+class Circle:
+    radius: float
+
+    def area(self) -> float:
+        return math.pi * self.radius ** 2
+
+
+class Square:
+    def __init__(self, side):
+        self.side = side
+
+    def area(self):
+        # squares are easy
+        return self.side * self.side
+
+
+def total_area(shapes):
+    return sum(shape.area() for shape in shapes if shape is not None)
+
+
+if __name__ == "__main__":
+    print(f"{total_area([Circle(1.0), Square(2)]):.2f}")
@@ -0,0 +1,15 @@
+from typing import Iterable, Protocol
+
+class Shape(Protocol):
+    def area(self) -> float: ...
+
+class Circle:
+    radius: float
+    def area(self) -> float: ...
+
+class Square:
+    side: float
+    def __init__(self, side: float) -> None: ...
+    def area(self) -> float: ...
+
+def total_area(shapes: Iterable[Shape]) -> float: ...
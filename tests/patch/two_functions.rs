@@ -0,0 +1,3 @@
+fn one() {}
+
+fn two() {}